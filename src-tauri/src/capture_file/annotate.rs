@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::io;
+use crate::frame;
+use crate::settings::CaptureSettings;
+use crate::packet_factory::RotmgPacketFactory;
+use crate::packet_source::FrameFilter;
+use super::CaptureReader;
+use super::pcapng::PcapngWriter;
+
+
+/**
+ * What was written by export_annotated_pcapng
+ */
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct AnnotationSummary {
+    pub frames: usize,
+    pub packets: usize,
+    pub cipher_events: usize,
+}
+
+
+/**
+ * Decode a pcap or pcapng capture and write it back out as pcapng, with every frame that completed a rotmg packet
+ * commented with the packet types and every frame that changed the cipher alignment commented with the change.
+ * Frames are numbered the same way wireshark numbers them, so the comments can be cross-referenced with realm-stat.
+ *
+ * The input is read twice, once to decode it and once to write the annotated copy.
+ */
+pub fn export_annotated_pcapng(input: &Path, output: &Path, settings: &CaptureSettings) -> io::Result<AnnotationSummary> {
+    let mut annotations: HashMap<usize, Vec<String>> = HashMap::new();
+    let mut summary = AnnotationSummary { frames: 0, packets: 0, cipher_events: 0 };
+
+    //Decode the capture, collecting comments for each frame number
+    let mut factory = RotmgPacketFactory::new();
+    let mut frame_filter = FrameFilter::new(settings);
+    let mut reader = CaptureReader::open(input)?;
+    let mut last_frame = 0;
+    while let Some(f) = reader.next_frame()? {
+        last_frame = f.number;
+        if frame_filter.matches(&f)? == false {
+            continue
+        }
+        if let Ok((slice, meta)) = frame::slice_frame(f.linktype, f.timestamp, f.number, &f.data, settings) {
+            factory.insert_packet(slice, meta);
+        }
+        collect_annotations(&mut factory, f.number, &mut annotations, &mut summary);
+    }
+    factory.finalize();
+    collect_annotations(&mut factory, last_frame, &mut annotations, &mut summary);
+
+    //Copy the capture with the comments added
+    let mut writer = PcapngWriter::create(output)?;
+    let mut interfaces: HashMap<(usize, u32), u32> = HashMap::new();
+    let mut reader = CaptureReader::open(input)?;
+    while let Some(mut f) = reader.next_frame()? {
+        summary.frames = f.number;
+        let interface = match interfaces.get(&(f.interface, f.linktype)) {
+            Some(id) => *id,
+            None => {
+                let id = writer.add_interface(f.linktype)?;
+                interfaces.insert((f.interface, f.linktype), id);
+                id
+            }
+        };
+        if let Some(comments) = annotations.remove(&f.number) {
+            f.comments.extend(comments);
+        }
+        writer.write_frame(interface, f.timestamp, f.original_len, &f.data, &f.comments, 0)?;
+    }
+    writer.flush()?;
+    Ok(summary)
+}
+
+
+fn collect_annotations(factory: &mut RotmgPacketFactory, frame_number: usize, annotations: &mut HashMap<usize, Vec<String>>, summary: &mut AnnotationSummary) {
+    while let Some(e) = factory.get_event() {
+        annotations.entry(frame_number).or_default().push(format!("realm-stat: {}", e.event_name()));
+        summary.cipher_events += 1;
+    }
+    while let Some(p) = factory.get_packet() {
+        annotations.entry(p.frame).or_default().push(format!("realm-stat: #{} {} ({}) {:?}", p.index, p.packet.type_name(), p.packet.type_num(), p.alignment));
+        summary.packets += 1;
+    }
+}
@@ -0,0 +1,146 @@
+/*
+Readers and writers for capture files, independent of libpcap
+*/
+pub mod pcap;
+pub mod pcapng;
+pub mod annotate;
+
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+use self::pcap::PcapReader;
+use self::pcapng::PcapngReader;
+
+
+/// Path used to read a capture stream from stdin
+pub const STDIN_PATH: &str = "-";
+
+
+/**
+ * A single frame read from a capture file
+ */
+#[derive(Debug, Clone)]
+pub struct CaptureFrame {
+    /// Number of the frame in its capture, counting from 1 like wireshark does
+    pub number: usize,
+    /// Interface the frame was captured on, always 0 for classic pcap files
+    pub interface: usize,
+    pub linktype: u32,
+    /// Capture time in microseconds since the unix epoch
+    pub timestamp: i64,
+    /// Length of the frame on the wire, may be longer than data if the capture was truncated
+    pub original_len: u32,
+    pub data: Vec<u8>,
+    /// Comments attached to the frame, only present in pcapng files
+    pub comments: Vec<String>,
+}
+
+
+/**
+ * Reads frames from either a classic pcap or a pcapng capture, detected from the first bytes of the stream
+ */
+pub enum CaptureReader<R: Read> {
+    Pcap(PcapReader<R>),
+    Pcapng(PcapngReader<R>),
+}
+impl CaptureReader<BufReader<File>> {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+impl CaptureReader<Box<dyn Read + Send>> {
+    /**
+     * Open a capture being written to stdin or a named pipe, like the output of `tcpdump -w -`.
+     * Frames are read as they arrive, so opening and reading block until the writer sends more or closes the stream.
+     */
+    pub fn open_stream(path: &str) -> io::Result<Self> {
+        let input: Box<dyn Read + Send> = match path {
+            STDIN_PATH => Box::new(BufReader::new(io::stdin())),
+            _ => Box::new(BufReader::new(File::open(path)?)),
+        };
+        Self::new(input)
+    }
+}
+impl<R: Read> CaptureReader<R> {
+    pub fn new(mut input: R) -> io::Result<Self> {
+        let mut magic = [0u8; 4];
+        input.read_exact(&mut magic)?;
+        if magic == pcapng::SHB_MAGIC {
+            Ok(CaptureReader::Pcapng(PcapngReader::with_magic(input)?))
+        } else {
+            Ok(CaptureReader::Pcap(PcapReader::with_magic(magic, input)?))
+        }
+    }
+
+    /**
+     * Read the next frame, returning None at the end of the capture
+     */
+    pub fn next_frame(&mut self) -> io::Result<Option<CaptureFrame>> {
+        match self {
+            CaptureReader::Pcap(r) => r.next_frame(),
+            CaptureReader::Pcapng(r) => r.next_frame(),
+        }
+    }
+}
+
+
+/**
+ * Fills buf from the reader.
+ * Returns false if the reader was already at the end, or if it ended partway through since a capture that was cut off is still readable up to that point.
+ */
+fn read_record(input: &mut impl Read, buf: &mut [u8]) -> io::Result<bool> {
+    match input.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use byteorder::{LittleEndian, WriteBytesExt};
+    use self::pcap::PcapWriter;
+    use self::pcapng::PcapngWriter;
+
+    #[test]
+    fn reads_back_written_frames() {
+        let mut bytes = vec![];
+        let mut pcap = PcapWriter::new(&mut bytes, 1).unwrap();
+        pcap.write_frame(1_500_000, 4, &[1, 2, 3, 4]).unwrap();
+        pcap.write_frame(1_600_000, 1, &[5]).unwrap();
+        drop(pcap);
+        let mut reader = CaptureReader::new(&bytes[..]).unwrap();
+        let frame = reader.next_frame().unwrap().unwrap();
+        assert_eq!((frame.number, frame.timestamp, frame.data), (1, 1_500_000, vec![1, 2, 3, 4]));
+        assert_eq!(reader.next_frame().unwrap().unwrap().number, 2);
+        assert!(reader.next_frame().unwrap().is_none());
+
+        let mut bytes = vec![];
+        let mut pcapng = PcapngWriter::new(&mut bytes).unwrap();
+        let interface = pcapng.add_interface(1).unwrap();
+        pcapng.write_frame(interface, 1_500_000, 3, &[5, 6, 7], &["note".to_string()], 0).unwrap();
+        drop(pcapng);
+        let mut reader = CaptureReader::new(&bytes[..]).unwrap();
+        let frame = reader.next_frame().unwrap().unwrap();
+        assert_eq!((frame.number, frame.timestamp, frame.data, frame.comments), (1, 1_500_000, vec![5, 6, 7], vec!["note".to_string()]));
+    }
+
+    #[test]
+    fn rejects_oversized_records() {
+        let mut bytes = vec![];
+        PcapWriter::new(&mut bytes, 1).unwrap().write_frame(0, 4, &[1, 2, 3, 4]).unwrap();
+        //captured length of the first record
+        bytes.splice(32..36, 0x1000_0000u32.to_le_bytes());
+        let error = CaptureReader::new(&bytes[..]).unwrap().next_frame().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let mut bytes = vec![];
+        PcapngWriter::new(&mut bytes).unwrap();
+        bytes.write_u32::<LittleEndian>(1).unwrap();
+        bytes.write_u32::<LittleEndian>(0xffff_fff0).unwrap();
+        let error = CaptureReader::new(&bytes[..]).unwrap().next_frame().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
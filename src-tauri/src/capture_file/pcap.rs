@@ -0,0 +1,143 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+use byteorder::{BigEndian, ByteOrder, LittleEndian, WriteBytesExt};
+use super::{CaptureFrame, read_record};
+
+
+const PCAP_MAGIC_MICROS: u32 = 0xa1b2c3d4;
+const PCAP_MAGIC_NANOS: u32 = 0xa1b23c4d;
+const PCAP_VERSION_MAJOR: u16 = 2;
+const PCAP_VERSION_MINOR: u16 = 4;
+const PCAP_GLOBAL_HEADER_LEN: u64 = 24;
+const PCAP_RECORD_HEADER_LEN: u64 = 16;
+pub const DEFAULT_SNAPLEN: u32 = 65535;
+/// Largest frame read from a capture, the largest snaplen wireshark uses. Anything bigger is a corrupt record length.
+const MAX_CAPTURED_LEN: u32 = 256 * 1024;
+
+
+/**
+ * Writes frames to a classic (libpcap) capture file with microsecond timestamps
+ */
+pub struct PcapWriter<W: Write> {
+    out: W,
+    snaplen: u32,
+    bytes_written: u64,
+}
+impl PcapWriter<BufWriter<File>> {
+    pub fn create(path: &Path, linktype: u32) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?), linktype)
+    }
+}
+impl<W: Write> PcapWriter<W> {
+    /**
+     * Writes the global header, every frame written after must use the same link type
+     */
+    pub fn new(mut out: W, linktype: u32) -> io::Result<Self> {
+        out.write_u32::<LittleEndian>(PCAP_MAGIC_MICROS)?;
+        out.write_u16::<LittleEndian>(PCAP_VERSION_MAJOR)?;
+        out.write_u16::<LittleEndian>(PCAP_VERSION_MINOR)?;
+        out.write_i32::<LittleEndian>(0)?; //timezone offset, always 0
+        out.write_u32::<LittleEndian>(0)?; //timestamp accuracy, always 0
+        out.write_u32::<LittleEndian>(DEFAULT_SNAPLEN)?;
+        out.write_u32::<LittleEndian>(linktype)?;
+        Ok(Self { out, snaplen: DEFAULT_SNAPLEN, bytes_written: PCAP_GLOBAL_HEADER_LEN })
+    }
+
+    /**
+     * Write a single frame.
+     * timestamp is in microseconds since the unix epoch, original_len is the length of the frame on the wire.
+     */
+    pub fn write_frame(&mut self, timestamp: i64, original_len: u32, data: &[u8]) -> io::Result<()> {
+        let data = &data[..data.len().min(self.snaplen as usize)];
+        self.out.write_u32::<LittleEndian>(timestamp.div_euclid(1_000_000) as u32)?;
+        self.out.write_u32::<LittleEndian>(timestamp.rem_euclid(1_000_000) as u32)?;
+        self.out.write_u32::<LittleEndian>(data.len() as u32)?;
+        self.out.write_u32::<LittleEndian>(original_len.max(data.len() as u32))?;
+        self.out.write_all(data)?;
+        self.bytes_written += PCAP_RECORD_HEADER_LEN + data.len() as u64;
+        Ok(())
+    }
+
+    /**
+     * Size of the file so far, including the global header
+     */
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+
+/**
+ * Reads frames from a classic (libpcap) capture, in either byte order and with micro or nanosecond timestamps
+ */
+pub struct PcapReader<R: Read> {
+    input: R,
+    big_endian: bool,
+    nanos: bool,
+    linktype: u32,
+    frames: usize,
+}
+impl<R: Read> PcapReader<R> {
+    /**
+     * Construct a reader from a stream that has already had its 4 byte magic number read
+     */
+    pub fn with_magic(magic: [u8; 4], mut input: R) -> io::Result<Self> {
+        let (big_endian, nanos) = match (LittleEndian::read_u32(&magic), BigEndian::read_u32(&magic)) {
+            (PCAP_MAGIC_MICROS, _) => (false, false),
+            (PCAP_MAGIC_NANOS, _) => (false, true),
+            (_, PCAP_MAGIC_MICROS) => (true, false),
+            (_, PCAP_MAGIC_NANOS) => (true, true),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "not a pcap or pcapng capture")),
+        };
+
+        let mut header = [0u8; 20];
+        input.read_exact(&mut header)?;
+        let mut reader = Self { input, big_endian, nanos, linktype: 0, frames: 0 };
+        //the upper bits of the link type field are used for fcs information
+        reader.linktype = reader.read_u32(&header[16..20]) & 0xffff;
+        Ok(reader)
+    }
+
+    pub fn linktype(&self) -> u32 {
+        self.linktype
+    }
+
+    pub fn next_frame(&mut self) -> io::Result<Option<CaptureFrame>> {
+        let mut header = [0u8; 16];
+        if read_record(&mut self.input, &mut header)? == false {
+            return Ok(None)
+        }
+        let seconds = self.read_u32(&header[0..4]) as i64;
+        let fraction = self.read_u32(&header[4..8]) as i64;
+        let captured_len = self.read_u32(&header[8..12]);
+        let original_len = self.read_u32(&header[12..16]);
+        if captured_len > MAX_CAPTURED_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("pcap record of {} bytes is larger than the {} byte limit", captured_len, MAX_CAPTURED_LEN)))
+        }
+
+        let mut data = vec![0u8; captured_len as usize];
+        if read_record(&mut self.input, &mut data)? == false {
+            return Ok(None)
+        }
+
+        self.frames += 1;
+        Ok(Some(CaptureFrame {
+            number: self.frames,
+            interface: 0,
+            linktype: self.linktype,
+            timestamp: seconds * 1_000_000 + if self.nanos { fraction / 1000 } else { fraction },
+            original_len,
+            data,
+            comments: vec![],
+        }))
+    }
+
+    fn read_u32(&self, bytes: &[u8]) -> u32 {
+        if self.big_endian { BigEndian::read_u32(bytes) } else { LittleEndian::read_u32(bytes) }
+    }
+}
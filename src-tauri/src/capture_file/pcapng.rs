@@ -0,0 +1,353 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+use byteorder::{BigEndian, ByteOrder, LittleEndian, WriteBytesExt};
+use super::{CaptureFrame, read_record};
+
+
+/// First four bytes of every pcapng file, the section header block type
+pub const SHB_MAGIC: [u8; 4] = [0x0a, 0x0d, 0x0d, 0x0a];
+
+const BLOCK_SECTION_HEADER: u32 = 0x0a0d0d0a;
+const BLOCK_INTERFACE_DESCRIPTION: u32 = 1;
+const BLOCK_PACKET: u32 = 2; //obsolete, but still readable
+const BLOCK_SIMPLE_PACKET: u32 = 3;
+const BLOCK_ENHANCED_PACKET: u32 = 6;
+const BYTE_ORDER_MAGIC: u32 = 0x1a2b3c4d;
+
+const OPT_END_OF_OPT: u16 = 0;
+const OPT_COMMENT: u16 = 1;
+const OPT_SHB_USER_APPL: u16 = 4;
+const OPT_IF_TSRESOL: u16 = 9;
+const OPT_EPB_FLAGS: u16 = 2;
+
+/// Largest block read from a capture, anything bigger is a corrupt block length
+const MAX_BLOCK_LEN: u32 = 8 * 1024 * 1024;
+
+/// epb_flags values for the packet direction
+pub const EPB_FLAG_INBOUND: u32 = 1;
+pub const EPB_FLAG_OUTBOUND: u32 = 2;
+
+
+struct Interface {
+    linktype: u32,
+    snaplen: u32,
+    /// if_tsresol option, 10^-n seconds if the high bit is clear, otherwise 2^-n seconds
+    tsresol: u8,
+}
+
+
+/**
+ * Reads frames from a pcapng capture.
+ * Handles multiple sections and interfaces with their own link types and timestamp resolutions, and keeps frame comments.
+ */
+pub struct PcapngReader<R: Read> {
+    input: R,
+    big_endian: bool,
+    interfaces: Vec<Interface>,
+    frames: usize,
+}
+impl<R: Read> PcapngReader<R> {
+    /**
+     * Construct a reader from a stream that has already had the section header block type read
+     */
+    pub fn with_magic(mut input: R) -> io::Result<Self> {
+        let big_endian = Self::read_section_header(&mut input)?;
+        Ok(Self { input, big_endian, interfaces: vec![], frames: 0 })
+    }
+
+    pub fn next_frame(&mut self) -> io::Result<Option<CaptureFrame>> {
+        loop {
+            let mut header = [0u8; 8];
+            if read_record(&mut self.input, &mut header)? == false {
+                return Ok(None)
+            }
+
+            let block_type = self.read_u32(&header[0..4]);
+            if block_type == BLOCK_SECTION_HEADER {
+                //A new section can switch byte order and always starts with no interfaces
+                self.big_endian = Self::read_section_header_after_type(&mut self.input, header[4..8].try_into().unwrap())?;
+                self.interfaces.clear();
+                continue
+            }
+
+            let block_len = self.read_u32(&header[4..8]);
+            if block_len < 12 || block_len % 4 != 0 || block_len > MAX_BLOCK_LEN {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid pcapng block length"))
+            }
+            let mut block = vec![0u8; block_len as usize - 8];
+            if read_record(&mut self.input, &mut block)? == false {
+                return Ok(None)
+            }
+            //the block length is repeated at the end of the block
+            let body = &block[..block.len() - 4];
+
+            let mut frame = match block_type {
+                BLOCK_INTERFACE_DESCRIPTION => {
+                    self.read_interface(body)?;
+                    continue
+                },
+                BLOCK_ENHANCED_PACKET => self.read_enhanced_packet(body)?,
+                BLOCK_SIMPLE_PACKET => self.read_simple_packet(body)?,
+                BLOCK_PACKET => self.read_packet(body)?,
+                _ => continue, //statistics, name resolution, etc.
+            };
+            self.frames += 1;
+            frame.number = self.frames;
+            return Ok(Some(frame))
+        }
+    }
+
+    /**
+     * Reads the rest of a section header block, returning whether the section is big endian
+     */
+    fn read_section_header(input: &mut R) -> io::Result<bool> {
+        let mut len = [0u8; 4];
+        input.read_exact(&mut len)?;
+        Self::read_section_header_after_type(input, len)
+    }
+
+    fn read_section_header_after_type(input: &mut R, len: [u8; 4]) -> io::Result<bool> {
+        let mut bom = [0u8; 4];
+        input.read_exact(&mut bom)?;
+        let big_endian = match (BigEndian::read_u32(&bom), LittleEndian::read_u32(&bom)) {
+            (BYTE_ORDER_MAGIC, _) => true,
+            (_, BYTE_ORDER_MAGIC) => false,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid pcapng byte order magic")),
+        };
+        let len = if big_endian { BigEndian::read_u32(&len) } else { LittleEndian::read_u32(&len) };
+        if len < 28 || len % 4 != 0 || len > MAX_BLOCK_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid pcapng section header length"))
+        }
+        //version, section length, options and the trailing block length aren't needed
+        let mut rest = vec![0u8; len as usize - 12];
+        input.read_exact(&mut rest)?;
+        Ok(big_endian)
+    }
+
+    fn read_interface(&mut self, body: &[u8]) -> io::Result<()> {
+        if body.len() < 8 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "truncated pcapng interface block"))
+        }
+        let mut interface = Interface {
+            linktype: self.read_u16(&body[0..2]) as u32,
+            snaplen: self.read_u32(&body[4..8]),
+            tsresol: 6,
+        };
+        for (code, value) in self.read_options(&body[8..]) {
+            if code == OPT_IF_TSRESOL && value.len() >= 1 {
+                interface.tsresol = value[0];
+            }
+        }
+        self.interfaces.push(interface);
+        Ok(())
+    }
+
+    fn read_enhanced_packet(&self, body: &[u8]) -> io::Result<CaptureFrame> {
+        if body.len() < 20 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "truncated pcapng packet block"))
+        }
+        let interface = self.read_u32(&body[0..4]) as usize;
+        let timestamp = (self.read_u32(&body[4..8]) as u64) << 32 | self.read_u32(&body[8..12]) as u64;
+        let captured_len = self.read_u32(&body[12..16]) as usize;
+        let original_len = self.read_u32(&body[16..20]);
+        self.frame(interface, timestamp, original_len, &body[20..], captured_len)
+    }
+
+    fn read_packet(&self, body: &[u8]) -> io::Result<CaptureFrame> {
+        if body.len() < 20 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "truncated pcapng packet block"))
+        }
+        let interface = self.read_u16(&body[0..2]) as usize;
+        let timestamp = (self.read_u32(&body[4..8]) as u64) << 32 | self.read_u32(&body[8..12]) as u64;
+        let captured_len = self.read_u32(&body[12..16]) as usize;
+        let original_len = self.read_u32(&body[16..20]);
+        self.frame(interface, timestamp, original_len, &body[20..], captured_len)
+    }
+
+    fn read_simple_packet(&self, body: &[u8]) -> io::Result<CaptureFrame> {
+        if body.len() < 4 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "truncated pcapng packet block"))
+        }
+        let original_len = self.read_u32(&body[0..4]);
+        let snaplen = self.interfaces.first().map(|i| i.snaplen).unwrap_or(0);
+        let mut captured_len = (original_len as usize).min(body.len() - 4);
+        if snaplen > 0 {
+            captured_len = captured_len.min(snaplen as usize);
+        }
+        //simple packet blocks have no timestamp
+        self.frame(0, 0, original_len, &body[4..], captured_len)
+    }
+
+    /**
+     * Build a frame from the packet data and options that follow a packet block header
+     */
+    fn frame(&self, interface: usize, timestamp: u64, original_len: u32, rest: &[u8], captured_len: usize) -> io::Result<CaptureFrame> {
+        let iface = match self.interfaces.get(interface) {
+            Some(i) => i,
+            None => return Err(io::Error::new(io::ErrorKind::InvalidData, "pcapng packet references an unknown interface")),
+        };
+        if captured_len > rest.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "pcapng packet data is longer than its block"))
+        }
+        let padded_len = (captured_len + 3) & !3;
+        let comments = self.read_options(&rest[padded_len.min(rest.len())..]).into_iter()
+            .filter(|(code, _)| *code == OPT_COMMENT)
+            .map(|(_, value)| String::from_utf8_lossy(value).to_string())
+            .collect();
+
+        Ok(CaptureFrame {
+            number: 0, //set once the frame is returned
+            interface,
+            linktype: iface.linktype,
+            timestamp: timestamp_to_micros(timestamp, iface.tsresol),
+            original_len,
+            data: rest[..captured_len].to_vec(),
+            comments,
+        })
+    }
+
+    fn read_options<'a>(&self, mut bytes: &'a [u8]) -> Vec<(u16, &'a [u8])> {
+        let mut options = vec![];
+        while bytes.len() >= 4 {
+            let code = self.read_u16(&bytes[0..2]);
+            let len = self.read_u16(&bytes[2..4]) as usize;
+            if code == OPT_END_OF_OPT || 4 + len > bytes.len() {
+                break
+            }
+            options.push((code, &bytes[4..4 + len]));
+            bytes = &bytes[(4 + ((len + 3) & !3)).min(bytes.len())..];
+        }
+        options
+    }
+
+    fn read_u16(&self, bytes: &[u8]) -> u16 {
+        if self.big_endian { BigEndian::read_u16(bytes) } else { LittleEndian::read_u16(bytes) }
+    }
+
+    fn read_u32(&self, bytes: &[u8]) -> u32 {
+        if self.big_endian { BigEndian::read_u32(bytes) } else { LittleEndian::read_u32(bytes) }
+    }
+}
+
+
+/**
+ * Convert a timestamp in units of the interface's if_tsresol to microseconds
+ */
+fn timestamp_to_micros(units: u64, tsresol: u8) -> i64 {
+    let units = units as u128;
+    let exponent = (tsresol & 0x7f) as u32;
+    let micros = if tsresol & 0x80 == 0 {
+        if exponent >= 6 {
+            units / 10u128.checked_pow(exponent - 6).unwrap_or(u128::MAX)
+        } else {
+            units * 10u128.pow(6 - exponent)
+        }
+    } else {
+        (units * 1_000_000) >> exponent.min(127)
+    };
+    micros as i64
+}
+
+
+/**
+ * Writes frames to a pcapng file with microsecond timestamps, little endian.
+ * Interfaces have to be added before frames can be written on them.
+ */
+pub struct PcapngWriter<W: Write> {
+    out: W,
+    interfaces: usize,
+}
+impl PcapngWriter<BufWriter<File>> {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?))
+    }
+}
+impl<W: Write> PcapngWriter<W> {
+    /**
+     * Writes the section header block
+     */
+    pub fn new(out: W) -> io::Result<Self> {
+        let mut writer = Self { out, interfaces: 0 };
+        let mut body = vec![];
+        body.write_u32::<LittleEndian>(BYTE_ORDER_MAGIC)?;
+        body.write_u16::<LittleEndian>(1)?; //major version
+        body.write_u16::<LittleEndian>(0)?; //minor version
+        body.write_i64::<LittleEndian>(-1)?; //section length is not specified
+        write_options(&mut body, &[(OPT_SHB_USER_APPL, "realm-stat".as_bytes())])?;
+        writer.write_block(BLOCK_SECTION_HEADER, &body)?;
+        Ok(writer)
+    }
+
+    /**
+     * Describe a new interface, returning its id for write_frame
+     */
+    pub fn add_interface(&mut self, linktype: u32) -> io::Result<u32> {
+        let mut body = vec![];
+        body.write_u16::<LittleEndian>(linktype as u16)?;
+        body.write_u16::<LittleEndian>(0)?; //reserved
+        body.write_u32::<LittleEndian>(0)?; //no snaplen limit
+        //no if_tsresol option means microseconds
+        self.write_block(BLOCK_INTERFACE_DESCRIPTION, &body)?;
+        self.interfaces += 1;
+        Ok(self.interfaces as u32 - 1)
+    }
+
+    /**
+     * Write a frame as an enhanced packet block.
+     * timestamp is in microseconds since the unix epoch, flags is the epb_flags option and is left out if 0.
+     */
+    pub fn write_frame(&mut self, interface: u32, timestamp: i64, original_len: u32, data: &[u8], comments: &[String], flags: u32) -> io::Result<()> {
+        if interface as usize >= self.interfaces {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "pcapng interface has not been added"))
+        }
+        let timestamp = timestamp.max(0) as u64;
+        let mut body = vec![];
+        body.write_u32::<LittleEndian>(interface)?;
+        body.write_u32::<LittleEndian>((timestamp >> 32) as u32)?;
+        body.write_u32::<LittleEndian>(timestamp as u32)?;
+        body.write_u32::<LittleEndian>(data.len() as u32)?;
+        body.write_u32::<LittleEndian>(original_len.max(data.len() as u32))?;
+        body.write_all(data)?;
+        body.resize((body.len() + 3) & !3, 0);
+
+        let flag_bytes = flags.to_le_bytes();
+        let mut options: Vec<(u16, &[u8])> = comments.iter().map(|c| (OPT_COMMENT, c.as_bytes())).collect();
+        if flags != 0 {
+            options.push((OPT_EPB_FLAGS, &flag_bytes));
+        }
+        write_options(&mut body, &options)?;
+        self.write_block(BLOCK_ENHANCED_PACKET, &body)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    fn write_block(&mut self, block_type: u32, body: &[u8]) -> io::Result<()> {
+        let block_len = body.len() as u32 + 12;
+        self.out.write_u32::<LittleEndian>(block_type)?;
+        self.out.write_u32::<LittleEndian>(block_len)?;
+        self.out.write_all(body)?;
+        self.out.write_u32::<LittleEndian>(block_len)
+    }
+}
+
+
+/**
+ * Append options to a block body, padding each to 4 bytes and ending with opt_endofopt
+ */
+fn write_options(body: &mut Vec<u8>, options: &[(u16, &[u8])]) -> io::Result<()> {
+    if options.is_empty() {
+        return Ok(())
+    }
+    for (code, value) in options {
+        body.write_u16::<LittleEndian>(*code)?;
+        body.write_u16::<LittleEndian>(value.len() as u16)?;
+        body.write_all(value)?;
+        body.resize((body.len() + 3) & !3, 0);
+    }
+    body.write_u16::<LittleEndian>(OPT_END_OF_OPT)?;
+    body.write_u16::<LittleEndian>(0)?;
+    Ok(())
+}
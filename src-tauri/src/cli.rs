@@ -0,0 +1,194 @@
+/*
+Headless mode, for decoding captures without the ui, e.g. `tcpdump -w - port 2050 | realm-stat --read -`
+*/
+use std::io::{self, Write};
+use crate::capture_file::{CaptureReader, STDIN_PATH};
+use crate::decode::{self, PayloadCipher, PayloadEncoding};
+use crate::filter::DisplayFilter;
+use crate::frame;
+use crate::packet_factory::RotmgPacketFactory;
+use crate::packet_source::FrameFilter;
+use crate::settings::{self, CaptureSettings};
+
+
+const USAGE: &str = "\
+Usage: realm-stat [command]
+
+Without a command the ui is started.
+
+Commands:
+  --read <path>    Decode a pcap or pcapng capture from a file, named pipe, or - for stdin.
+                   Packets are written to stdout as json lines as they are decoded,
+                   cipher alignment changes are written to stderr.
+  --filter <expr>  With --read, only write packets matching a display filter,
+                   e.g. 'type == Damage && damage_amount > 500'. Times of day are in utc.
+  --settings <path>
+                   With --read, use the capture settings in this json file instead of the ones saved by the ui.
+                   Frames are filtered by their bpf override, or by the game ports without one
+  --port <ports>   With --read, only decode traffic from these comma separated game ports
+  --decode <data>  Decode a single packet given as hex or base64, including its length and type header.
+                   The packet and the field each byte was read into are written to stdout as json.
+  --base64         With --decode, read the packet as base64 even if it is valid hex
+  --key <hex>      With --decode, decrypt the packet with this rc4 key first
+  --offset <n>     With --key, number of keystream bytes used before the packet, 0 by default
+  --help           Show this message
+";
+
+
+/**
+ * Run a headless command if one was given on the command line.
+ * Returns the exit code, or None if the ui should be started instead.
+ */
+pub fn run(args: &[String]) -> Option<i32> {
+    let code = match args.get(1).map(|a| a.as_str()) {
+        Some("--read") => {
+            let path = args.get(2).map(|p| p.as_str()).filter(|p| p.starts_with("--") == false).unwrap_or(STDIN_PATH);
+            let settings = match capture_settings(args) {
+                Ok(s) => s,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return Some(2)
+                }
+            };
+            let filter = match option(args, "--filter") {
+                Ok(Some(expr)) => match DisplayFilter::parse(expr) {
+                    Ok(f) => Some(f),
+                    Err(e) => {
+                        eprintln!("Invalid filter: {}", e);
+                        return Some(2)
+                    }
+                },
+                Ok(None) => None,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return Some(2)
+                }
+            };
+            match read_capture(path, filter.as_ref(), &settings) {
+                Ok(()) => 0,
+                //the consumer of stdout went away, like `realm-stat --read - | head`
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => 0,
+                Err(e) => {
+                    eprintln!("Error reading {}: {}", path, e);
+                    1
+                }
+            }
+        },
+        Some("--decode") => match decode_command(args) {
+            Ok(code) => code,
+            Err(e) => {
+                eprintln!("{}", e);
+                2
+            }
+        },
+        Some("--help") => {
+            print!("{}", USAGE);
+            0
+        },
+        _ => return None
+    };
+    Some(code)
+}
+
+
+/**
+ * Value given after an option, or an error if the option is last
+ */
+fn option<'a>(args: &'a [String], name: &str) -> Result<Option<&'a str>, String> {
+    match args.iter().position(|a| a == name).map(|i| args.get(i + 1)) {
+        Some(Some(value)) => Ok(Some(value.as_str())),
+        Some(None) => Err(format!("{} needs a value", name)),
+        None => Ok(None)
+    }
+}
+
+
+/**
+ * Capture settings for reading a capture: the file given with --settings, or the settings saved by the ui if there are any,
+ * with the game ports replaced by --port
+ */
+fn capture_settings(args: &[String]) -> Result<CaptureSettings, String> {
+    let mut settings = match option(args, "--settings")? {
+        Some(path) => {
+            let path = std::path::Path::new(path);
+            if path.exists() == false {
+                return Err(format!("No settings file at {}", path.display()))
+            }
+            CaptureSettings::load(path).map_err(|e| format!("Error reading {}: {}", path.display(), e))?
+        },
+        None => match settings::default_settings_path() {
+            Some(path) => CaptureSettings::load(&path).map_err(|e| format!("Error reading {}: {}", path.display(), e))?,
+            None => CaptureSettings::default()
+        }
+    };
+    if let Some(ports) = option(args, "--port")? {
+        settings.game_ports = ports.split(',')
+            .map(|p| p.trim().parse().map_err(|_| format!("Invalid port {}", p)))
+            .collect::<Result<_, _>>()?;
+    }
+    settings.validate()?;
+    Ok(settings)
+}
+
+
+/**
+ * Decode a single packet given on the command line.
+ * Exits with 1 if the packet couldn't be decoded, its field map is still written to show where decoding stopped.
+ */
+fn decode_command(args: &[String]) -> Result<i32, String> {
+    let text = option(args, "--decode")?.ok_or("--decode needs a packet as hex or base64")?;
+    let encoding = if args.iter().any(|a| a == "--base64") { Some(PayloadEncoding::Base64) } else { None };
+    let cipher = match option(args, "--key")? {
+        Some(key) => Some(PayloadCipher {
+            key: key.to_string(),
+            offset: option(args, "--offset")?.map(|o| o.parse().map_err(|_| format!("Invalid keystream offset {}", o))).transpose()?.unwrap_or(0),
+        }),
+        None => None
+    };
+    let decoded = decode::decode_payload(text, encoding, cipher.as_ref()).map_err(|e| e.to_string())?;
+    println!("{}", serde_json::to_string(&decoded).map_err(|e| e.to_string())?);
+    Ok(if decoded.packet.is_some() { 0 } else { 1 })
+}
+
+
+/**
+ * Decode a capture incrementally, writing each packet as soon as it is decoded
+ */
+fn read_capture(path: &str, filter: Option<&DisplayFilter>, settings: &CaptureSettings) -> io::Result<()> {
+    let mut reader = CaptureReader::open_stream(path)?;
+    let mut frame_filter = FrameFilter::new(settings);
+    let mut factory = RotmgPacketFactory::new();
+    let stdout = io::stdout();
+    let mut out = io::BufWriter::new(stdout.lock());
+
+    while let Some(f) = reader.next_frame()? {
+        if frame_filter.matches(&f)? == false {
+            continue
+        }
+        if let Ok((slice, meta)) = frame::slice_frame(f.linktype, f.timestamp, f.number, &f.data, settings) {
+            factory.insert_packet(slice, meta);
+        }
+        write_output(&mut factory, filter, &mut out)?;
+    }
+    factory.finalize();
+    write_output(&mut factory, filter, &mut out)
+}
+
+fn write_output(factory: &mut RotmgPacketFactory, filter: Option<&DisplayFilter>, out: &mut impl Write) -> io::Result<()> {
+    while let Some(e) = factory.get_event() {
+        eprintln!("{}", e.event_name());
+    }
+    let mut wrote = false;
+    while let Some(p) = factory.get_packet() {
+        if filter.map(|f| f.matches(&p)).unwrap_or(true) == false {
+            continue
+        }
+        serde_json::to_writer(&mut *out, &p.with_data())?;
+        out.write_all(b"\n")?;
+        wrote = true;
+    }
+    if wrote {
+        out.flush()?;
+    }
+    Ok(())
+}
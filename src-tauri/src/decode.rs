@@ -0,0 +1,99 @@
+/*
+Decodes a single packet pasted as text, like a snippet from a log or another tool, without needing a capture
+*/
+use base64::Engine;
+use crate::error::{self, Error};
+use crate::packet_factory::field_map::AnnotatedPacket;
+use crate::rc4::Rc4;
+
+
+/// Bytes of a packet before the encrypted body, the length and type are sent in the clear
+const HEADER_LEN: usize = 5;
+/// Largest keystream offset a payload is decrypted at, as far as the tick alignment searches the keystream
+const MAX_KEYSTREAM_OFFSET: usize = 100_000_000;
+
+
+/**
+ * How a pasted payload is written
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum PayloadEncoding {
+    Hex,
+    Base64,
+}
+impl PayloadEncoding {
+    /**
+     * Guess the encoding of a payload, anything that is valid hex is taken to be hex
+     */
+    pub fn detect(text: &str) -> Self {
+        let digits = strip_hex(text);
+        if digits.len() % 2 == 0 && digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return PayloadEncoding::Hex
+        }
+        PayloadEncoding::Base64
+    }
+}
+
+
+/**
+ * Cipher state an encrypted payload was sent with
+ */
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct PayloadCipher {
+    /// Rc4 key as hex
+    pub key: String,
+    /// Number of keystream bytes used before this packet
+    #[serde(default)]
+    pub offset: usize,
+}
+
+
+/**
+ * Decode a packet given as hex or base64, with its length and type header as sent on the wire.
+ * Encrypted packets are decrypted with the cipher first, the header isn't encrypted so it is left as is.
+ * Encoding is detected from the text if it isn't given.
+ */
+pub fn decode_payload(text: &str, encoding: Option<PayloadEncoding>, cipher: Option<&PayloadCipher>) -> error::Result<AnnotatedPacket> {
+    let mut bytes = match encoding.unwrap_or_else(|| PayloadEncoding::detect(text)) {
+        PayloadEncoding::Hex => hex::decode(strip_hex(text)).map_err(|e| Error::BadPayload(format!("Invalid hex: {}", e)))?,
+        PayloadEncoding::Base64 => {
+            let text = text.chars().filter(|c| c.is_whitespace() == false).collect::<String>();
+            base64::engine::general_purpose::STANDARD.decode(text).map_err(|e| Error::BadPayload(format!("Invalid base64: {}", e)))?
+        },
+    };
+    if bytes.len() < HEADER_LEN {
+        return Err(Error::BadPayload(format!("A packet is at least {} bytes, the length and type header", HEADER_LEN)))
+    }
+    if let Some(cipher) = cipher {
+        if cipher.offset > MAX_KEYSTREAM_OFFSET {
+            return Err(Error::BadPayload(format!("Keystream offset is at most {}", MAX_KEYSTREAM_OFFSET)))
+        }
+        let mut rc4 = Rc4::from_string_key(&cipher.key).map_err(|e| Error::BadPayload(format!("Invalid key: {}", e)))?;
+        rc4.skip(cipher.offset);
+        bytes = rc4.apply_keystream(HEADER_LEN, &bytes);
+    }
+    Ok(AnnotatedPacket::decode(bytes))
+}
+
+
+/**
+ * Hex without whitespace, byte separators like the colons wireshark copies with, or a 0x prefix
+ */
+fn strip_hex(text: &str) -> String {
+    let text = text.trim();
+    let text = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).unwrap_or(text);
+    text.chars().filter(|c| c.is_whitespace() == false && *c != ':').collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_keystream_offsets_past_the_cap() {
+        let cipher = |offset| PayloadCipher { key: "0123456789abcdef".to_string(), offset };
+        assert!(matches!(decode_payload("0000000509", Some(PayloadEncoding::Hex), Some(&cipher(MAX_KEYSTREAM_OFFSET + 1))), Err(Error::BadPayload(_))));
+        assert!(decode_payload("0000000509", Some(PayloadEncoding::Hex), Some(&cipher(1000))).is_ok());
+    }
+}
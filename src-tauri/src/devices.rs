@@ -0,0 +1,105 @@
+use std::time::{Duration, Instant};
+use pcap::Device;
+use crate::frame::{self, SkipReason};
+use crate::settings::CaptureSettings;
+
+
+/// How long each device is listened to when detecting the game interface
+pub const DEFAULT_DETECTION_TIME: Duration = Duration::from_secs(3);
+
+/// The pseudo device capturing on every interface, which would always win detection
+const ANY_DEVICE: &str = "any";
+
+
+/**
+ * A capture device shown in the ui. Devices are identified by name since many don't have a description.
+ */
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct DeviceInfo {
+    pub name: String,
+    pub description: Option<String>,
+}
+impl From<&Device> for DeviceInfo {
+    fn from(d: &Device) -> Self {
+        Self {
+            name: d.name.clone(),
+            description: d.desc.clone(),
+        }
+    }
+}
+
+
+/**
+ * How much game traffic was seen on a device while detecting
+ */
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct DetectionResult {
+    pub device: DeviceInfo,
+    /// Tcp segments seen from a game port, including bare acks
+    pub game_frames: usize,
+}
+
+
+pub fn list_devices() -> Result<Vec<DeviceInfo>, pcap::Error> {
+    Ok(Device::list()?.iter().map(DeviceInfo::from).collect())
+}
+
+pub fn find_device(name: &str) -> Result<Option<Device>, pcap::Error> {
+    Ok(Device::list()?.into_iter().find(|d| d.name == name))
+}
+
+
+/**
+ * Listen on every device at once for the given time and count the game traffic seen on each.
+ * Devices that can't be opened are left out. Results are sorted with the most game traffic first.
+ */
+pub fn detect_game_traffic(settings: &CaptureSettings, duration: Duration) -> Result<Vec<DetectionResult>, pcap::Error> {
+    let handles: Vec<_> = Device::list()?
+        .into_iter()
+        .filter(|d| d.name != ANY_DEVICE)
+        .map(|d| {
+            let settings = settings.clone();
+            std::thread::spawn(move || count_game_frames(d, &settings, duration))
+        })
+        .collect();
+
+    let mut results: Vec<DetectionResult> = handles.into_iter().filter_map(|h| h.join().ok().flatten()).collect();
+    results.sort_by(|a, b| b.game_frames.cmp(&a.game_frames));
+    Ok(results)
+}
+
+/**
+ * Count the game frames captured on a single device, None if the device couldn't be opened
+ */
+fn count_game_frames(device: Device, settings: &CaptureSettings, duration: Duration) -> Option<DetectionResult> {
+    let info = DeviceInfo::from(&device);
+    let mut cap = match pcap::Capture::from_device(device).and_then(|c| c.immediate_mode(true).timeout(100).open()) {
+        Ok(c) => c,
+        Err(e) => {
+            log::debug!("Skipping device {} in detection: {}", info.name, e);
+            return None
+        }
+    };
+    let linktype = frame::linktype_from_dlt(cap.get_datalink().0);
+    if let Err(e) = cap.filter(&frame::capture_filter(linktype, settings), true) {
+        log::debug!("Skipping device {} in detection: {}", info.name, e);
+        return None
+    }
+
+    let mut game_frames = 0;
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        match cap.next_packet() {
+            Ok(p) => match frame::slice_frame(linktype, 0, 0, &p, settings) {
+                Ok(_) | Err(SkipReason::Empty) => game_frames += 1,
+                Err(_) => (),
+            },
+            Err(pcap::Error::TimeoutExpired) => (),
+            Err(e) => {
+                log::debug!("Stopping detection on device {}: {}", info.name, e);
+                break
+            }
+        }
+    }
+    Some(DetectionResult { device: info, game_frames })
+}
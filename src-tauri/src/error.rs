@@ -0,0 +1,139 @@
+use std::fmt;
+use std::io;
+use crate::filter::FilterError;
+
+
+/**
+ * Errors returned from tauri commands.
+ * Sent to the ui as {kind, message} so it can show the message and react to specific kinds.
+ */
+#[derive(Debug)]
+pub enum Error {
+    /// A live capture was started before a device was selected
+    NoDeviceSelected,
+    /// No capture device has the given name
+    DeviceNotFound(String),
+    /// The app isn't allowed to capture, usually fixed by running as root or granting capture capabilities
+    PermissionDenied(String),
+    /// The capture filter couldn't be compiled
+    BadFilter(String),
+    /// The display filter of a packet query couldn't be parsed
+    BadDisplayFilter(String),
+    /// Any other error from libpcap
+    Capture(String),
+    InvalidSettings(String),
+    /// A replay command was sent while no replay is running
+    NoReplay,
+    /// The capture was paused or resumed while nothing is being captured
+    NotCapturing,
+    /// A past session can't be opened while capturing into the current one
+    CaptureRunning,
+    /// No saved session has the given id
+    SessionNotFound(i64),
+    /// The current session has no packet at the given index
+    PacketNotFound(usize),
+    /// A pasted packet, or the key to decrypt it with, couldn't be read
+    BadPayload(String),
+    /// The session database couldn't be opened, read or written
+    Database(String),
+    Io(io::Error),
+}
+impl Error {
+    /**
+     * Name of the error kind sent to the ui
+     */
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::NoDeviceSelected => "NoDeviceSelected",
+            Error::DeviceNotFound(_) => "DeviceNotFound",
+            Error::PermissionDenied(_) => "PermissionDenied",
+            Error::BadFilter(_) => "BadFilter",
+            Error::BadDisplayFilter(_) => "BadDisplayFilter",
+            Error::Capture(_) => "Capture",
+            Error::InvalidSettings(_) => "InvalidSettings",
+            Error::NoReplay => "NoReplay",
+            Error::NotCapturing => "NotCapturing",
+            Error::CaptureRunning => "CaptureRunning",
+            Error::SessionNotFound(_) => "SessionNotFound",
+            Error::PacketNotFound(_) => "PacketNotFound",
+            Error::BadPayload(_) => "BadPayload",
+            Error::Database(_) => "Database",
+            Error::Io(_) => "Io",
+        }
+    }
+
+    /**
+     * Wrap an error from compiling or setting a capture filter
+     */
+    pub fn bad_filter(e: pcap::Error) -> Self {
+        Error::BadFilter(e.to_string())
+    }
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NoDeviceSelected => write!(f, "No capture device selected"),
+            Error::DeviceNotFound(name) => write!(f, "No capture device named {}", name),
+            Error::PermissionDenied(e) => write!(f, "Permission to capture was denied, try running as administrator or granting capture permissions ({})", e),
+            Error::BadFilter(e) => write!(f, "Invalid capture filter: {}", e),
+            Error::BadDisplayFilter(e) => write!(f, "Invalid display filter: {}", e),
+            Error::Capture(e) => write!(f, "Capture error: {}", e),
+            Error::InvalidSettings(e) => write!(f, "Invalid settings: {}", e),
+            Error::NoReplay => write!(f, "No replay is running"),
+            Error::NotCapturing => write!(f, "No capture is running"),
+            Error::CaptureRunning => write!(f, "Stop the capture first"),
+            Error::SessionNotFound(id) => write!(f, "No saved session with id {}", id),
+            Error::PacketNotFound(index) => write!(f, "No packet {} in the current session", index),
+            Error::BadPayload(e) => write!(f, "{}", e),
+            Error::Database(e) => write!(f, "Session database error: {}", e),
+            Error::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::PermissionDenied => Error::PermissionDenied(e.to_string()),
+            _ => Error::Io(e),
+        }
+    }
+}
+
+impl From<pcap::Error> for Error {
+    fn from(e: pcap::Error) -> Self {
+        //libpcap only reports a lack of capture permissions in its error message
+        let message = e.to_string();
+        let lower = message.to_lowercase();
+        if lower.contains("permission") || lower.contains("not permitted") {
+            return Error::PermissionDenied(message)
+        }
+        Error::Capture(message)
+    }
+}
+
+impl From<FilterError> for Error {
+    fn from(e: FilterError) -> Self {
+        Error::BadDisplayFilter(e.to_string())
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        Error::Database(e.to_string())
+    }
+}
+
+impl serde::Serialize for Error {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let mut s = serializer.serialize_struct("Error", 2)?;
+        s.serialize_field("kind", self.kind())?;
+        s.serialize_field("message", &self.to_string())?;
+        s.end()
+    }
+}
+
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,123 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use crate::packet_factory::packet_envelope::PacketEnvelope;
+use super::{ExportSummary, flatten_fields};
+
+
+/// Columns every table starts with, before the packet fields
+const ENVELOPE_COLUMNS: [&str; 7] = ["index", "timestamp", "source", "destination", "tcp_seq", "frame", "alignment"];
+
+
+/**
+ * A csv file for one packet type.
+ * Packets of a type don't always have the same fields, so rows are held in a scratch file
+ * until every packet has been seen and the columns are known.
+ */
+struct Table {
+    path: PathBuf,
+    rows_path: PathBuf,
+    rows: BufWriter<File>,
+    /// Every packet field seen in the table so far
+    columns: BTreeSet<String>,
+}
+impl Table {
+    fn create(path: PathBuf) -> io::Result<Self> {
+        let rows_path = path.with_extension("csv.rows");
+        Ok(Self {
+            rows: BufWriter::new(File::create(&rows_path)?),
+            path,
+            rows_path,
+            columns: BTreeSet::new(),
+        })
+    }
+
+    fn write(&mut self, envelope: &[String], fields: &BTreeMap<String, String>) -> io::Result<()> {
+        self.columns.extend(fields.keys().cloned());
+        serde_json::to_writer(&mut self.rows, &(envelope, fields))?;
+        self.rows.write_all(b"\n")
+    }
+
+    /**
+     * Write the csv file with a column for every field any of its packets had, leaving the ones a packet didn't have empty
+     */
+    fn finish(self) -> io::Result<PathBuf> {
+        let Table { path, rows_path, rows, columns } = self;
+        drop(rows.into_inner().map_err(|e| e.into_error())?);
+
+        let mut writer = csv::Writer::from_path(&path).map_err(csv_error)?;
+        writer.write_record(ENVELOPE_COLUMNS.iter().map(|c| c.to_string()).chain(columns.iter().cloned())).map_err(csv_error)?;
+        for line in BufReader::new(File::open(&rows_path)?).lines() {
+            let (envelope, fields): (Vec<String>, BTreeMap<String, String>) = serde_json::from_str(&line?)?;
+            let values = columns.iter().map(|c| fields.get(c).cloned().unwrap_or_default());
+            writer.write_record(envelope.into_iter().chain(values)).map_err(csv_error)?;
+        }
+        writer.flush()?;
+        std::fs::remove_file(&rows_path)?;
+        Ok(path)
+    }
+}
+
+
+/**
+ * Write a csv table per packet type to the output directory, named after the type
+ */
+pub fn export(packets: impl Iterator<Item = PacketEnvelope>, output: &Path) -> io::Result<ExportSummary> {
+    std::fs::create_dir_all(output)?;
+    let mut tables: BTreeMap<&'static str, Table> = BTreeMap::new();
+    let mut count = 0;
+    for p in packets {
+        let packet = serde_json::to_value(&p.packet)?;
+        let fields: BTreeMap<String, String> = flatten_fields(&packet).into_iter().collect();
+
+        let type_name = p.packet.type_name();
+        if tables.contains_key(type_name) == false {
+            tables.insert(type_name, Table::create(output.join(format!("{type_name}.csv")))?);
+        }
+        let envelope = [
+            p.index.to_string(),
+            p.timestamp.to_string(),
+            p.connection.source.to_string(),
+            p.connection.destination.to_string(),
+            p.tcp_seq.to_string(),
+            p.frame.to_string(),
+            format!("{:?}", p.alignment),
+        ];
+        tables.get_mut(type_name).unwrap().write(&envelope, &fields)?;
+        count += 1;
+    }
+
+    let mut files = vec![];
+    for (_, table) in tables {
+        files.push(table.finish()?);
+    }
+    Ok(ExportSummary { packets: count, files })
+}
+
+fn csv_error(e: csv::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn has_a_column_for_every_field_of_a_type() {
+        let path = std::env::temp_dir().join(format!("realm-stat-csv-test-{}.csv", std::process::id()));
+        let mut table = Table::create(path.clone()).unwrap();
+        let envelope: Vec<String> = ENVELOPE_COLUMNS.iter().map(|c| c.to_string()).collect();
+        let fields = |pairs: &[(&str, &str)]| pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        table.write(&envelope, &fields(&[("a", "1")])).unwrap();
+        table.write(&envelope, &fields(&[("a", "2"), ("b.c", "3")])).unwrap();
+        table.finish().unwrap();
+
+        let csv = std::fs::read_to_string(&path).unwrap();
+        let envelope = ENVELOPE_COLUMNS.join(",");
+        assert_eq!(csv, format!("{envelope},a,b.c\n{envelope},1,\n{envelope},2,3\n"));
+        assert!(path.with_extension("csv.rows").exists() == false);
+        let _ = std::fs::remove_file(&path);
+    }
+}
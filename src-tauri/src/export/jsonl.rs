@@ -0,0 +1,21 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use crate::packet_factory::packet_envelope::PacketEnvelope;
+use super::ExportSummary;
+
+
+/**
+ * Write every packet envelope as a line of json, the same as the --read command line output
+ */
+pub fn export(packets: impl Iterator<Item = PacketEnvelope>, output: &Path) -> io::Result<ExportSummary> {
+    let mut out = BufWriter::new(File::create(output)?);
+    let mut count = 0;
+    for p in packets {
+        serde_json::to_writer(&mut out, &p.with_data())?;
+        out.write_all(b"\n")?;
+        count += 1;
+    }
+    out.flush()?;
+    Ok(ExportSummary { packets: count, files: vec![output.to_path_buf()] })
+}
@@ -0,0 +1,79 @@
+/*
+Writers for getting decoded sessions out of the app, for analysis in other tools
+*/
+mod jsonl;
+mod csv_tables;
+mod parquet_file;
+pub mod wireshark;
+
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::packet_factory::packet_envelope::PacketEnvelope;
+
+
+/**
+ * File formats a session can be exported to
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum ExportFormat {
+    /// A single file with every packet envelope as a json object per line
+    JsonLines,
+    /// A directory with a csv table per packet type, with the packet fields flattened into columns
+    Csv,
+    /// A single parquet file with a row per packet and the decoded packet as a json column
+    Parquet,
+}
+
+
+/**
+ * Returned once an export has finished
+ */
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ExportSummary {
+    pub packets: usize,
+    /// Every file that was written
+    pub files: Vec<PathBuf>,
+}
+
+
+/**
+ * Write packets to output in the given format.
+ * For csv output is a directory that is created if needed, otherwise it is the file to write.
+ */
+pub fn export(format: ExportFormat, packets: impl Iterator<Item = PacketEnvelope>, output: &Path) -> io::Result<ExportSummary> {
+    match format {
+        ExportFormat::JsonLines => jsonl::export(packets, output),
+        ExportFormat::Csv => csv_tables::export(packets, output),
+        ExportFormat::Parquet => parquet_file::export(packets, output),
+    }
+}
+
+
+/**
+ * Flatten a decoded packet into (column, value) pairs.
+ * Nested objects become dotted column names, arrays are kept as json text.
+ */
+fn flatten_fields(packet: &serde_json::Value) -> Vec<(String, String)> {
+    let mut fields = vec![];
+    //packets are serialized as {"TypeName": {fields}}
+    let fields_value = match packet {
+        serde_json::Value::Object(o) if o.len() == 1 => o.values().next().unwrap(),
+        other => other,
+    };
+    flatten_value("", fields_value, &mut fields);
+    fields
+}
+
+fn flatten_value(prefix: &str, value: &serde_json::Value, fields: &mut Vec<(String, String)>) {
+    match value {
+        serde_json::Value::Object(o) => {
+            for (key, v) in o {
+                let name = if prefix.is_empty() { key.clone() } else { format!("{prefix}.{key}") };
+                flatten_value(&name, v, fields);
+            }
+        },
+        serde_json::Value::Null => fields.push((prefix.to_string(), String::new())),
+        serde_json::Value::String(s) => fields.push((prefix.to_string(), s.clone())),
+        other => fields.push((prefix.to_string(), other.to_string())),
+    }
+}
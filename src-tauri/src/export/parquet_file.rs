@@ -0,0 +1,125 @@
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use parquet::basic::Compression;
+use parquet::data_type::{ByteArray, ByteArrayType, Int64Type};
+use parquet::errors::ParquetError;
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+use crate::packet_factory::packet_envelope::PacketEnvelope;
+use super::ExportSummary;
+
+
+/// Packets written per row group
+const ROW_GROUP_SIZE: usize = 50_000;
+
+const SCHEMA: &str = "
+message packet {
+    REQUIRED INT64 index;
+    REQUIRED INT64 timestamp (TIMESTAMP(MICROS, true));
+    REQUIRED BYTE_ARRAY type (UTF8);
+    REQUIRED BYTE_ARRAY source (UTF8);
+    REQUIRED BYTE_ARRAY destination (UTF8);
+    REQUIRED INT64 tcp_seq;
+    REQUIRED INT64 frame;
+    REQUIRED BYTE_ARRAY alignment (UTF8);
+    REQUIRED BYTE_ARRAY packet (JSON);
+    REQUIRED BYTE_ARRAY data;
+}
+";
+
+
+/**
+ * Column values of the packets waiting to be written as a row group
+ */
+#[derive(Default)]
+struct Columns {
+    index: Vec<i64>,
+    timestamp: Vec<i64>,
+    type_name: Vec<ByteArray>,
+    source: Vec<ByteArray>,
+    destination: Vec<ByteArray>,
+    tcp_seq: Vec<i64>,
+    frame: Vec<i64>,
+    alignment: Vec<ByteArray>,
+    packet: Vec<ByteArray>,
+    data: Vec<ByteArray>,
+}
+impl Columns {
+    fn push(&mut self, p: PacketEnvelope) -> io::Result<()> {
+        self.index.push(p.index as i64);
+        self.timestamp.push(p.timestamp);
+        self.type_name.push(ByteArray::from(p.packet.type_name()));
+        self.source.push(ByteArray::from(p.connection.source.to_string().as_str()));
+        self.destination.push(ByteArray::from(p.connection.destination.to_string().as_str()));
+        self.tcp_seq.push(p.tcp_seq as i64);
+        self.frame.push(p.frame as i64);
+        self.alignment.push(ByteArray::from(format!("{:?}", p.alignment).as_str()));
+        self.packet.push(ByteArray::from(serde_json::to_vec(&p.packet)?));
+        self.data.push(ByteArray::from(p.data));
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    /**
+     * Write the pending values as a row group and clear them
+     */
+    fn write_row_group(&mut self, writer: &mut SerializedFileWriter<File>) -> Result<(), ParquetError> {
+        let mut row_group = writer.next_row_group()?;
+        let mut column_number = 0;
+        //columns are handed out in schema order
+        while let Some(mut column) = row_group.next_column()? {
+            match column_number {
+                0 => column.typed::<Int64Type>().write_batch(&self.index, None, None)?,
+                1 => column.typed::<Int64Type>().write_batch(&self.timestamp, None, None)?,
+                2 => column.typed::<ByteArrayType>().write_batch(&self.type_name, None, None)?,
+                3 => column.typed::<ByteArrayType>().write_batch(&self.source, None, None)?,
+                4 => column.typed::<ByteArrayType>().write_batch(&self.destination, None, None)?,
+                5 => column.typed::<Int64Type>().write_batch(&self.tcp_seq, None, None)?,
+                6 => column.typed::<Int64Type>().write_batch(&self.frame, None, None)?,
+                7 => column.typed::<ByteArrayType>().write_batch(&self.alignment, None, None)?,
+                8 => column.typed::<ByteArrayType>().write_batch(&self.packet, None, None)?,
+                _ => column.typed::<ByteArrayType>().write_batch(&self.data, None, None)?,
+            };
+            column.close()?;
+            column_number += 1;
+        }
+        row_group.close()?;
+        *self = Self::default();
+        Ok(())
+    }
+}
+
+
+/**
+ * Write every packet as a row of a parquet file, snappy compressed
+ */
+pub fn export(packets: impl Iterator<Item = PacketEnvelope>, output: &Path) -> io::Result<ExportSummary> {
+    let schema = Arc::new(parse_message_type(SCHEMA).map_err(parquet_error)?);
+    let properties = Arc::new(WriterProperties::builder().set_compression(Compression::SNAPPY).build());
+    let mut writer = SerializedFileWriter::new(File::create(output)?, schema, properties).map_err(parquet_error)?;
+
+    let mut columns = Columns::default();
+    let mut count = 0;
+    for p in packets {
+        columns.push(p)?;
+        count += 1;
+        if columns.len() >= ROW_GROUP_SIZE {
+            columns.write_row_group(&mut writer).map_err(parquet_error)?;
+        }
+    }
+    if columns.len() > 0 {
+        columns.write_row_group(&mut writer).map_err(parquet_error)?;
+    }
+    writer.close().map_err(parquet_error)?;
+    Ok(ExportSummary { packets: count, files: vec![output.to_path_buf()] })
+}
+
+fn parquet_error(e: ParquetError) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
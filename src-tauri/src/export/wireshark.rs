@@ -0,0 +1,186 @@
+/*
+Decrypted session captures and a lua dissector for inspecting them in wireshark
+*/
+use std::fmt::Write as _;
+use std::io;
+use std::path::Path;
+use crate::capture_file::pcapng::{PcapngWriter, EPB_FLAG_INBOUND, EPB_FLAG_OUTBOUND};
+use crate::frame::{LINKTYPE_USER0, LINKTYPE_USER15};
+use crate::packet_factory::packet_envelope::PacketEnvelope;
+use crate::packet_factory::rotmg_packet::{FieldKind, PACKET_TYPES};
+use crate::settings::CaptureSettings;
+use super::ExportSummary;
+
+
+/**
+ * Write a pcapng capture with a frame per decrypted packet, holding the packet as it was on the wire before encryption:
+ * its length as a u32, type number and plaintext body.
+ * Frames keep the capture time of the packet, and are marked inbound if they were sent by a game server.
+ *
+ * A lua dissector for the link type is written next to the capture, named after it.
+ */
+pub fn export_decrypted_pcapng(packets: impl Iterator<Item = PacketEnvelope>, output: &Path, linktype: u32, settings: &CaptureSettings) -> io::Result<ExportSummary> {
+    if (LINKTYPE_USER0..=LINKTYPE_USER15).contains(&linktype) == false {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Link type {} isn't a user link type ({}-{})", linktype, LINKTYPE_USER0, LINKTYPE_USER15)))
+    }
+    let mut writer = PcapngWriter::create(output)?;
+    let interface = writer.add_interface(linktype)?;
+    let mut count = 0;
+    for p in packets {
+        let mut frame = Vec::with_capacity(p.data.len() + 5);
+        frame.extend_from_slice(&(p.data.len() as u32 + 5).to_be_bytes());
+        frame.push(p.packet.type_num());
+        frame.extend_from_slice(&p.data);
+        let flags = if settings.is_game_port(p.connection.source.port()) { EPB_FLAG_INBOUND } else { EPB_FLAG_OUTBOUND };
+        writer.write_frame(interface, p.timestamp, frame.len() as u32, &frame, &[], flags)?;
+        count += 1;
+    }
+    writer.flush()?;
+
+    let dissector_path = output.with_extension("lua");
+    std::fs::write(&dissector_path, lua_dissector(linktype))?;
+    Ok(ExportSummary { packets: count, files: vec![output.to_path_buf(), dissector_path] })
+}
+
+
+/**
+ * Generate a wireshark lua dissector for decrypted packets captured under the user link type,
+ * naming every known packet type and splitting the decoded ones into their fields
+ */
+pub fn lua_dissector(linktype: u32) -> String {
+    let mut lua = String::new();
+    //writing to a String can't fail
+    let _ = write_dissector(&mut lua, linktype);
+    lua
+}
+
+fn write_dissector(lua: &mut String, linktype: u32) -> std::fmt::Result {
+    writeln!(lua, "-- RotMG dissector generated by realm-stat for decrypted captures using link type {linktype}")?;
+    writeln!(lua, "-- Copy to the wireshark plugins directory, or run wireshark with -X lua_script:<this file>")?;
+    writeln!(lua)?;
+    writeln!(lua, "local rotmg = Proto(\"rotmg\", \"RotMG Application Packet\")")?;
+    writeln!(lua)?;
+    writeln!(lua, "local packet_types = {{")?;
+    for t in PACKET_TYPES {
+        writeln!(lua, "    [{}] = \"{}\",", t.type_num, t.name)?;
+    }
+    writeln!(lua, "}}")?;
+    writeln!(lua)?;
+    writeln!(lua, "local f = rotmg.fields")?;
+    writeln!(lua, "f.length = ProtoField.uint32(\"rotmg.length\", \"Length\", base.DEC)")?;
+    writeln!(lua, "f.type = ProtoField.uint8(\"rotmg.type\", \"Type\", base.DEC, packet_types)")?;
+    writeln!(lua, "f.rem = ProtoField.bytes(\"rotmg.rem\", \"Undecoded\")")?;
+
+    let mut layouts = String::new();
+    for t in PACKET_TYPES {
+        //bytes that aren't decoded yet are left to the rem field
+        let fields = t.fields.iter().filter(|(_, kind)| *kind != FieldKind::Rem).collect::<Vec<_>>();
+        if fields.is_empty() {
+            continue
+        }
+        let (type_num, type_name) = (t.type_num, t.name);
+        writeln!(layouts, "    [{type_num}] = {{")?;
+        for (field, kind) in fields {
+            let id = format!("{type_name}_{field}");
+            let abbr = format!("rotmg.{type_name}.{field}");
+            let proto_field = match kind {
+                FieldKind::U8 | FieldKind::Bool => format!("ProtoField.uint8(\"{abbr}\", \"{field}\", base.DEC)"),
+                FieldKind::U16 => format!("ProtoField.uint16(\"{abbr}\", \"{field}\", base.DEC)"),
+                FieldKind::U32 => format!("ProtoField.uint32(\"{abbr}\", \"{field}\", base.DEC)"),
+                FieldKind::F32 => format!("ProtoField.float(\"{abbr}\", \"{field}\")"),
+                FieldKind::String => format!("ProtoField.string(\"{abbr}\", \"{field}\")"),
+                FieldKind::ShortBytes | FieldKind::Rest | FieldKind::Rem => format!("ProtoField.bytes(\"{abbr}\", \"{field}\")"),
+            };
+            writeln!(lua, "f.{id} = {proto_field}")?;
+            writeln!(layouts, "        {{\"{}\", f.{id}}},", lua_kind(*kind))?;
+        }
+        writeln!(layouts, "    }},")?;
+    }
+    writeln!(lua)?;
+    writeln!(lua, "-- fields of the decoded packet types, in the order they are read")?;
+    writeln!(lua, "local layouts = {{")?;
+    lua.push_str(&layouts);
+    writeln!(lua, "}}")?;
+    writeln!(lua)?;
+    lua.push_str(DISSECTOR_BODY);
+    writeln!(lua)?;
+    writeln!(lua, "DissectorTable.get(\"wtap_encap\"):add((wtap_encaps or wtap).USER0 + {}, rotmg)", linktype - LINKTYPE_USER0)?;
+    Ok(())
+}
+
+fn lua_kind(kind: FieldKind) -> &'static str {
+    match kind {
+        FieldKind::U8 | FieldKind::Bool => "u8",
+        FieldKind::U16 => "u16",
+        FieldKind::U32 => "u32",
+        FieldKind::F32 => "f32",
+        FieldKind::String => "string",
+        FieldKind::ShortBytes => "short_bytes",
+        FieldKind::Rest | FieldKind::Rem => "rest",
+    }
+}
+
+const DISSECTOR_BODY: &str = r#"local sizes = {u8 = 1, u16 = 2, u32 = 4, f32 = 4}
+local direction = Field.new("frame.packet_flags_direction")
+
+function rotmg.dissector(buffer, pinfo, tree)
+    pinfo.cols.protocol = "RotMG"
+    local type_num = buffer(4, 1):uint()
+    local name = packet_types[type_num] or ("Unknown" .. type_num)
+    local dir = direction()
+    if dir ~= nil and dir.value == 1 then
+        pinfo.cols.info = "S → C " .. name
+    elseif dir ~= nil and dir.value == 2 then
+        pinfo.cols.info = "C → S " .. name
+    else
+        pinfo.cols.info = name
+    end
+
+    local subtree = tree:add(rotmg, buffer(), "RotMG " .. name)
+    subtree:add(f.length, buffer(0, 4))
+    subtree:add(f.type, buffer(4, 1))
+
+    local offset = 5
+    for _, field in ipairs(layouts[type_num] or {}) do
+        local kind, proto_field = field[1], field[2]
+        local remaining = buffer:len() - offset
+        if remaining <= 0 then break end
+        if kind == "string" then
+            local len = buffer(offset, 2):uint()
+            if len > 0 then subtree:add(proto_field, buffer(offset + 2, len)) end
+            offset = offset + 2 + len
+        elseif kind == "short_bytes" then
+            local len = buffer(offset, 1):uint()
+            if len > 0 then subtree:add(proto_field, buffer(offset + 1, len)) end
+            offset = offset + 1 + len
+        elseif kind == "rest" then
+            subtree:add(proto_field, buffer(offset))
+            offset = buffer:len()
+        else
+            subtree:add(proto_field, buffer(offset, sizes[kind]))
+            offset = offset + sizes[kind]
+        end
+    end
+    if offset < buffer:len() then
+        subtree:add(f.rem, buffer(offset))
+    end
+end
+"#;
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dissects_every_decoded_packet_type() {
+        let lua = lua_dissector(LINKTYPE_USER0);
+        assert!(lua.contains("[10] = \"NewTick\","));
+        assert!(lua.contains("[62] = \"Move\","));
+        assert!(lua.contains("f.NewTick_tick_id = ProtoField.uint32(\"rotmg.NewTick.tick_id\""));
+        assert!(lua.contains("f.Damage_effects = ProtoField.bytes("));
+        //undecoded bytes are only added as the rem field
+        assert!(lua.contains("NewTick_rem") == false);
+        assert!(lua.contains("(wtap_encaps or wtap).USER0 + 0, rotmg)"));
+    }
+}
@@ -0,0 +1,579 @@
+/*
+Display filters over decoded packets, in the style of wireshark's, e.g.
+    type == Damage && damage_amount > 500
+    Text.recipient != "" && time > 10:00
+    Reconnect || (MapInfo && name contains "Realm")
+
+Fields are either envelope fields (type, type_num, index, time, timestamp, frame, source, destination, alignment, len, tcp_seq)
+or packet fields as they are serialized, optionally qualified with the packet type. Nested fields are joined with dots.
+A field on its own checks that it is present, and a packet type name on its own checks the packet type.
+Comparisons with fields a packet doesn't have are false, whatever the operator.
+*/
+use std::cell::OnceCell;
+use std::fmt;
+use crate::packet_factory::packet_envelope::PacketEnvelope;
+
+
+const MICROS_PER_SECOND: f64 = 1_000_000.0;
+const MICROS_PER_DAY: i64 = 86_400_000_000;
+
+
+/**
+ * Why a display filter couldn't be parsed
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterError {
+    pub message: String,
+    /// Character position in the filter the error was found at
+    pub position: usize,
+}
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+impl std::error::Error for FilterError {}
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(f64),
+    Str(String),
+    /// Time of day in microseconds since midnight
+    Time(i64),
+    Op(&'static str),
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CompareOp {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    Contains,
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EnvelopeField {
+    Type,
+    TypeNum,
+    Index,
+    Time,
+    Timestamp,
+    Frame,
+    Source,
+    Destination,
+    Alignment,
+    Len,
+    TcpSeq,
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum Field {
+    Envelope(EnvelopeField),
+    /// Path into the serialized packet fields, only matching packets of packet_type if it is given
+    Packet { packet_type: Option<String>, path: Vec<String> },
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum Literal {
+    Number(f64),
+    Str(String),
+    Time(i64),
+    Bool(bool),
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(Field, CompareOp, Literal),
+    Exists(Field),
+    IsType(String),
+}
+
+
+/**
+ * A value taken from a packet to compare against a literal
+ */
+enum Value {
+    Number(f64),
+    Str(String),
+    Bool(bool),
+    /// Capture timestamp in microseconds since the unix epoch
+    Time(i64),
+}
+
+
+/**
+ * A parsed display filter
+ */
+#[derive(Debug, Clone)]
+pub struct DisplayFilter {
+    expr: Expr,
+    /// Offset from utc in microseconds that times of day are compared in
+    utc_offset: i64,
+}
+impl DisplayFilter {
+    pub fn parse(text: &str) -> Result<Self, FilterError> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser { tokens, position: 0, end: text.chars().count() };
+        let expr = parser.parse_or()?;
+        if let Some((_, position)) = parser.tokens.get(parser.position) {
+            return Err(FilterError { message: "Unexpected input".to_string(), position: *position })
+        }
+        Ok(Self { expr, utc_offset: 0 })
+    }
+
+    /**
+     * Compare times of day like 10:00 in the time zone offset from utc by the given minutes, instead of in utc
+     */
+    pub fn with_utc_offset(mut self, minutes: i32) -> Self {
+        self.utc_offset = minutes as i64 * 60_000_000;
+        self
+    }
+
+    pub fn matches(&self, envelope: &PacketEnvelope) -> bool {
+        let context = Context { envelope, packet: OnceCell::new(), utc_offset: self.utc_offset };
+        context.eval(&self.expr)
+    }
+}
+
+
+/**
+ * A packet being matched, serialized only if a packet field is looked at
+ */
+struct Context<'a> {
+    envelope: &'a PacketEnvelope,
+    packet: OnceCell<serde_json::Value>,
+    utc_offset: i64,
+}
+impl<'a> Context<'a> {
+    fn eval(&self, expr: &Expr) -> bool {
+        match expr {
+            Expr::And(a, b) => self.eval(a) && self.eval(b),
+            Expr::Or(a, b) => self.eval(a) || self.eval(b),
+            Expr::Not(a) => self.eval(a) == false,
+            Expr::IsType(name) => self.envelope.packet.type_name() == name,
+            Expr::Exists(field) => self.value(field).is_some(),
+            Expr::Compare(field, op, literal) => match self.value(field) {
+                Some(value) => self.compare(&value, *op, literal),
+                None => false
+            },
+        }
+    }
+
+    fn value(&self, field: &Field) -> Option<Value> {
+        let e = self.envelope;
+        let value = match field {
+            Field::Envelope(EnvelopeField::Type) => Value::Str(e.packet.type_name().to_string()),
+            Field::Envelope(EnvelopeField::TypeNum) => Value::Number(e.packet.type_num() as f64),
+            Field::Envelope(EnvelopeField::Index) => Value::Number(e.index as f64),
+            Field::Envelope(EnvelopeField::Time) => Value::Time(e.timestamp),
+            Field::Envelope(EnvelopeField::Timestamp) => Value::Number(e.timestamp as f64),
+            Field::Envelope(EnvelopeField::Frame) => Value::Number(e.frame as f64),
+            Field::Envelope(EnvelopeField::Source) => Value::Str(e.connection.source.to_string()),
+            Field::Envelope(EnvelopeField::Destination) => Value::Str(e.connection.destination.to_string()),
+            Field::Envelope(EnvelopeField::Alignment) => Value::Str(format!("{:?}", e.alignment)),
+            Field::Envelope(EnvelopeField::Len) => Value::Number(e.encrypted_len as f64),
+            Field::Envelope(EnvelopeField::TcpSeq) => Value::Number(e.tcp_seq as f64),
+            Field::Packet { packet_type, path } => {
+                if let Some(t) = packet_type {
+                    if e.packet.type_name() != t {
+                        return None
+                    }
+                }
+                //packets are serialized as {"TypeName": {fields}}
+                let packet = self.packet.get_or_init(|| serde_json::to_value(&e.packet).unwrap_or_default());
+                let mut value = packet.as_object()?.values().next()?;
+                for key in path {
+                    value = value.get(key)?;
+                }
+                match value {
+                    serde_json::Value::Number(n) => Value::Number(n.as_f64()?),
+                    serde_json::Value::String(s) => Value::Str(s.clone()),
+                    serde_json::Value::Bool(b) => Value::Bool(*b),
+                    serde_json::Value::Null => return None,
+                    other => Value::Str(other.to_string()),
+                }
+            }
+        };
+        Some(value)
+    }
+
+    fn compare(&self, value: &Value, op: CompareOp, literal: &Literal) -> bool {
+        match (value, literal) {
+            (Value::Time(t), Literal::Time(time_of_day)) => {
+                let local = (t + self.utc_offset).rem_euclid(MICROS_PER_DAY);
+                compare_ord(local.cmp(time_of_day), op)
+            },
+            //plain numbers are compared with times as seconds since the unix epoch
+            (Value::Time(t), Literal::Number(n)) => compare_numbers(*t as f64 / MICROS_PER_SECOND, *n, op),
+            (Value::Number(a), Literal::Number(b)) => compare_numbers(*a, *b, op),
+            (Value::Number(a), Literal::Str(b)) => match b.parse::<f64>() {
+                Ok(b) => compare_numbers(*a, b, op),
+                Err(_) => compare_strings(&a.to_string(), b, op),
+            },
+            (Value::Str(a), Literal::Str(b)) => compare_strings(a, b, op),
+            (Value::Str(a), Literal::Number(b)) => match a.parse::<f64>() {
+                Ok(a) => compare_numbers(a, *b, op),
+                Err(_) => compare_strings(a, &b.to_string(), op),
+            },
+            (Value::Bool(a), Literal::Bool(b)) => compare_ord(a.cmp(b), op),
+            (Value::Bool(a), Literal::Number(b)) => compare_numbers(*a as u8 as f64, *b, op),
+            _ => false
+        }
+    }
+}
+
+fn compare_ord(ordering: std::cmp::Ordering, op: CompareOp) -> bool {
+    use std::cmp::Ordering::*;
+    match op {
+        CompareOp::Eq => ordering == Equal,
+        CompareOp::Ne => ordering != Equal,
+        CompareOp::Gt => ordering == Greater,
+        CompareOp::Ge => ordering != Less,
+        CompareOp::Lt => ordering == Less,
+        CompareOp::Le => ordering != Greater,
+        CompareOp::Contains => false,
+    }
+}
+
+fn compare_numbers(a: f64, b: f64, op: CompareOp) -> bool {
+    match a.partial_cmp(&b) {
+        Some(ordering) => compare_ord(ordering, op),
+        None => op == CompareOp::Ne,
+    }
+}
+
+fn compare_strings(a: &str, b: &str, op: CompareOp) -> bool {
+    match op {
+        CompareOp::Contains => a.contains(b),
+        _ => compare_ord(a.cmp(b), op),
+    }
+}
+
+
+/**
+ * Split a filter into tokens, each with the character position it starts at
+ */
+fn tokenize(text: &str) -> Result<Vec<(Token, usize)>, FilterError> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        if c.is_whitespace() {
+            i += 1;
+            continue
+        }
+
+        if c == '"' {
+            let mut s = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => return Err(FilterError { message: "Unterminated string".to_string(), position: start }),
+                    Some('"') => break,
+                    Some('\\') => {
+                        i += 1;
+                        match chars.get(i) {
+                            Some(escaped) => s.push(*escaped),
+                            None => return Err(FilterError { message: "Unterminated string".to_string(), position: start }),
+                        }
+                    },
+                    Some(other) => s.push(*other),
+                }
+                i += 1;
+            }
+            i += 1;
+            tokens.push((Token::Str(s), start));
+        } else if c.is_ascii_digit() || (c == '-' && chars.get(i + 1).map(|n| n.is_ascii_digit()).unwrap_or(false)) {
+            i += 1;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.' || chars[i] == ':') {
+                i += 1;
+            }
+            let literal: String = chars[start..i].iter().collect();
+            let token = if literal.contains(':') {
+                Token::Time(parse_time_of_day(&literal).ok_or(FilterError { message: format!("Invalid time {}", literal), position: start })?)
+            } else {
+                Token::Number(literal.parse().map_err(|_| FilterError { message: format!("Invalid number {}", literal), position: start })?)
+            };
+            tokens.push((token, start));
+        } else if c.is_alphabetic() || c == '_' {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '.') {
+                i += 1;
+            }
+            tokens.push((Token::Ident(chars[start..i].iter().collect()), start));
+        } else {
+            let two: String = chars[i..(i + 2).min(chars.len())].iter().collect();
+            let op = ["==", "!=", ">=", "<=", "&&", "||"].into_iter().find(|op| two == *op)
+                .or(["!", ">", "<", "(", ")"].into_iter().find(|op| op.starts_with(c)))
+                .ok_or(FilterError { message: format!("Unexpected character {}", c), position: start })?;
+            i += op.len();
+            tokens.push((Token::Op(op), start));
+        }
+    }
+    Ok(tokens)
+}
+
+/**
+ * Parse hh:mm or hh:mm:ss with optional fractional seconds into microseconds since midnight
+ */
+fn parse_time_of_day(text: &str) -> Option<i64> {
+    let parts: Vec<&str> = text.split(':').collect();
+    if parts.len() < 2 || parts.len() > 3 {
+        return None
+    }
+    let hours: i64 = parts[0].parse().ok()?;
+    let minutes: i64 = parts[1].parse().ok()?;
+    let seconds: f64 = match parts.get(2) {
+        Some(s) => s.parse().ok()?,
+        None => 0.0,
+    };
+    if hours > 23 || minutes > 59 || seconds < 0.0 || seconds >= 60.0 {
+        return None
+    }
+    Some((hours * 3600 + minutes * 60) * 1_000_000 + (seconds * MICROS_PER_SECOND) as i64)
+}
+
+
+/**
+ * Recursive descent parser, from lowest to highest precedence: or, and, not, comparisons
+ */
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+    /// Character length of the filter, for errors at the end of it
+    end: usize,
+}
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|t| &t.0)
+    }
+
+    fn error(&self, message: &str) -> FilterError {
+        let position = self.tokens.get(self.position).map(|t| t.1).unwrap_or(self.end);
+        FilterError { message: message.to_string(), position }
+    }
+
+    /**
+     * Consume the next token if it is the operator or keyword
+     */
+    fn accept(&mut self, op: &str, keyword: &str) -> bool {
+        let found = match self.peek() {
+            Some(Token::Op(o)) => *o == op,
+            Some(Token::Ident(i)) => i.eq_ignore_ascii_case(keyword),
+            _ => false
+        };
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, FilterError> {
+        let mut expr = self.parse_and()?;
+        while self.accept("||", "or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, FilterError> {
+        let mut expr = self.parse_not()?;
+        while self.accept("&&", "and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_not()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, FilterError> {
+        if self.accept("!", "not") {
+            return Ok(Expr::Not(Box::new(self.parse_not()?)))
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, FilterError> {
+        if self.accept("(", "(") {
+            let expr = self.parse_or()?;
+            if self.accept(")", ")") == false {
+                return Err(self.error("Expected )"))
+            }
+            return Ok(expr)
+        }
+
+        let field = match self.peek() {
+            Some(Token::Ident(name)) => parse_field(name),
+            _ => return Err(self.error("Expected a field"))
+        };
+        self.position += 1;
+
+        let op = match self.peek() {
+            Some(Token::Op("==")) => CompareOp::Eq,
+            Some(Token::Op("!=")) => CompareOp::Ne,
+            Some(Token::Op(">")) => CompareOp::Gt,
+            Some(Token::Op(">=")) => CompareOp::Ge,
+            Some(Token::Op("<")) => CompareOp::Lt,
+            Some(Token::Op("<=")) => CompareOp::Le,
+            Some(Token::Ident(i)) => match i.to_lowercase().as_str() {
+                "eq" => CompareOp::Eq,
+                "ne" => CompareOp::Ne,
+                "gt" => CompareOp::Gt,
+                "ge" => CompareOp::Ge,
+                "lt" => CompareOp::Lt,
+                "le" => CompareOp::Le,
+                "contains" => CompareOp::Contains,
+                _ => return Ok(bare_field(field))
+            },
+            _ => return Ok(bare_field(field))
+        };
+        self.position += 1;
+
+        let literal = match self.peek() {
+            Some(Token::Number(n)) => Literal::Number(*n),
+            Some(Token::Str(s)) => Literal::Str(s.clone()),
+            Some(Token::Time(t)) => Literal::Time(*t),
+            Some(Token::Ident(i)) if i == "true" => Literal::Bool(true),
+            Some(Token::Ident(i)) if i == "false" => Literal::Bool(false),
+            //bare words are strings, like type == Damage
+            Some(Token::Ident(i)) => Literal::Str(i.clone()),
+            _ => return Err(self.error("Expected a value"))
+        };
+        self.position += 1;
+        Ok(Expr::Compare(field, op, literal))
+    }
+}
+
+/**
+ * A field without a comparison checks that it is present, or the packet type if it names one
+ */
+fn bare_field(field: Field) -> Expr {
+    match field {
+        Field::Packet { packet_type: Some(t), path } if path.is_empty() => Expr::IsType(t),
+        field => Expr::Exists(field),
+    }
+}
+
+/**
+ * Envelope fields are matched first, then names starting with a capital are taken as packet types
+ */
+fn parse_field(name: &str) -> Field {
+    let envelope = match name {
+        "type" => Some(EnvelopeField::Type),
+        "type_num" => Some(EnvelopeField::TypeNum),
+        "index" => Some(EnvelopeField::Index),
+        "time" => Some(EnvelopeField::Time),
+        "timestamp" => Some(EnvelopeField::Timestamp),
+        "frame" => Some(EnvelopeField::Frame),
+        "source" => Some(EnvelopeField::Source),
+        "destination" => Some(EnvelopeField::Destination),
+        "alignment" => Some(EnvelopeField::Alignment),
+        "len" => Some(EnvelopeField::Len),
+        "tcp_seq" => Some(EnvelopeField::TcpSeq),
+        _ => None
+    };
+    if let Some(e) = envelope {
+        return Field::Envelope(e)
+    }
+
+    let mut path: Vec<String> = name.split('.').filter(|p| p.is_empty() == false).map(|p| p.to_string()).collect();
+    let packet_type = match path.first() {
+        Some(first) if first.starts_with(|c: char| c.is_uppercase()) => Some(path.remove(0)),
+        _ => None
+    };
+    Field::Packet { packet_type, path }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_factory::test_stream::envelope;
+
+    //2022-01-08 11:00:00 utc
+    const ELEVEN_UTC: i64 = 1_641_639_600_000_000;
+
+    fn damage(damage_amount: u16) -> PacketEnvelope {
+        let [high, low] = damage_amount.to_be_bytes();
+        envelope(0, 75, vec![0, 0, 0, 7, 2, 0xaa, 0xbb, high, low, 1, 0, 3, 0, 0, 0, 9], ELEVEN_UTC)
+    }
+
+    fn string(s: &str) -> Vec<u8> {
+        [(s.len() as u16).to_be_bytes().to_vec(), s.as_bytes().to_vec()].concat()
+    }
+
+    fn text(recipient: &str, content: &str, timestamp: i64) -> PacketEnvelope {
+        let body = [
+            string("Oryx"),
+            vec![0, 0, 0, 1, 0, 5, 0],
+            string(recipient),
+            string(content),
+            string(content),
+            vec![0, 0, 0, 0, 0],
+        ].concat();
+        envelope(0, 44, body, timestamp)
+    }
+
+    fn matches(filter: &str, envelope: &PacketEnvelope) -> bool {
+        DisplayFilter::parse(filter).unwrap().matches(envelope)
+    }
+
+    #[test]
+    fn compares_envelope_and_packet_fields() {
+        let filter = "type == Damage && damage_amount > 500";
+        assert!(matches(filter, &damage(600)));
+        assert!(matches(filter, &damage(500)) == false);
+        assert!(matches(filter, &text("", "hi", ELEVEN_UTC)) == false);
+    }
+
+    #[test]
+    fn compares_qualified_fields_and_times_of_day() {
+        let filter = "Text.recipient != \"\" && time > 10:00";
+        assert!(matches(filter, &text("Player", "hi", ELEVEN_UTC)));
+        assert!(matches(filter, &text("", "hi", ELEVEN_UTC)) == false);
+        //09:00 utc
+        assert!(matches(filter, &text("Player", "hi", ELEVEN_UTC - 7_200_000_000)) == false);
+        //the same packet is after 10:00 two hours east of utc
+        let filter = DisplayFilter::parse(filter).unwrap().with_utc_offset(120);
+        assert!(filter.matches(&text("Player", "hi", ELEVEN_UTC - 7_200_000_000)));
+        //fields of other packet types never match
+        assert!(matches("Damage.damage_amount > 0", &text("Player", "hi", ELEVEN_UTC)) == false);
+    }
+
+    #[test]
+    fn matches_bare_type_names() {
+        assert!(matches("Damage", &damage(1)));
+        assert!(matches("Damage", &text("", "hi", ELEVEN_UTC)) == false);
+        assert!(matches("Text || Damage", &text("", "hi", ELEVEN_UTC)));
+        assert!(matches("!Damage", &text("", "hi", ELEVEN_UTC)));
+    }
+
+    #[test]
+    fn matches_contains() {
+        assert!(matches("content contains \"Realm\"", &text("", "Realm closed", ELEVEN_UTC)));
+        assert!(matches("content contains \"realm\"", &text("", "Realm closed", ELEVEN_UTC)) == false);
+        assert!(matches("content contains \"Realm\"", &damage(1)) == false);
+    }
+
+    #[test]
+    fn reports_error_positions() {
+        let error = |filter: &str| DisplayFilter::parse(filter).unwrap_err().position;
+        assert_eq!(error("type =="), 7);
+        assert_eq!(error("type == Damage )"), 15);
+        assert_eq!(error("name == \"Oryx"), 8);
+        assert_eq!(error("time > 25:00"), 7);
+        assert_eq!(error("len > 5 $"), 8);
+    }
+}
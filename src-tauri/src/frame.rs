@@ -0,0 +1,216 @@
+use std::net::IpAddr;
+use etherparse::{InternetSlice, SlicedPacket, TransportSlice};
+use crate::packet_factory::packet_envelope::{Connection, FrameMeta};
+use crate::settings::CaptureSettings;
+
+
+/// Default port the game servers send from
+pub const GAME_PORT: u16 = 2050;
+
+/// Link types as written in capture files, see https://www.tcpdump.org/linktypes.html
+pub const LINKTYPE_NULL: u32 = 0;
+pub const LINKTYPE_ETHERNET: u32 = 1;
+pub const LINKTYPE_RAW: u32 = 101;
+pub const LINKTYPE_LOOP: u32 = 108;
+pub const LINKTYPE_LINUX_SLL: u32 = 113;
+pub const LINKTYPE_IPV4: u32 = 228;
+pub const LINKTYPE_IPV6: u32 = 229;
+pub const LINKTYPE_LINUX_SLL2: u32 = 276;
+/// Link types reserved for private use, wireshark lets them be assigned to any dissector
+pub const LINKTYPE_USER0: u32 = 147;
+pub const LINKTYPE_USER15: u32 = 162;
+
+const IPV6_HEADER_LEN: usize = 40;
+
+/// libpcap reports raw ip captures with a platform specific DLT instead of LINKTYPE_RAW
+const DLT_RAW: i32 = 12;
+const DLT_RAW_OPENBSD: i32 = 14;
+
+
+/**
+ * Convert a datalink type reported by libpcap to the link type written in capture files
+ */
+pub fn linktype_from_dlt(dlt: i32) -> u32 {
+    match dlt {
+        DLT_RAW | DLT_RAW_OPENBSD => LINKTYPE_RAW,
+        _ => dlt as u32
+    }
+}
+
+
+enum SliceError {
+    UnsupportedLinkType,
+    Malformed(String),
+}
+
+
+/**
+ * Convert a capture file link type to the datalink type libpcap expects
+ */
+pub fn dlt_from_linktype(linktype: u32) -> i32 {
+    match linktype {
+        LINKTYPE_RAW if cfg!(target_os = "openbsd") => DLT_RAW_OPENBSD,
+        LINKTYPE_RAW => DLT_RAW,
+        _ => linktype as i32
+    }
+}
+
+
+/**
+ * Slices the link layer header off a captured frame according to the capture's link type.
+ * Returns an error for link types that can't be decoded.
+ */
+fn slice_link_layer(linktype: u32, data: &[u8]) -> Result<SlicedPacket<'_>, SliceError> {
+    let result = match linktype {
+        LINKTYPE_ETHERNET => SlicedPacket::from_ethernet(data),
+        //Linux cooked capture, the protocol type is the last field of the 16 byte header
+        LINKTYPE_LINUX_SLL => {
+            if data.len() < 16 {
+                return Err(SliceError::Malformed("Truncated linux cooked header".to_string()))
+            }
+            SlicedPacket::from_ether_type(u16::from_be_bytes([data[14], data[15]]), &data[16..])
+        },
+        //Linux cooked capture v2, the protocol type is the first field of the 20 byte header
+        LINKTYPE_LINUX_SLL2 => {
+            if data.len() < 20 {
+                return Err(SliceError::Malformed("Truncated linux cooked v2 header".to_string()))
+            }
+            SlicedPacket::from_ether_type(u16::from_be_bytes([data[0], data[1]]), &data[20..])
+        },
+        //Loopback headers hold an address family whose value and byte order depend on the capturing os,
+        //so the ip version is taken from the ip header instead
+        LINKTYPE_NULL | LINKTYPE_LOOP => {
+            if data.len() < 4 {
+                return Err(SliceError::Malformed("Truncated loopback header".to_string()))
+            }
+            SlicedPacket::from_ip(&data[4..])
+        },
+        LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => SlicedPacket::from_ip(data),
+        _ => return Err(SliceError::UnsupportedLinkType)
+    };
+    result.map_err(|e| SliceError::Malformed(e.to_string()))
+}
+
+
+/**
+ * Why a captured frame was not handed to the packet factory
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    /// The frame's link type can't be decoded
+    UnsupportedLinkType,
+    /// The frame's headers are truncated or invalid
+    Malformed,
+    /// The frame isn't a tcp segment over ipv4 or ipv6
+    NotTcp,
+    /// The segment isn't from a game port
+    NotGameTraffic,
+    /// The segment is from a server that isn't in the allowlist
+    NotAllowedServer,
+    /// The segment carries no data, like a bare ack
+    Empty,
+}
+
+
+/**
+ * Number of frames seen in a capture and what happened to them
+ */
+#[derive(Debug, Default, Clone, Copy, serde::Serialize, serde::Deserialize)]
+pub struct FrameCounts {
+    pub frames: usize,
+    /// Frames handed to the packet factory
+    pub accepted: usize,
+    pub unsupported_link_type: usize,
+    pub malformed: usize,
+    pub not_tcp: usize,
+    pub not_game_traffic: usize,
+    pub not_allowed_server: usize,
+    pub empty: usize,
+}
+impl FrameCounts {
+    pub fn record<T>(&mut self, result: &Result<T, SkipReason>) {
+        self.frames += 1;
+        match result {
+            Ok(_) => self.accepted += 1,
+            Err(SkipReason::UnsupportedLinkType) => self.unsupported_link_type += 1,
+            Err(SkipReason::Malformed) => self.malformed += 1,
+            Err(SkipReason::NotTcp) => self.not_tcp += 1,
+            Err(SkipReason::NotGameTraffic) => self.not_game_traffic += 1,
+            Err(SkipReason::NotAllowedServer) => self.not_allowed_server += 1,
+            Err(SkipReason::Empty) => self.empty += 1,
+        }
+    }
+}
+
+
+/**
+ * BPF filter selecting game traffic for a capture with the given link type, unless the settings override it.
+ * Ipv6 packets with extension headers can't be matched on their tcp port by BPF, so they are all let through and filtered by slice_frame.
+ * The server allowlist is left to slice_frame as well, since it can grow while capturing.
+ */
+pub fn capture_filter(linktype: u32, settings: &CaptureSettings) -> String {
+    if let Some(filter) = settings.bpf_override.as_ref().filter(|f| f.trim().is_empty() == false) {
+        return filter.clone()
+    }
+
+    let ports = settings.game_ports.iter().map(|p| format!("src port {}", p)).collect::<Vec<_>>().join(" or ");
+    let game_traffic = format!("(tcp and ({})) or (ip6 and not (ip6 proto 6 or ip6 proto 17 or ip6 proto 58))", ports);
+    //vlan tags can only be matched on ethernet captures, libpcap rejects the filter for other link types
+    if linktype == LINKTYPE_ETHERNET {
+        return format!("{} or (vlan and ({}))", game_traffic, game_traffic)
+    }
+    game_traffic
+}
+
+
+/**
+ * Slices a captured frame and collects the capture information the packet factory needs.
+ * Vlan tags and ipv6 extension headers are skipped over, and the payload is trimmed to the length given by the ip header
+ * so ethernet padding isn't mistaken for stream data.
+ * Returns why the frame was skipped for anything that isn't a tcp segment with a payload sent from a game port of an allowed server.
+ *
+ * linktype is the capture file link type of the frame, timestamp is in microseconds since the unix epoch
+ * and frame is the number of the frame in the capture.
+ */
+pub fn slice_frame<'a>(linktype: u32, timestamp: i64, frame: usize, data: &'a [u8], settings: &CaptureSettings) -> Result<(SlicedPacket<'a>, FrameMeta), SkipReason> {
+    let mut s = match slice_link_layer(linktype, data) {
+        Ok(s) => s,
+        Err(SliceError::UnsupportedLinkType) => return Err(SkipReason::UnsupportedLinkType),
+        Err(SliceError::Malformed(e)) => {
+            log::debug!("Packet data error: {}", e);
+            return Err(SkipReason::Malformed)
+        }
+    };
+
+    let (ip_header, ip_len, connection, tcp_seq) = match (s.clone().ip, s.clone().transport) {
+        (Some(InternetSlice::Ipv4(ip_h, _)), Some(TransportSlice::Tcp(tcp_h))) => (
+            ip_h.slice(),
+            ip_h.total_len() as usize,
+            Connection::new(IpAddr::V4(ip_h.source_addr()), tcp_h.source_port(), IpAddr::V4(ip_h.destination_addr()), tcp_h.destination_port()),
+            tcp_h.sequence_number()
+        ),
+        (Some(InternetSlice::Ipv6(ip_h, _)), Some(TransportSlice::Tcp(tcp_h))) => (
+            ip_h.slice(),
+            IPV6_HEADER_LEN + ip_h.payload_length() as usize,
+            Connection::new(IpAddr::V6(ip_h.source_addr()), tcp_h.source_port(), IpAddr::V6(ip_h.destination_addr()), tcp_h.destination_port()),
+            tcp_h.sequence_number()
+        ),
+        _ => return Err(SkipReason::NotTcp)
+    };
+    if settings.is_game_port(connection.source.port()) == false {
+        return Err(SkipReason::NotGameTraffic)
+    }
+    if settings.is_allowed_server(&connection.source.ip()) == false {
+        return Err(SkipReason::NotAllowedServer)
+    }
+
+    //everything between the start of the ip header and the tcp payload is headers, whatever follows the ip length is link layer padding
+    let header_len = s.payload.as_ptr() as usize - ip_header.as_ptr() as usize;
+    let payload_len = ip_len.saturating_sub(header_len).min(s.payload.len());
+    s.payload = &s.payload[..payload_len];
+    if payload_len == 0 {
+        return Err(SkipReason::Empty)
+    }
+
+    Ok((s, FrameMeta { timestamp, connection, tcp_seq, frame }))
+}
@@ -0,0 +1,79 @@
+use crate::frame::{FrameCounts, SkipReason};
+use crate::packet_factory::FactoryStats;
+use crate::packet_factory::packet_envelope::CipherEvent;
+use crate::packet_source::SourceStats;
+
+
+/**
+ * Snapshot of how well a capture is going, returned by get_capture_health and sent to the ui as "capture-health" events
+ */
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct CaptureHealth {
+    pub frames: FrameCounts,
+    /// Bytes on the wire of every frame seen
+    pub bytes: u64,
+    /// Frame counts from libpcap, only available for live captures
+    pub source: Option<SourceStats>,
+    pub factory: FactoryStats,
+    /// Times the cipher was aligned again after losing alignment
+    pub realignments: usize,
+    /// Capture time spent waiting to realign the cipher, in microseconds
+    pub misaligned_time: i64,
+    /// Capture time the cipher was first aligned at, in microseconds since the unix epoch
+    pub first_aligned: Option<i64>,
+    /// Capture time the cipher lost its alignment at if it is currently misaligned
+    pub misaligned_since: Option<i64>,
+}
+
+
+/**
+ * Collects the parts of the capture health that aren't counted by the packet factory
+ */
+#[derive(Debug, Clone, Default)]
+pub struct HealthTracker {
+    health: CaptureHealth,
+}
+impl HealthTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /**
+     * Count a frame and whether it was handed to the packet factory
+     */
+    pub fn record_frame<T>(&mut self, original_len: u32, result: &Result<T, SkipReason>) {
+        self.health.frames.record(result);
+        self.health.bytes += original_len as u64;
+    }
+
+    /**
+     * Track alignment changes, timed with the capture time of the frame that caused them
+     */
+    pub fn record_event(&mut self, event: CipherEvent, timestamp: i64) {
+        let health = &mut self.health;
+        match event {
+            CipherEvent::Misaligned => {
+                health.misaligned_since = health.misaligned_since.or(Some(timestamp));
+            },
+            CipherEvent::Aligned => {
+                if let Some(since) = health.misaligned_since.take() {
+                    health.realignments += 1;
+                    health.misaligned_time += (timestamp - since).max(0);
+                }
+                health.first_aligned = health.first_aligned.or(Some(timestamp));
+            },
+        }
+    }
+
+    pub fn record_source_stats(&mut self, stats: SourceStats) {
+        self.health.source = Some(stats);
+    }
+
+    pub fn frame_counts(&self) -> FrameCounts {
+        self.health.frames
+    }
+
+    pub fn snapshot(&self, factory: FactoryStats) -> CaptureHealth {
+        CaptureHealth { factory, ..self.health.clone() }
+    }
+}
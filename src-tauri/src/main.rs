@@ -4,68 +4,455 @@
 
 use std::sync::{Mutex, Arc};
 
-use packet_factory::rotmg_packet::RotmgPacket;
-use tauri::Window;
+use tauri::{Window, Manager};
 
 use crate::sniffer::Sniffer;
+use crate::session::{PacketQuery, PacketPage, SessionConfig};
+use crate::packet_stream::StreamConfig;
+use crate::recorder::{RecorderConfig, RecordingInfo};
+use crate::capture_file::annotate::AnnotationSummary;
+use crate::frame::FrameCounts;
+use crate::settings::CaptureSettings;
+use crate::devices::{DeviceInfo, DetectionResult};
+use crate::replay::ReplayStatus;
+use crate::health::CaptureHealth;
+use crate::session_store::SessionInfo;
+use crate::session_file::{SessionFileInfo, SessionFileReader};
+use crate::export::{ExportFormat, ExportSummary};
+use crate::packet_factory::field_map::AnnotatedPacket;
+use crate::decode::{PayloadEncoding, PayloadCipher};
+use crate::error::Error;
 
 mod rc4;
+mod capture_file;
+mod frame;
 mod packet_factory;
+mod session;
+mod packet_stream;
+mod recorder;
+mod settings;
+mod devices;
+mod cli;
+mod packet_source;
+mod replay;
+mod health;
+mod filter;
+mod session_store;
+mod session_file;
+mod export;
+mod decode;
+mod error;
 mod sniffer;
 
 // Learn more about Tauri commands at https://tauri.app/v1/guides/features/command
 
 #[tauri::command]
-fn start_collection(sniffer: tauri::State<Arc<Mutex<Sniffer>>>, window: Window) {
+fn start_collection(sniffer: tauri::State<Arc<Mutex<Sniffer>>>, window: Window) -> error::Result<()> {
     //log::debug!("Starting collection"); 
-    sniffer.lock().unwrap().start(window);
+    sniffer.lock().unwrap().start(window)
 }
 
 #[tauri::command]
-fn start_pcap(sniffer: tauri::State<Arc<Mutex<Sniffer>>>, window: Window, file_path: String) {
-    sniffer.lock().unwrap().start_using_pcap_file(window, file_path.clone());
+fn start_pcap(sniffer: tauri::State<Arc<Mutex<Sniffer>>>, window: Window, file_path: String) -> error::Result<()> {
+    sniffer.lock().unwrap().start_using_pcap_file(window, file_path.clone())
     //log::debug!("{}", file_path);
 }
 
+/**
+ * Replay a capture file following its timestamps, speed is a multiple of the captured speed or 0 for as fast as possible
+ */
+#[tauri::command]
+fn start_replay(sniffer: tauri::State<Arc<Mutex<Sniffer>>>, window: Window, file_path: String, speed: f64) -> error::Result<()> {
+    sniffer.lock().unwrap().start_replay(window, file_path, speed)
+}
+
+/**
+ * Control the running replay, failing if the current capture isn't a replay
+ */
+fn with_replay<T>(sniffer: &tauri::State<Arc<Mutex<Sniffer>>>, f: impl FnOnce(&replay::ReplayControl) -> T) -> error::Result<T> {
+    match sniffer.lock().unwrap().replay() {
+        Some(control) => Ok(f(control)),
+        None => Err(Error::NoReplay),
+    }
+}
+
+#[tauri::command]
+fn pause_replay(sniffer: tauri::State<Arc<Mutex<Sniffer>>>) -> error::Result<()> {
+    with_replay(&sniffer, |r| r.set_paused(true))
+}
+
+#[tauri::command]
+fn resume_replay(sniffer: tauri::State<Arc<Mutex<Sniffer>>>) -> error::Result<()> {
+    with_replay(&sniffer, |r| r.set_paused(false))
+}
+
+#[tauri::command]
+fn set_replay_speed(sniffer: tauri::State<Arc<Mutex<Sniffer>>>, speed: f64) -> error::Result<()> {
+    with_replay(&sniffer, |r| r.set_speed(speed))
+}
+
+/**
+ * Seek to a capture timestamp in microseconds since the unix epoch
+ */
+#[tauri::command]
+fn seek_replay(sniffer: tauri::State<Arc<Mutex<Sniffer>>>, timestamp: i64) -> error::Result<()> {
+    with_replay(&sniffer, |r| r.seek(timestamp))
+}
+
+#[tauri::command]
+fn get_replay_status(sniffer: tauri::State<Arc<Mutex<Sniffer>>>) -> error::Result<ReplayStatus> {
+    with_replay(&sniffer, |r| r.status())
+}
+
+/**
+ * Read a capture stream from a named pipe, or from stdin if no path is given
+ */
+#[tauri::command]
+fn start_pipe(sniffer: tauri::State<Arc<Mutex<Sniffer>>>, window: Window, path: Option<String>) -> error::Result<()> {
+    let path = path.unwrap_or(capture_file::STDIN_PATH.to_string());
+    sniffer.lock().unwrap().start_using_stream(window, path)
+}
+
 #[tauri::command]
 fn stop_collection(sniffer: tauri::State<Arc<Mutex<Sniffer>>>) {
     //log::debug!("Stopping collection");
     sniffer.lock().unwrap().stop(); 
 }
 
+/**
+ * Keep following the capture without storing packets, so it can be resumed without realigning the cipher
+ */
 #[tauri::command]
-fn get_packets(sniffer: tauri::State<Arc<Mutex<Sniffer>>>) -> Vec<RotmgPacket> {
-    //log::debug!("Fetching packets");
-    let p = sniffer.lock().unwrap().get_all_packets();
-    //log::debug!("{:?}", p);
-    return p
+fn pause_collection(sniffer: tauri::State<Arc<Mutex<Sniffer>>>) -> error::Result<()> {
+    sniffer.lock().unwrap().pause()
 }
 
 #[tauri::command]
-fn get_devices() -> Vec<String> {
-    return pcap::Device::list().expect("device list failed").iter().map(|d| d.desc.clone().unwrap_or("error".to_string())).collect();
+fn resume_collection(sniffer: tauri::State<Arc<Mutex<Sniffer>>>) -> error::Result<()> {
+    sniffer.lock().unwrap().resume()
 }
+
+/**
+ * Clear the stored packets, a running capture continues into the new session
+ */
 #[tauri::command]
-fn use_device(sniffer: tauri::State<Arc<Mutex<Sniffer>>>, device_string: String) -> Result<(), ()> {
-    match pcap::Device::list().expect("Device list failed").iter().find(|d| d.desc == Some(device_string.clone())) {
-        None => return Err(()),
-        Some(d) => sniffer.lock().unwrap().set_device(d),
+fn new_session(sniffer: tauri::State<Arc<Mutex<Sniffer>>>, window: Window) -> error::Result<()> {
+    sniffer.lock().unwrap().new_session(&window)
+}
+
+/**
+ * Every session saved to the session database, newest first
+ */
+#[tauri::command]
+fn list_sessions(sniffer: tauri::State<Arc<Mutex<Sniffer>>>) -> error::Result<Vec<SessionInfo>> {
+    sniffer.lock().unwrap().list_sessions()
+}
+
+/**
+ * Load a saved session in place of the current packets, fetched afterwards with fetch_packets
+ */
+#[tauri::command]
+async fn open_session(sniffer: tauri::State<'_, Arc<Mutex<Sniffer>>>, id: i64) -> error::Result<SessionInfo> {
+    //the current session is only replaced once the saved one has been read
+    let (mut session, store) = {
+        let sniffer = sniffer.lock().unwrap();
+        (sniffer.session_for_loading()?, sniffer.session_store())
+    };
+    let info = store.load(id, |p| session.push(p))?;
+    sniffer.lock().unwrap().replace_session(session)?;
+    Ok(info)
+}
+
+/**
+ * Save the current session to a session file that can be loaded again or shared
+ */
+#[tauri::command]
+async fn save_session_file(sniffer: tauri::State<'_, Arc<Mutex<Sniffer>>>, path: String) -> error::Result<usize> {
+    let (packets, versions) = {
+        let sniffer = sniffer.lock().unwrap();
+        (sniffer.session_pages(), sniffer.session_pages())
+    };
+    Ok(session_file::save(std::path::Path::new(&path), packets, versions)?)
+}
+
+/**
+ * Load a session file in place of the current packets, fetched afterwards with fetch_packets
+ */
+#[tauri::command]
+async fn load_session_file(sniffer: tauri::State<'_, Arc<Mutex<Sniffer>>>, path: String) -> error::Result<SessionFileInfo> {
+    //the current session is only replaced once the whole file has been read
+    let mut session = sniffer.lock().unwrap().session_for_loading()?;
+    let mut reader = SessionFileReader::open(std::path::Path::new(&path))?;
+    reader.read_all(|p| session.push(p))?;
+    sniffer.lock().unwrap().replace_session(session)?;
+    Ok(reader.info().clone())
+}
+
+/**
+ * Export the packets of the current session matching the query.
+ * Csv exports write a table per packet type into the directory at path, other formats write a single file.
+ */
+#[tauri::command]
+async fn export_session(sniffer: tauri::State<'_, Arc<Mutex<Sniffer>>>, format: ExportFormat, path: String, query: Option<PacketQuery>) -> error::Result<ExportSummary> {
+    let matcher = query.unwrap_or_default().matcher()?;
+    let packets = sniffer.lock().unwrap().session_pages();
+    Ok(export::export(format, packets.filter(|p| matcher.matches(p)), std::path::Path::new(&path))?)
+}
+
+/**
+ * Export the packets of the current session matching the query as a pcapng of decrypted packets under a user link type,
+ * along with a lua dissector for wireshark. The link type defaults to the first user link type, 147.
+ */
+#[tauri::command]
+async fn export_decrypted_pcapng(sniffer: tauri::State<'_, Arc<Mutex<Sniffer>>>, path: String, linktype: Option<u32>, query: Option<PacketQuery>) -> error::Result<ExportSummary> {
+    let linktype = linktype.unwrap_or(frame::LINKTYPE_USER0);
+    let matcher = query.unwrap_or_default().matcher()?;
+    let (packets, settings) = {
+        let sniffer = sniffer.lock().unwrap();
+        (sniffer.session_pages(), sniffer.capture_settings())
+    };
+    Ok(export::wireshark::export_decrypted_pcapng(packets.filter(|p| matcher.matches(p)), std::path::Path::new(&path), linktype, &settings)?)
+}
+
+#[tauri::command]
+fn rename_session(sniffer: tauri::State<Arc<Mutex<Sniffer>>>, id: i64, name: String) -> error::Result<()> {
+    sniffer.lock().unwrap().rename_session(id, &name)
+}
+
+#[tauri::command]
+fn delete_session(sniffer: tauri::State<Arc<Mutex<Sniffer>>>, id: i64) -> error::Result<()> {
+    sniffer.lock().unwrap().delete_session(id)
+}
+
+/**
+ * Fetch up to limit packets matching the query, starting at the cursor.
+ * The UI keeps the returned next_cursor so it only pulls packets it hasn't seen.
+ */
+#[tauri::command]
+fn fetch_packets(sniffer: tauri::State<Arc<Mutex<Sniffer>>>, cursor: usize, limit: usize, query: Option<PacketQuery>) -> error::Result<PacketPage> {
+    sniffer.lock().unwrap().fetch_packets(cursor, limit, &query.unwrap_or_default())
+}
+
+/**
+ * The decrypted bytes of a packet in the current session along with the field each byte was decoded into
+ */
+#[tauri::command]
+fn inspect_packet(sniffer: tauri::State<Arc<Mutex<Sniffer>>>, index: usize) -> error::Result<AnnotatedPacket> {
+    sniffer.lock().unwrap().inspect_packet(index)
+}
+
+/**
+ * Decode a packet pasted as hex or base64, decrypting it first if a cipher is given
+ */
+#[tauri::command]
+fn decode_payload(text: String, encoding: Option<PayloadEncoding>, cipher: Option<PayloadCipher>) -> error::Result<AnnotatedPacket> {
+    decode::decode_payload(&text, encoding, cipher.as_ref())
+}
+
+/**
+ * Check that a display filter parses, without matching it against the session
+ */
+#[tauri::command]
+fn validate_filter(filter: String) -> error::Result<()> {
+    filter::DisplayFilter::parse(&filter)?;
+    Ok(())
+}
+
+#[tauri::command]
+fn get_packet_count(sniffer: tauri::State<Arc<Mutex<Sniffer>>>, query: Option<PacketQuery>) -> error::Result<usize> {
+    sniffer.lock().unwrap().packet_count(&query.unwrap_or_default())
+}
+
+#[tauri::command]
+fn get_session_config(sniffer: tauri::State<Arc<Mutex<Sniffer>>>) -> SessionConfig {
+    sniffer.lock().unwrap().session_config()
+}
+
+#[tauri::command]
+fn set_session_config(sniffer: tauri::State<Arc<Mutex<Sniffer>>>, config: SessionConfig) -> error::Result<()> {
+    sniffer.lock().unwrap().set_session_config(config)
+}
+
+/**
+ * Called by the ui once it has handled a "packets" event batch
+ */
+#[tauri::command]
+fn ack_packets(sniffer: tauri::State<Arc<Mutex<Sniffer>>>, batch_id: u64) {
+    sniffer.lock().unwrap().ack_packets(batch_id);
+}
+
+#[tauri::command]
+fn get_stream_config(sniffer: tauri::State<Arc<Mutex<Sniffer>>>) -> StreamConfig {
+    sniffer.lock().unwrap().stream_config()
+}
+
+#[tauri::command]
+fn set_stream_config(sniffer: tauri::State<Arc<Mutex<Sniffer>>>, config: StreamConfig) -> error::Result<()> {
+    sniffer.lock().unwrap().set_stream_config(config)
+}
+
+#[tauri::command]
+fn get_recorder_config(sniffer: tauri::State<Arc<Mutex<Sniffer>>>) -> RecorderConfig {
+    sniffer.lock().unwrap().recorder_config()
+}
+
+#[tauri::command]
+fn set_recorder_config(sniffer: tauri::State<Arc<Mutex<Sniffer>>>, config: RecorderConfig) {
+    sniffer.lock().unwrap().set_recorder_config(config);
+}
+
+/**
+ * Recordings of previous live captures, which can be replayed with start_pcap
+ */
+#[tauri::command]
+fn list_recordings(sniffer: tauri::State<Arc<Mutex<Sniffer>>>) -> error::Result<Vec<RecordingInfo>> {
+    let config = sniffer.lock().unwrap().recorder_config();
+    Ok(recorder::list_recordings(&config)?)
+}
+
+/**
+ * How many frames the current capture has seen and why any of them were skipped
+ */
+#[tauri::command]
+fn get_frame_counts(sniffer: tauri::State<Arc<Mutex<Sniffer>>>) -> FrameCounts {
+    sniffer.lock().unwrap().frame_counts()
+}
+
+/**
+ * Frame, drop, stream and cipher statistics for the current capture
+ */
+#[tauri::command]
+fn get_capture_health(sniffer: tauri::State<Arc<Mutex<Sniffer>>>) -> CaptureHealth {
+    sniffer.lock().unwrap().capture_health()
+}
+
+/**
+ * Decode a pcap or pcapng file and write a pcapng copy with the decoded packet types and cipher alignment changes as frame comments
+ */
+#[tauri::command]
+async fn export_annotated_pcapng(sniffer: tauri::State<'_, Arc<Mutex<Sniffer>>>, input_path: String, output_path: String) -> error::Result<AnnotationSummary> {
+    let settings = sniffer.lock().unwrap().capture_settings();
+    Ok(capture_file::annotate::export_annotated_pcapng(std::path::Path::new(&input_path), std::path::Path::new(&output_path), &settings)?)
+}
+
+#[tauri::command]
+fn get_capture_settings(sniffer: tauri::State<Arc<Mutex<Sniffer>>>) -> CaptureSettings {
+    sniffer.lock().unwrap().capture_settings()
+}
+
+/**
+ * Validate and save new capture settings
+ */
+#[tauri::command]
+fn set_capture_settings(sniffer: tauri::State<Arc<Mutex<Sniffer>>>, settings: CaptureSettings) -> error::Result<()> {
+    sniffer.lock().unwrap().set_capture_settings(settings)
+}
+
+#[tauri::command]
+fn get_devices() -> error::Result<Vec<DeviceInfo>> {
+    Ok(devices::list_devices()?)
+}
+#[tauri::command]
+fn use_device(sniffer: tauri::State<Arc<Mutex<Sniffer>>>, device_name: String) -> error::Result<()> {
+    match devices::find_device(&device_name)? {
+        None => return Err(Error::DeviceNotFound(device_name)),
+        Some(d) => sniffer.lock().unwrap().set_device(&d),
     }
     return Ok(())
 }
+/**
+ * Listen on every device for game traffic and use the busiest one.
+ * Returns None without changing the device if no game traffic was seen.
+ */
+#[tauri::command]
+async fn detect_device(sniffer: tauri::State<'_, Arc<Mutex<Sniffer>>>, duration_ms: Option<u64>) -> error::Result<Option<DetectionResult>> {
+    let settings = sniffer.lock().unwrap().capture_settings();
+    let duration = duration_ms.map(std::time::Duration::from_millis).unwrap_or(devices::DEFAULT_DETECTION_TIME);
+    let best = devices::detect_game_traffic(&settings, duration)?
+        .into_iter()
+        .next()
+        .filter(|r| r.game_frames > 0);
+    if let Some(r) = &best {
+        if let Some(d) = devices::find_device(&r.device.name)? {
+            sniffer.lock().unwrap().set_device(&d);
+        }
+    }
+    Ok(best)
+}
 
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(code) = cli::run(&args) {
+        std::process::exit(code);
+    }
+
     //let _ = simple_logging::log_to_file("log.log", log::LevelFilter::Debug);
     tauri::Builder::default()
         .manage(Arc::new(Mutex::new(Sniffer::new())))
         .plugin(tauri_plugin_log::Builder::default().build())
+        .setup(|app| {
+            //Keep recordings with the rest of the app data
+            if let Some(dir) = app.path_resolver().app_data_dir() {
+                let sniffer = app.state::<Arc<Mutex<Sniffer>>>();
+                let mut sniffer = sniffer.lock().unwrap();
+                let config = RecorderConfig { directory: dir.join("recordings"), ..sniffer.recorder_config() };
+                sniffer.set_recorder_config(config);
+                if let Err(e) = sniffer.open_session_store(&dir.join("sessions.sqlite")) {
+                    log::debug!("Error opening session database {:?}", e);
+                }
+            }
+            if let Some(dir) = app.path_resolver().app_config_dir() {
+                let sniffer = app.state::<Arc<Mutex<Sniffer>>>();
+                let result = sniffer.lock().unwrap().load_capture_settings(settings::settings_path(&dir));
+                if let Err(e) = result {
+                    log::debug!("Error loading capture settings {:?}", e);
+                }
+            }
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             start_collection,
             start_pcap,
+            start_pipe,
+            start_replay,
+            pause_replay,
+            resume_replay,
+            set_replay_speed,
+            seek_replay,
+            get_replay_status,
             stop_collection,
-            get_packets,
+            pause_collection,
+            resume_collection,
+            new_session,
+            list_sessions,
+            open_session,
+            rename_session,
+            delete_session,
+            save_session_file,
+            load_session_file,
+            export_session,
+            export_decrypted_pcapng,
+            fetch_packets,
+            get_packet_count,
+            validate_filter,
+            inspect_packet,
+            decode_payload,
+            get_session_config,
+            set_session_config,
+            ack_packets,
+            get_stream_config,
+            set_stream_config,
+            get_recorder_config,
+            set_recorder_config,
+            list_recordings,
+            export_annotated_pcapng,
+            get_frame_counts,
+            get_capture_health,
+            get_capture_settings,
+            set_capture_settings,
             get_devices,
-            use_device
+            use_device,
+            detect_device
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
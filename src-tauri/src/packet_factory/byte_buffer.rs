@@ -1,16 +1,61 @@
 #![allow(dead_code)]
 use byteorder::{BigEndian, ByteOrder};
+use super::field_map::FieldTrace;
 
 
 
-#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
 pub struct ByteBuffer {
     pub bytes: Vec<u8>,
     pub index: usize,
+    /// Records the fields read for the field map, only set when decoding with AnnotatedPacket
+    #[serde(skip)]
+    trace: Option<FieldTrace>,
 }
+impl PartialEq for ByteBuffer {
+    fn eq(&self, other: &Self) -> bool {
+        self.bytes == other.bytes && self.index == other.index
+    }
+}
+impl Eq for ByteBuffer {}
 impl ByteBuffer {
     pub fn new(bytes: Vec<u8>) -> Self {
-        Self { bytes, index: 0 }
+        Self { bytes, index: 0, trace: None }
+    }
+
+    /**
+     * Buffer that records every field read from it to trace
+     */
+    pub fn traced(bytes: Vec<u8>, trace: FieldTrace) -> Self {
+        Self { bytes, index: 0, trace: Some(trace) }
+    }
+
+    /**
+     * Name the next field read in the field map, does nothing unless the buffer is traced
+     */
+    pub fn field(&mut self, name: &'static str) -> &mut Self {
+        if let Some(trace) = &self.trace {
+            trace.name_next(name);
+        }
+        return self
+    }
+
+    /**
+     * Run a read, recording it as a single field if the buffer is traced
+     */
+    fn traced_read<T: serde::Serialize>(&mut self, kind: &'static str, read: impl FnOnce(&mut Self) -> Result<T, ()>) -> Result<T, ()> {
+        let trace = match &self.trace {
+            Some(t) => t.clone(),
+            None => return read(self)
+        };
+        let start = self.index;
+        trace.enter();
+        let result = read(self);
+        trace.leave();
+        if let Ok(value) = &result {
+            trace.record(start, self.index - start, kind, || serde_json::to_value(value).unwrap_or_default());
+        }
+        result
     }
 
     pub fn reset(mut self) -> Self {
@@ -24,6 +69,9 @@ impl ByteBuffer {
             self.index -= n;
             return Err(())
         }
+        if let Some(trace) = &self.trace {
+            trace.record(self.index - n, n, "bytes", || hex::encode(&self.bytes[self.index-n..self.index]).into());
+        }
         return Ok(&self.bytes[self.index-n..self.index])
     }
     pub fn read_n_bytes_static(&self, n: usize) -> Result<&[u8], ()> {
@@ -34,42 +82,42 @@ impl ByteBuffer {
     }
 
     pub fn read_u8(&mut self) -> Result<u8, ()> {
-        Ok(self.read_n_bytes(1)?[0])
+        self.traced_read("u8", |b| Ok(b.read_n_bytes(1)?[0]))
     }
     pub fn read_u8_static(&self) -> Result<u8, ()> {
         Ok(self.read_n_bytes_static(1)?[0])
     }
 
     pub fn read_u16(&mut self) -> Result<u16, ()> {
-        Ok(BigEndian::read_u16(self.read_n_bytes(2)?))
+        self.traced_read("u16", |b| Ok(BigEndian::read_u16(b.read_n_bytes(2)?)))
     }
     pub fn read_u16_static(&self) -> Result<u16, ()> {
         Ok(BigEndian::read_u16(self.read_n_bytes_static(2)?))
     }
 
     pub fn read_u32(&mut self) -> Result<u32, ()> {
-        Ok(BigEndian::read_u32(self.read_n_bytes(4)?))
+        self.traced_read("u32", |b| Ok(BigEndian::read_u32(b.read_n_bytes(4)?)))
     }
     pub fn read_u32_static(&self) -> Result<u32, ()> {
         Ok(BigEndian::read_u32(self.read_n_bytes_static(4)?))
     }
 
     pub fn read_u64(&mut self) -> Result<u64, ()> {
-        Ok(BigEndian::read_u64(self.read_n_bytes(8)?))
+        self.traced_read("u64", |b| Ok(BigEndian::read_u64(b.read_n_bytes(8)?)))
     }
     pub fn read_u64_static(&self) -> Result<u64, ()> {
         Ok(BigEndian::read_u64(self.read_n_bytes_static(8)?))
     }
 
     pub fn read_f32(&mut self) -> Result<f32, ()> {
-        Ok(BigEndian::read_f32(self.read_n_bytes(4)?))
+        self.traced_read("f32", |b| Ok(BigEndian::read_f32(b.read_n_bytes(4)?)))
     }
     pub fn read_f32_static(&self) -> Result<f32, ()> {
         Ok(BigEndian::read_f32(self.read_n_bytes_static(4)?))
     }
 
     pub fn read_bool(&mut self) -> Result<bool, ()> {
-        Ok(self.read_u8()? != 0)
+        self.traced_read("bool", |b| Ok(b.read_u8()? != 0))
     }
     pub fn read_bool_static(&self) -> Result<bool, ()> {
         Ok(self.read_u8_static()? != 0)
@@ -79,12 +127,27 @@ impl ByteBuffer {
      * Reads string length as the first two bytes from the head of the buffer, then reads the string
      */
     pub fn read_string(&mut self) -> Result<String, ()> {
-        let length = self.read_u16()?;
-        let byte_string = self.read_n_bytes(length as usize)?;
-        String::from_utf8(byte_string.to_vec()).or(Err(()))
+        self.traced_read("string", |b| {
+            let length = b.read_u16()?;
+            let byte_string = b.read_n_bytes(length as usize)?;
+            String::from_utf8(byte_string.to_vec()).or(Err(()))
+        })
+    }
+
+    /**
+     * Reads a byte count from the head of the buffer, then that many bytes
+     */
+    pub fn read_short_bytes(&mut self) -> Result<Vec<u8>, ()> {
+        self.traced_read("short_bytes", |b| {
+            let length = b.read_u8()?;
+            Ok(b.read_n_bytes(length as usize)?.to_vec())
+        })
     }
 
     pub fn read_compressed_i32(&mut self) -> Result<i32, ()> {
+        self.traced_read("compressed_i32", |b| b.read_compressed_i32_untraced())
+    }
+    fn read_compressed_i32_untraced(&mut self) -> Result<i32, ()> {
         let mut ubyte = self.read_u8()? as i32;
         let is_negative = (ubyte & 64) != 0;
         let mut shift = 6u32;
@@ -99,17 +162,22 @@ impl ByteBuffer {
     }
 
     pub fn read_compressed_i32_arr(&mut self) -> Result<Vec<i32>, ()> {
-        let mut ret = vec![];
-        for _ in 0..self.read_compressed_i32()? {
-            ret.push(self.read_compressed_i32()?);
-        }
-        Ok(ret)
+        self.traced_read("compressed_i32_array", |b| {
+            let mut ret = vec![];
+            for _ in 0..b.read_compressed_i32()? {
+                ret.push(b.read_compressed_i32()?);
+            }
+            Ok(ret)
+        })
     }
 
     pub fn to_vec(&self) -> Vec<u8> {
         self.bytes.clone()
     }
     pub fn rem_to_vec(&self) -> Vec<u8> {
+        if let Some(trace) = &self.trace {
+            trace.record(self.index, self.rem_len(), "bytes", || hex::encode(&self.bytes[self.index..]).into());
+        }
         self.bytes[self.index..].into()
     }
 
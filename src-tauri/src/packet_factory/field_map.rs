@@ -0,0 +1,111 @@
+/*
+Records which bytes of a decrypted packet were decoded into which field, for inspecting packets at the byte level.
+Decoding through a traced ByteBuffer records every read made at the top level, reads made inside another read
+(like the length of a string) are part of the outer field.
+*/
+use std::sync::{Arc, Mutex};
+use super::byte_buffer::ByteBuffer;
+use super::rotmg_packet::RotmgPacket;
+
+
+/**
+ * A range of bytes in a packet that was decoded as a single field
+ */
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct FieldSpan {
+    /// Name of the packet field, None for reads try_from doesn't name
+    pub name: Option<&'static str>,
+    /// Offset from the start of the packet, including its header
+    pub offset: usize,
+    pub length: usize,
+    /// How the bytes were read, like "u32" or "string"
+    pub kind: &'static str,
+    /// Decoded value, None for the bytes left unparsed
+    pub value: Option<serde_json::Value>,
+}
+
+
+#[derive(Debug, Default)]
+struct TraceState {
+    fields: Vec<FieldSpan>,
+    /// Name given to the next field read
+    name: Option<&'static str>,
+    /// Number of reads in progress, only the outermost one is recorded
+    depth: usize,
+}
+
+
+/**
+ * Collects the fields read from a ByteBuffer.
+ * Shared between the buffer and whoever made it, since decoding moves the buffer into the packet or drops it.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct FieldTrace {
+    state: Arc<Mutex<TraceState>>,
+}
+impl FieldTrace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn name_next(&self, name: &'static str) {
+        self.state.lock().unwrap().name = Some(name);
+    }
+
+    pub fn enter(&self) {
+        self.state.lock().unwrap().depth += 1;
+    }
+
+    pub fn leave(&self) {
+        self.state.lock().unwrap().depth -= 1;
+    }
+
+    /**
+     * Record a field unless it was read as part of another one.
+     * The value is only built when the field is recorded.
+     */
+    pub fn record(&self, offset: usize, length: usize, kind: &'static str, value: impl FnOnce() -> serde_json::Value) {
+        let mut state = self.state.lock().unwrap();
+        if state.depth > 0 {
+            return
+        }
+        let name = state.name.take();
+        state.fields.push(FieldSpan { name, offset, length, kind, value: Some(value()) });
+    }
+
+    pub fn fields(&self) -> Vec<FieldSpan> {
+        self.state.lock().unwrap().fields.clone()
+    }
+}
+
+
+/**
+ * A decrypted packet with the field every byte was decoded into
+ */
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct AnnotatedPacket {
+    /// The decrypted packet, including its 5 byte header
+    #[serde(with = "hex")]
+    pub bytes: Vec<u8>,
+    /// None if the packet couldn't be decoded, the fields then stop before the read that failed
+    pub packet: Option<RotmgPacket>,
+    /// Fields in the order they were read, ending with the unparsed rem region if there is one
+    pub fields: Vec<FieldSpan>,
+}
+impl AnnotatedPacket {
+    /**
+     * Decode a decrypted packet, recording its field map
+     */
+    pub fn decode(bytes: Vec<u8>) -> Self {
+        let trace = FieldTrace::new();
+        let packet = RotmgPacket::try_from(ByteBuffer::traced(bytes.clone(), trace.clone())).ok();
+        let mut fields = trace.fields();
+
+        //whatever wasn't read is kept as the packet's rem bytes, or is where decoding failed
+        let end = fields.iter().map(|f| f.offset + f.length).max().unwrap_or(0);
+        if end < bytes.len() {
+            fields.push(FieldSpan { name: Some("rem"), offset: end, length: bytes.len() - end, kind: "rest", value: None });
+        }
+        Self { bytes, packet, fields }
+    }
+}
@@ -1,13 +1,18 @@
 pub mod rotmg_packet;
 pub mod byte_buffer;
 pub mod data_types;
+pub mod packet_envelope;
+pub mod field_map;
 mod rotmg_packet_constructor;
 mod rotmg_packet_stitcher;
+#[cfg(test)]
+pub mod test_stream;
 
+use std::collections::BTreeMap;
 use etherparse::SlicedPacket;
-use self::rotmg_packet::RotmgPacket;
+use self::packet_envelope::{PacketEnvelope, FrameMeta, CipherEvent};
 use self::rotmg_packet_constructor::RotmgPacketConstructor;
-use self::rotmg_packet_stitcher::RotmgPacketStitcher;
+use self::rotmg_packet_stitcher::{RotmgPacketStitcher, SegmentResult};
 
 
 
@@ -20,52 +25,161 @@ pub struct RotmgPacketFactory {
     pub stitcher: RotmgPacketStitcher,
     pub constructor: RotmgPacketConstructor,
 
+    //The stitcher can only find packet boundaries once a segment smaller than the maximum size has been received
+    synced: bool,
+
+    pub packets_in: usize,
+    pub packets_out: usize,
+    //Packets decoded while paused that weren't stored
+    pub packets_skipped: usize,
+    pub reconnects: usize,
+    //Times packet boundaries were found again after a gap in the tcp stream
+    pub resyncs: usize,
+}
+
+
+/**
+ * Counters describing how well the factory is keeping up with the stream
+ */
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct FactoryStats {
     pub packets_in: usize,
     pub packets_out: usize,
+    /// Packets decoded while the capture was paused, which weren't stored
+    pub packets_skipped: usize,
+    pub reconnects: usize,
+    pub tcp_gaps: usize,
+    pub retransmissions: usize,
+    pub resyncs: usize,
+    /// Packets that failed to parse after decrypting, by packet type number
+    pub decode_failures: BTreeMap<u8, usize>,
 }
 impl RotmgPacketFactory {
     pub fn new() -> Self {
         Self {
             stitcher: RotmgPacketStitcher::new(),
             constructor: RotmgPacketConstructor::new(),
+            synced: false,
             packets_in: 0,
-            packets_out: 0
+            packets_out: 0,
+            packets_skipped: 0,
+            reconnects: 0,
+            resyncs: 0,
         }
     }
 
     /**
      * Hand a sliced packet to the factory for processing
      */
-    pub fn insert_packet(&mut self, packet: SlicedPacket, window: &tauri::Window) {
+    pub fn insert_packet(&mut self, packet: SlicedPacket, meta: FrameMeta) {
         //do nothing if the packet is empty
         if packet.payload.len() == 0 {return}
 
-        //send packet to the stitcher
-        self.stitcher.insert_packet(packet.payload);
+        //a full size segment is likely continued in the next one, so it can't be the start of the stream
+        if packet.payload.len() < 1460 && self.synced == false {
+            self.synced = true;
+            self.stitcher.resync();
+            if self.stitcher.gaps > 0 {
+                self.resyncs += 1;
+            }
+        }
+        if self.synced == false {return}
+
+        //send packet to the stitcher, a gap means waiting for the stream to sync again
+        if self.stitcher.insert_packet(packet.payload, meta) == SegmentResult::Gap {
+            self.synced = false;
+        }
 
         //get any packets output by the stitcher and send them to the constructor
         while let Some(p) = self.stitcher.get_packet() {
             self.packets_in += 1;
-            self.constructor.insert_packet(p, window);
+            if p.type_num == 45 { //Reconnect packet type number
+                self.reconnects += 1;
+            }
+            self.constructor.insert_packet(p);
         }
     }
 
 
     /**
-     * Get a rotmg packet from the head of the output queue
+     * Get a rotmg packet from the head of the output queue, numbered by its position in the session
      */
-    pub fn get_packet(&mut self) -> Option<RotmgPacket> {
-        let p = self.constructor.get_packet();
-        match p {
-            Some(_) => self.packets_out += 1,
+    pub fn get_packet(&mut self) -> Option<PacketEnvelope> {
+        let mut p = self.constructor.get_packet();
+        match p.as_mut() {
+            Some(envelope) => {
+                envelope.index = self.packets_out;
+                self.packets_out += 1;
+            },
             None => (),
         }
         return p
     }
 
+    /**
+     * Get a rotmg packet from the head of the output queue without numbering it.
+     * Used for packets that won't be stored, so the packets that are stored stay numbered without gaps.
+     */
+    pub fn skip_packet(&mut self) -> Option<PacketEnvelope> {
+        let p = self.constructor.get_packet();
+        if p.is_some() {
+            self.packets_skipped += 1;
+        }
+        return p
+    }
+
+    /**
+     * Number packets from 0 again for a new session, without losing track of the stream or the cipher
+     */
+    pub fn new_session(&mut self) {
+        self.packets_out = 0;
+    }
+
+    /**
+     * Get the oldest cipher alignment change that hasn't been handled yet
+     */
+    pub fn get_event(&mut self) -> Option<CipherEvent> {
+        self.constructor.get_event()
+    }
+
+    /**
+     * Flush out any packets that are still waiting on a tick packet.
+     * Used at the end of a capture so the last packets of a session aren't lost.
+     */
+    pub fn finalize(&mut self) {
+        self.constructor.finalize();
+    }
+
+    /**
+     * Whether the cipher is currently aligned to the stream
+     */
+    pub fn is_aligned(&self) -> bool {
+        self.constructor.is_aligned()
+    }
+
+    pub fn stats(&self) -> FactoryStats {
+        FactoryStats {
+            packets_in: self.packets_in,
+            packets_out: self.packets_out,
+            packets_skipped: self.packets_skipped,
+            reconnects: self.reconnects,
+            tcp_gaps: self.stitcher.gaps,
+            retransmissions: self.stitcher.retransmissions,
+            resyncs: self.resyncs,
+            decode_failures: self.constructor.decode_failures.clone(),
+        }
+    }
+
     pub fn reset(&mut self) {
         self.stitcher.reset();
         self.constructor.reset();
+        self.constructor.decode_failures.clear();
+        self.synced = false;
+        self.packets_in = 0;
+        self.packets_out = 0;
+        self.packets_skipped = 0;
+        self.reconnects = 0;
+        self.resyncs = 0;
     }
 
     
@@ -0,0 +1,137 @@
+use std::net::{IpAddr, SocketAddr};
+use super::byte_buffer::ByteBuffer;
+use super::rotmg_packet::RotmgPacket;
+
+
+/**
+ * The source and destination addresses of the tcp connection a packet was captured on
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct Connection {
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+}
+impl Connection {
+    pub fn new(source_ip: IpAddr, source_port: u16, destination_ip: IpAddr, destination_port: u16) -> Self {
+        Self {
+            source: SocketAddr::new(source_ip, source_port),
+            destination: SocketAddr::new(destination_ip, destination_port),
+        }
+    }
+}
+
+
+/**
+ * Capture information about a single tcp segment, handed to the packet factory alongside its payload
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameMeta {
+    /// Capture time in microseconds since the unix epoch
+    pub timestamp: i64,
+    pub connection: Connection,
+    /// Sequence number of the first payload byte of the segment
+    pub tcp_seq: u32,
+    /// Number of the frame in the capture, counting from 1 like wireshark does
+    pub frame: usize,
+}
+
+
+/**
+ * How the cipher state was known to be correct when a packet was decrypted
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum AlignmentState {
+    /// A tick packet following this packet confirmed the cipher was aligned
+    Aligned,
+    /// The packet was decrypted right after the cipher was realigned to a tick packet
+    Realigned,
+    /// The capture ended before a tick packet could confirm the cipher alignment
+    Unverified,
+}
+
+
+/**
+ * Changes in the cipher alignment, reported by the packet factory so the ui or a capture file can be informed
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum CipherEvent {
+    /// The cipher was realigned to a tick packet
+    Aligned,
+    /// A tick packet did not decrypt to the expected tick id
+    Misaligned,
+}
+impl CipherEvent {
+    /**
+     * Name of the event emitted to the ui
+     */
+    pub fn event_name(&self) -> &'static str {
+        match self {
+            CipherEvent::Aligned => "cipher-aligned",
+            CipherEvent::Misaligned => "cipher-misaligned",
+        }
+    }
+}
+
+
+/**
+ * A decrypted rotmg packet along with everything known about how and when it was captured
+ */
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct PacketEnvelope {
+    /// Position of the packet in the capture session, starting at 0
+    pub index: usize,
+    /// Capture time in microseconds since the unix epoch of the segment that completed this packet
+    pub timestamp: i64,
+    pub connection: Connection,
+    /// Tcp sequence number of the first byte of this packet
+    pub tcp_seq: u32,
+    /// Number of the capture frame that completed this packet, counting from 1 like wireshark does
+    pub frame: usize,
+    /// Length of the packet on the wire, including the length and type header
+    pub encrypted_len: usize,
+    pub alignment: AlignmentState,
+    pub packet: RotmgPacket,
+    /// Decrypted packet body without the length and type header.
+    /// Left out when serialized so the ui isn't sent every packet twice, inspect_packet serves it on demand.
+    #[serde(skip)]
+    pub data: Vec<u8>,
+}
+impl PacketEnvelope {
+    /**
+     * Serialize the envelope with its decrypted body as hex, for exports and the command line
+     */
+    pub fn with_data(&self) -> EnvelopeWithData<'_> {
+        EnvelopeWithData { envelope: self, data: &self.data }
+    }
+
+    /**
+     * The decrypted packet with the length and type header it had on the wire
+     */
+    pub fn decrypted(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.data.len() + 5);
+        bytes.extend_from_slice(&(self.data.len() as u32 + 5).to_be_bytes());
+        bytes.push(self.packet.type_num());
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+
+    /**
+     * Decode the packet again from the decrypted body, restoring the rem bytes that aren't serialized with it
+     */
+    pub fn redecode(&mut self) -> Result<(), ()> {
+        self.packet = RotmgPacket::try_from(ByteBuffer::new(self.decrypted()))?;
+        Ok(())
+    }
+}
+
+
+/**
+ * A packet envelope serialized along with its decrypted body as hex
+ */
+#[derive(Debug, serde::Serialize)]
+pub struct EnvelopeWithData<'a> {
+    #[serde(flatten)]
+    envelope: &'a PacketEnvelope,
+    #[serde(with = "hex")]
+    data: &'a [u8],
+}
@@ -2,633 +2,367 @@
 use crate::packet_factory::byte_buffer::ByteBuffer;
 use super::data_types::*;
 
-#[repr(u16)]
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
-pub enum RotmgPacket {
-    Failure {
-        rem: ByteBuffer
-    } = 0,
-    Teleport {
-        rem: ByteBuffer
-    } = 1,
-    //Missing
-    ClaimLoginReward {
-        rem: ByteBuffer
-    } = 3,
-    DeletePet {
-        rem: ByteBuffer
-    } = 4,
-    RequestTrade {
-        rem: ByteBuffer
-    } = 5,
-    QuestFetchResponse {
-        rem: ByteBuffer
-    } = 6,
-    JoinGuild {
-        rem: ByteBuffer
-    } = 7,
-    Ping {
-        rem: ByteBuffer
-    } = 8,
-    PlayerText {
-        rem: ByteBuffer
-    } = 9,
-    NewTick {
-        tick_id: u32,
-        tick_time: u32,
-        server_current_time: u32,
-        server_prev_time: u16,
-        rem: ByteBuffer
-    } = 10,
-    ShowEffect {
-        rem: ByteBuffer
-    } = 11,
-    ServerPlayerShoot {
-        rem: ByteBuffer
-    } = 12,
-    UseItem {
-        rem: ByteBuffer
-    } = 13,
-    TradeAccepted {
-        rem: ByteBuffer
-    } = 14,
-    GuildRemove {
-        rem: ByteBuffer
-    } = 15,
-    PetUpgradeRequest {
-        rem: ByteBuffer
-    } = 16,
-    EnterArena {
-        rem: ByteBuffer
-    } = 17,
-    GoTo {
-        rem: ByteBuffer
-    } = 18,
-    InventoryDrop {
-        rem: ByteBuffer
-    } = 19,
-    OtherHit {
-        rem: ByteBuffer
-    } = 20,
-    NameResult {
-        rem: ByteBuffer
-    } = 21,
-    BuyResult {
-        rem: ByteBuffer
-    } = 22,
-    HatchPet {
-        rem: ByteBuffer
-    } = 23,
-    ActivePetUpdateRequest {
-        rem: ByteBuffer
-    } = 24,
-    EnemyHit {
-        rem: ByteBuffer
-    } = 25,
-    GuildResult {
-        rem: ByteBuffer
-    } = 26,
-    EditAccountList {
-        rem: ByteBuffer
-    } = 27,
-    TradeChanged {
-        rem: ByteBuffer
-    } = 28,
-    //missing
-    PlayerShoot {
-        rem: ByteBuffer
-    } = 30,
-    Pong {
-        rem: ByteBuffer
-    } = 31,
-    //missing
-    PetChangeSkinMessage {
-        rem: ByteBuffer
-    } = 33,
-    TradeDone {
-        rem: ByteBuffer
-    } = 34,
-    EnemyShoot {
-        rem: ByteBuffer
-    } = 35,
-    AcceptTrade {
-        rem: ByteBuffer
-    } = 36,
-    ChangeGuildRank {
-        rem: ByteBuffer
-    } = 37,
-    PlaySound {
-        rem: ByteBuffer
-    } = 38,
-    VerifyEmail {
-        rem: ByteBuffer
-    } = 39,
-    SquareHit {
-        rem: ByteBuffer
-    } = 40,
-    NewAbility {
-        rem: ByteBuffer
-    } = 41,
-    Update {
-        //position: PositionData,
-        //level: u8, //unknown
-        //ground_tile_data: Vec<GroundTileData>,
-        //object_data: Vec<ObjectData>,
-        //drops: Vec<i32>,
-        rem: ByteBuffer
-    } = 42,
-    //missing
-    Text {
-        name: String,
-        object_id: u32,
-        num_stars: u16,
-        display_time: u8,
-        recipient: String,
-        content: String,
-        clean_text: String,
-        is_supporter: bool,
-        star_background: u32
-    } = 44,
-    Reconnect {
-        name: String,
-        host: String,
-        unknown: u32,
-        port: u32,
-        game_id: u32,
-        key: Vec<u8>,
-    } = 45,
-    Death {
-        rem: ByteBuffer
-    } = 46,
-    UsePortal {
-        rem: ByteBuffer
-    } = 47,
-    QuestRoomMessage {
-        rem: ByteBuffer
-    } = 48,
-    AllyShoot {
-        rem: ByteBuffer
-    } = 49,
-    ImminentArenaWave {
-        rem: ByteBuffer
-    } = 50,
-    Reskin {
-        rem: ByteBuffer
-    } = 51,
-    ResetDailyQuests {
-        rem: ByteBuffer
-    } = 52,
-    PetChangeFormMsg {
-        rem: ByteBuffer
-    } = 53,
-    //missing
-    InvResult {
-        rem: ByteBuffer
-    } = 55,
-    ChangeTrade {
-        rem: ByteBuffer
-    } = 56,
-    Create {
-        rem: ByteBuffer
-    } = 57,
-    QuestRedeem {
-        rem: ByteBuffer
-    } = 58,
-    CreateGuild {
-        rem: ByteBuffer
-    } = 59,
-    SetCondition {
-        rem: ByteBuffer
-    } = 60,
-    Load {
-        rem: ByteBuffer
-    } = 61,
-    Move {
-        tick_id: u32,
-        time: u32,
-        rem: ByteBuffer
-    } = 62,
-    KeyInfoResponse {
-        rem: ByteBuffer
-    } = 63,
-    Aoe {
-        rem: ByteBuffer
-    } = 64,
-    GoToAck {
-        rem: ByteBuffer
-    } = 65,
-    GlobalNotification {
-        rem: ByteBuffer
-    } = 66,
-    Notification {
-        rem: ByteBuffer
-    } = 67,
-    ArenaDeath {
-        rem: ByteBuffer
-    } = 68,
-    ClientStat {
-        rem: ByteBuffer
-    } = 69,
-    //missing
-    //missing
-    //missing
-    //missing
-    Hello {
-        rem: ByteBuffer
-    } = 74,
-    Damage {
-        target_id: u32,
-        effects: Vec<u8>,
-        damage_amount: u16,
-        killed: bool,
-        armor_piercing: bool,
-        bullet_id: u8,
-        owner_id: u32
-    } = 75,
-    ActivePetUpdate {
-        rem: ByteBuffer
-    } = 76,
-    InvitedToGuild {
-        rem: ByteBuffer
-    } = 77,
-    PetYardUpdate {
-        rem: ByteBuffer
-    } = 78,
-    PasswordPrompt {
-        rem: ByteBuffer
-    } = 79,
-    AcceptArenaDeath {
-        rem: ByteBuffer
-    } = 80,
-    UpdateAck {
-        rem: ByteBuffer
-    } = 81,
-    QuestObjectId {
-        rem: ByteBuffer
-    } = 82,
-    Pic {
-        rem: ByteBuffer
-    } = 83,
-    RealmHeroLeftMsg {
-        rem: ByteBuffer
-    } = 84,
-    Buy {
-        rem: ByteBuffer
-    } = 85,
-    TradeStart {
-        rem: ByteBuffer
-    } = 86,
-    EvolvePet {
-        rem: ByteBuffer
-    } = 87,
-    TradeRequested {
-        rem: ByteBuffer
-    } = 88,
-    AoeAck {
-        rem: ByteBuffer
-    } = 89,
-    PlayerHit {
-        rem: ByteBuffer
-    } = 90,
-    CancelTrade {
-        rem: ByteBuffer
-    } = 91,
-    MapInfo {
-        width: u32,
-        height: u32,
-        name: String,
-        display_name: String,
-        realm_name: String,
-        difficulty: f32,
-        seed: u32,
-        background: u32,
-        allow_teleport: bool,
-        show_displays: bool,
-        unknown_bool: bool,
-        max_players: u16,
-        game_opened_time: u32,
-        build_version: String,
-        unknown_int: u32,
-        dungeon_mods: String
-    } = 92,
-    LoginRewardMsg {
-        rem: ByteBuffer
-    } = 93,
-    KeyInfoRequest {
-        rem: ByteBuffer
-    } = 94,
-    InvSwap {
-        rem: ByteBuffer
-    } = 95,
-    QuestRedeemResponse {
-        rem: ByteBuffer
-    } = 96,
-    ChooseName {
-        rem: ByteBuffer
-    } = 97,
-    QuestFetchAsk {
-        rem: ByteBuffer
-    } = 98,
-    AccountList {
-        rem: ByteBuffer
-    } = 99,
-    ShootAck {
-        rem: ByteBuffer
-    } = 100,
-    CreateSuccess {
-        rem: ByteBuffer
-    } = 101,
-    CheckCredits {
-        rem: ByteBuffer
-    } = 102,
-    GroundDamage {
-        rem: ByteBuffer
-    } = 103,
-    GuildInvite {
-        rem: ByteBuffer
-    } = 104,
-    Escape {
-        rem: ByteBuffer
-    } = 105,
-    File {
-        rem: ByteBuffer
-    } = 106,
-    ReskinUnlock {
-        rem: ByteBuffer
-    } = 107,
-    NewCharacterInfo {
-        rem: ByteBuffer
-    } = 108,
-    UnlockInfo {
-        rem: ByteBuffer
-    } = 109,
-    //missing
-    //missing
-    QueueInfo {
-        rem: ByteBuffer
-    } = 112,
-    QueueCancel {
-        rem: ByteBuffer
-    } = 113,
-    ExaltationBonusChanged {
-        rem: ByteBuffer
-    } = 114,
-    RedeemExaltationReward {
-        rem: ByteBuffer
-    } = 115,
-    //missing
-    VaultUpdate {
-        rem: ByteBuffer
-    } = 117,
-    ForgeRequest {
-        rem: ByteBuffer
-    } = 118,
-    ForgeResult {
-        rem: ByteBuffer
-    } = 119,
-    ForgeUnlockedBlueprints {
-        rem: ByteBuffer
-    } = 120,
-    ShootAckCounter {
-        rem: ByteBuffer
-    } = 121,
-    ChangeAllyShoot {
-        rem: ByteBuffer
-    } = 122,
-    GetPlayersListMessage {
-        rem: ByteBuffer
-    } = 123,
-    ModeratorActionMessage {
-        rem: ByteBuffer
-    } = 124,
-    //missing
-    CreepMoveMessage {
-        rem: ByteBuffer
-    } = 126,
-    //missing
-    //missing
-    //missing
-    //missing
-    //missing
-    //missing
-    //missing
-    Unknown134  {
-        rem: ByteBuffer
-    } = 134,
-    //missing
-    //missing
-    Dash {
-        rem: ByteBuffer
-    } = 137,
-    DashAck {
-        rem: ByteBuffer
-    } = 138,
-    Unknown139 {
-        rem: ByteBuffer
-    } = 139,
-    //missing
-    //missing
-    //missing
-    //missing
-    //missing
-    Unknown145 {
-        rem: ByteBuffer
-    } = 145,
-    Unknown146 {
-        rem: ByteBuffer
-    } = 146,
-    Unknown147 {
-        rem: ByteBuffer
-    } = 147,
-    //missing
-    ClaimBattlePass {
-        rem: ByteBuffer
-    } = 149,
-    ClaimBPMilestoneResult {
-        rem: ByteBuffer
-    } = 150,
-    //missing
-    //missing
-    //missing
-    ConvertSeasonal {
-        rem: ByteBuffer
-    } = 154,
-    //missing
-    //missing
-    //missing
-    //missing
-    Emote {
-        rem: ByteBuffer
-    } = 159,
-    //missing
-    //missing
-    //missing
-    Unknown163 {
-        rem: ByteBuffer
-    } = 163,
-    Unknown164 {
-        rem: ByteBuffer
-    } = 164,
-    Unknown165 {
-        rem: ByteBuffer
-    } = 165,
-    Stasis {
-        rem: ByteBuffer
-    } = 166,
-    //missing
-    //missing
-    Unknown169 {
-        rem: ByteBuffer
-    } = 169,
 
-    Other { //catch-all for any packets whose type number is not listed here
-        type_num: u8,
-        rem: ByteBuffer
-    } = 1000,
+/**
+ * Rust type of a packet field read as the given kind
+ */
+macro_rules! field_type {
+    (U8) => { u8 };
+    (U16) => { u16 };
+    (U32) => { u32 };
+    (F32) => { f32 };
+    (Bool) => { bool };
+    (String) => { String };
+    (ShortBytes) => { Vec<u8> };
+    (Rest) => { Vec<u8> };
+    (Rem) => { ByteBuffer };
 }
-impl TryFrom<ByteBuffer> for RotmgPacket {
-    type Error = ();
 
-    fn try_from(mut buf: ByteBuffer) -> Result<Self, ()> {
-        use RotmgPacket::*;
-        let _packet_len = buf.read_u32()?;
-        let packet_type = buf.read_u8()?;
-        return Ok(match packet_type {
-            0 => Failure { rem: buf },
-            1 => Teleport { rem: buf },
-            3 => ClaimLoginReward { rem: buf },
-            4 => DeletePet { rem: buf },
-            5 => RequestTrade { rem: buf },
-            6 => QuestFetchResponse { rem: buf },
-            7 => JoinGuild { rem: buf },
-            8 => Ping { rem: buf },
-            9 => PlayerText { rem: buf },
-            10 => NewTick { tick_id: buf.read_u32()?, tick_time: buf.read_u32()?, server_current_time: buf.read_u32()?, server_prev_time: buf.read_u16()?, rem: buf },
-            11 => ShowEffect { rem: buf },
-            12 => ServerPlayerShoot { rem: buf },
-            13 => UseItem { rem: buf },
-            14 => TradeAccepted { rem: buf },
-            15 => GuildRemove { rem: buf },
-            16 => PetUpgradeRequest { rem: buf },
-            17 => EnterArena { rem: buf },
-            18 => GoTo { rem: buf },
-            19 => InventoryDrop { rem: buf },
-            20 => OtherHit { rem: buf },
-            21 => NameResult { rem: buf },
-            22 => HatchPet { rem: buf },
-            23 => HatchPet { rem: buf },
-            24 => ActivePetUpdateRequest { rem: buf },
-            25 => EnemyHit { rem: buf },
-            26 => GuildResult { rem: buf },
-            27 => EditAccountList { rem: buf },
-            28 => TradeChanged { rem: buf },
-            30 => PlayerShoot { rem: buf },
-            31 => Pong { rem: buf },
-            33 => PetChangeSkinMessage { rem: buf },
-            34 => TradeDone { rem: buf },
-            35 => EnemyShoot { rem: buf },
-            36 => AcceptTrade { rem: buf },
-            37 => ChangeGuildRank { rem: buf },
-            38 => PlaySound { rem: buf },
-            39 => VerifyEmail { rem: buf },
-            40 => SquareHit { rem: buf },
-            41 => NewAbility { rem: buf },
-            42 => Update { rem: buf },//{ position: PositionData::deserialize_from_buf(&mut buf)?, level: buf.read_u8()?, ground_tile_data: GroundTileData::deserialize_arr_from_buf(&mut buf)?, object_data: ObjectData::deserialize_arr_from_buf(&mut buf)?, drops: buf.read_compressed_i32_arr()?, rem: buf },
-            44 => Text { name: buf.read_string()?, object_id: buf.read_u32()?, num_stars: buf.read_u16()?, display_time: buf.read_u8()?, recipient: buf.read_string()?, content: buf.read_string()?, clean_text: buf.read_string()?, is_supporter: buf.read_bool()?, star_background: buf.read_u32()? },
-            45 => Reconnect { name: buf.read_string()?, host: buf.read_string()?, unknown: buf.read_u32()?, port: buf.read_u32()?, game_id: buf.read_u32()?, key: buf.rem_to_vec() },
-            46 => Death { rem: buf },
-            47 => UsePortal { rem: buf },
-            48 => QuestRoomMessage { rem: buf },
-            49 => AllyShoot { rem: buf },
-            50 => ImminentArenaWave { rem: buf },
-            51 => Reskin { rem: buf },
-            52 => ResetDailyQuests { rem: buf },
-            53 => PetChangeFormMsg { rem: buf },
-            55 => InvResult { rem: buf },
-            56 => ChangeTrade { rem: buf },
-            57 => Create { rem: buf },
-            58 => QuestRedeem { rem: buf },
-            59 => CreateGuild { rem: buf },
-            60 => SetCondition { rem: buf },
-            61 => Load { rem: buf },
-            62 => Move { tick_id: buf.read_u32()?, time: buf.read_u32()?, rem: buf },
-            63 => KeyInfoResponse { rem: buf },
-            64 => Aoe { rem: buf },
-            65 => GoToAck { rem: buf },
-            66 => GlobalNotification { rem: buf },
-            67 => Notification { rem: buf },
-            68 => ArenaDeath { rem: buf },
-            69 => ClientStat { rem: buf },
-            74 => Hello { rem: buf },
-            75 => {
-                let target_id = buf.read_u32()?; let effect_len = buf.read_u8()?; let effects = buf.read_n_bytes(effect_len as usize)?.to_vec(); 
-                Damage { target_id, effects, damage_amount: buf.read_u16()?, killed: buf.read_bool()?, armor_piercing: buf.read_bool()?, bullet_id: buf.read_u8()?, owner_id: buf.read_u32()? }
+/**
+ * Read a packet field of the given kind from buf, naming it in the field map.
+ * Rem takes the buffer itself, so it has to be the last field of a packet.
+ */
+macro_rules! read_field {
+    ($buf:ident, $name:ident, U8) => { $buf.field(stringify!($name)).read_u8()? };
+    ($buf:ident, $name:ident, U16) => { $buf.field(stringify!($name)).read_u16()? };
+    ($buf:ident, $name:ident, U32) => { $buf.field(stringify!($name)).read_u32()? };
+    ($buf:ident, $name:ident, F32) => { $buf.field(stringify!($name)).read_f32()? };
+    ($buf:ident, $name:ident, Bool) => { $buf.field(stringify!($name)).read_bool()? };
+    ($buf:ident, $name:ident, String) => { $buf.field(stringify!($name)).read_string()? };
+    ($buf:ident, $name:ident, ShortBytes) => { $buf.field(stringify!($name)).read_short_bytes()? };
+    ($buf:ident, $name:ident, Rest) => { $buf.field(stringify!($name)).rem_to_vec() };
+    ($buf:ident, $name:ident, Rem) => { $buf };
+}
+
+/**
+ * Whether a packet field is left out when packets are serialized.
+ * Rem bytes are, the decrypted body is kept alongside the packet and undecoded bytes would only repeat it.
+ */
+trait SkipField {
+    fn skip(&self) -> bool {
+        return false
+    }
+}
+impl SkipField for u8 {}
+impl SkipField for u16 {}
+impl SkipField for u32 {}
+impl SkipField for f32 {}
+impl SkipField for bool {}
+impl SkipField for String {}
+impl SkipField for Vec<u8> {}
+impl SkipField for ByteBuffer {
+    fn skip(&self) -> bool {
+        return true
+    }
+}
+
+/**
+ * Declares RotmgPacket along with its type numbers, names, decoding and PACKET_TYPES from a single table,
+ * so a packet type can't be decoded under one number and reported or dissected under another.
+ * Fields are read in the order they are listed.
+ */
+macro_rules! packets {
+    ($($type_num:literal => $name:ident { $($field:ident: $kind:ident),* $(,)? }),* $(,)?) => {
+        #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+        pub enum RotmgPacket {
+            $($name { $(#[serde(skip_serializing_if = "SkipField::skip", default)] $field: field_type!($kind)),* },)*
+            Other { //catch-all for any packets whose type number is not listed here
+                type_num: u8,
+                #[serde(skip, default)]
+                rem: ByteBuffer
             },
-            76 => ActivePetUpdate { rem: buf },
-            77 => InvitedToGuild { rem: buf },
-            78 => PetYardUpdate { rem: buf },
-            79 => PasswordPrompt { rem: buf },
-            80 => AcceptArenaDeath { rem: buf },
-            81 => UpdateAck { rem: buf },
-            82 => QuestObjectId { rem: buf },
-            83 => Pic { rem: buf },
-            84 => RealmHeroLeftMsg { rem: buf },
-            85 => Buy { rem: buf },
-            86 => TradeStart { rem: buf },
-            87 => EvolvePet { rem: buf },
-            88 => TradeRequested { rem: buf },
-            89 => AoeAck { rem: buf },
-            90 => PlayerHit { rem: buf },
-            91 => CancelTrade { rem: buf },
-            92 => MapInfo { width: buf.read_u32()?, height: buf.read_u32()?, name: buf.read_string()?, display_name: buf.read_string()?, realm_name: buf.read_string()?, difficulty: buf.read_f32()?, seed: buf.read_u32()?, background: buf.read_u32()?, allow_teleport: buf.read_bool()?, show_displays: buf.read_bool()?, unknown_bool: buf.read_bool()?, max_players: buf.read_u16()?, game_opened_time: buf.read_u32()?, build_version: buf.read_string()?, unknown_int: buf.read_u32()?, dungeon_mods: buf.read_string()? },
-            93 => LoginRewardMsg { rem: buf },
-            94 => KeyInfoRequest { rem: buf },
-            95 => InvSwap { rem: buf },
-            96 => QuestRedeemResponse { rem: buf },
-            97 => ChooseName { rem: buf },
-            98 => QuestFetchAsk { rem: buf },
-            99 => AccountList { rem: buf },
-            100 => ShootAck { rem: buf },
-            101 => CreateSuccess { rem: buf },
-            102 => CheckCredits { rem: buf },
-            103 => GroundDamage { rem: buf },
-            104 => GuildInvite { rem: buf },
-            105 => Escape { rem: buf },
-            106 => File { rem: buf },
-            107 => ReskinUnlock { rem: buf },
-            108 => NewCharacterInfo { rem: buf },
-            109 => UnlockInfo { rem: buf },
-            112 => QueueInfo { rem: buf },
-            113 => QueueCancel { rem: buf },
-            114 => ExaltationBonusChanged { rem: buf },
-            115 => RedeemExaltationReward { rem: buf },
-            117 => VaultUpdate { rem: buf },
-            118 => ForgeRequest { rem: buf },
-            119 => ForgeResult { rem: buf },
-            120 => ForgeUnlockedBlueprints { rem: buf },
-            121 => ShootAckCounter { rem: buf },
-            122 => ChangeAllyShoot { rem: buf },
-            123 => GetPlayersListMessage { rem: buf },
-            124 => ModeratorActionMessage { rem: buf },
-            126 => CreepMoveMessage { rem: buf },
-            134 => Unknown134 { rem: buf },
-            137 => Dash { rem: buf },
-            138 => DashAck { rem: buf },
-            139 => Unknown139 { rem: buf },
-            145 => Unknown145 { rem: buf },
-            146 => Unknown146 { rem: buf },
-            147 => Unknown147 { rem: buf },
-            149 => ClaimBattlePass { rem: buf },
-            150 => ClaimBPMilestoneResult { rem: buf },
-            154 => ConvertSeasonal { rem: buf },
-            159 => Emote { rem: buf },
-            163 => Unknown163 { rem: buf },
-            164 => Unknown164 { rem: buf },
-            165 => Unknown165 { rem: buf },
-            166 => Stasis { rem: buf },
-            169 => Unknown169 { rem: buf },
+        }
+        impl RotmgPacket {
+            /**
+             * The type number of the packet as it appears in the packet header
+             */
+            pub fn type_num(&self) -> u8 {
+                match self {
+                    $(RotmgPacket::$name { .. } => $type_num,)*
+                    RotmgPacket::Other { type_num, .. } => *type_num,
+                }
+            }
+
+            /**
+             * The name of the packet type, matching the variant name used when the packet is serialized
+             */
+            pub fn type_name(&self) -> &'static str {
+                return Self::type_name_of(self.type_num())
+            }
+
+            /**
+             * The name of the packet type with the given type number, "Other" if it isn't listed
+             */
+            pub fn type_name_of(type_num: u8) -> &'static str {
+                match type_num {
+                    $($type_num => stringify!($name),)*
+                    _ => "Other",
+                }
+            }
+        }
+
+        /**
+         * Every packet type with its own variant, along with the fields read from it
+         */
+        pub const PACKET_TYPES: &[PacketType] = &[
+            $(PacketType { type_num: $type_num, name: stringify!($name), fields: &[$((stringify!($field), FieldKind::$kind)),*] },)*
+        ];
+
+        impl TryFrom<ByteBuffer> for RotmgPacket {
+            type Error = ();
+
+            fn try_from(mut buf: ByteBuffer) -> Result<Self, ()> {
+                let _packet_len = buf.field("length").read_u32()?;
+                let packet_type = buf.field("type").read_u8()?;
+                return Ok(match packet_type {
+                    $($type_num => RotmgPacket::$name { $($field: read_field!(buf, $field, $kind)),* },)*
+                    _ => RotmgPacket::Other { type_num: packet_type, rem: buf },
+                })
+            }
+        }
+    };
+}
+
+
+packets! {
+    0 => Failure { rem: Rem },
+    1 => Teleport { rem: Rem },
+    3 => ClaimLoginReward { rem: Rem },
+    4 => DeletePet { rem: Rem },
+    5 => RequestTrade { rem: Rem },
+    6 => QuestFetchResponse { rem: Rem },
+    7 => JoinGuild { rem: Rem },
+    8 => Ping { rem: Rem },
+    9 => PlayerText { rem: Rem },
+    10 => NewTick { tick_id: U32, tick_time: U32, server_current_time: U32, server_prev_time: U16, rem: Rem },
+    11 => ShowEffect { rem: Rem },
+    12 => ServerPlayerShoot { rem: Rem },
+    13 => UseItem { rem: Rem },
+    14 => TradeAccepted { rem: Rem },
+    15 => GuildRemove { rem: Rem },
+    16 => PetUpgradeRequest { rem: Rem },
+    17 => EnterArena { rem: Rem },
+    18 => GoTo { rem: Rem },
+    19 => InventoryDrop { rem: Rem },
+    20 => OtherHit { rem: Rem },
+    21 => NameResult { rem: Rem },
+    22 => BuyResult { rem: Rem },
+    23 => HatchPet { rem: Rem },
+    24 => ActivePetUpdateRequest { rem: Rem },
+    25 => EnemyHit { rem: Rem },
+    26 => GuildResult { rem: Rem },
+    27 => EditAccountList { rem: Rem },
+    28 => TradeChanged { rem: Rem },
+    30 => PlayerShoot { rem: Rem },
+    31 => Pong { rem: Rem },
+    33 => PetChangeSkinMessage { rem: Rem },
+    34 => TradeDone { rem: Rem },
+    35 => EnemyShoot { rem: Rem },
+    36 => AcceptTrade { rem: Rem },
+    37 => ChangeGuildRank { rem: Rem },
+    38 => PlaySound { rem: Rem },
+    39 => VerifyEmail { rem: Rem },
+    40 => SquareHit { rem: Rem },
+    41 => NewAbility { rem: Rem },
+    //position: PositionData, level: u8, ground_tile_data: Vec<GroundTileData>, object_data: Vec<ObjectData> and drops: Vec<i32> aren't decoded yet
+    42 => Update { rem: Rem },
+    44 => Text { name: String, object_id: U32, num_stars: U16, display_time: U8, recipient: String, content: String, clean_text: String, is_supporter: Bool, star_background: U32 },
+    45 => Reconnect { name: String, host: String, unknown: U32, port: U32, game_id: U32, key: Rest },
+    46 => Death { rem: Rem },
+    47 => UsePortal { rem: Rem },
+    48 => QuestRoomMessage { rem: Rem },
+    49 => AllyShoot { rem: Rem },
+    50 => ImminentArenaWave { rem: Rem },
+    51 => Reskin { rem: Rem },
+    52 => ResetDailyQuests { rem: Rem },
+    53 => PetChangeFormMsg { rem: Rem },
+    55 => InvResult { rem: Rem },
+    56 => ChangeTrade { rem: Rem },
+    57 => Create { rem: Rem },
+    58 => QuestRedeem { rem: Rem },
+    59 => CreateGuild { rem: Rem },
+    60 => SetCondition { rem: Rem },
+    61 => Load { rem: Rem },
+    62 => Move { tick_id: U32, time: U32, rem: Rem },
+    63 => KeyInfoResponse { rem: Rem },
+    64 => Aoe { rem: Rem },
+    65 => GoToAck { rem: Rem },
+    66 => GlobalNotification { rem: Rem },
+    67 => Notification { rem: Rem },
+    68 => ArenaDeath { rem: Rem },
+    69 => ClientStat { rem: Rem },
+    74 => Hello { rem: Rem },
+    75 => Damage { target_id: U32, effects: ShortBytes, damage_amount: U16, killed: Bool, armor_piercing: Bool, bullet_id: U8, owner_id: U32 },
+    76 => ActivePetUpdate { rem: Rem },
+    77 => InvitedToGuild { rem: Rem },
+    78 => PetYardUpdate { rem: Rem },
+    79 => PasswordPrompt { rem: Rem },
+    80 => AcceptArenaDeath { rem: Rem },
+    81 => UpdateAck { rem: Rem },
+    82 => QuestObjectId { rem: Rem },
+    83 => Pic { rem: Rem },
+    84 => RealmHeroLeftMsg { rem: Rem },
+    85 => Buy { rem: Rem },
+    86 => TradeStart { rem: Rem },
+    87 => EvolvePet { rem: Rem },
+    88 => TradeRequested { rem: Rem },
+    89 => AoeAck { rem: Rem },
+    90 => PlayerHit { rem: Rem },
+    91 => CancelTrade { rem: Rem },
+    92 => MapInfo { width: U32, height: U32, name: String, display_name: String, realm_name: String, difficulty: F32, seed: U32, background: U32, allow_teleport: Bool, show_displays: Bool, unknown_bool: Bool, max_players: U16, game_opened_time: U32, build_version: String, unknown_int: U32, dungeon_mods: String },
+    93 => LoginRewardMsg { rem: Rem },
+    94 => KeyInfoRequest { rem: Rem },
+    95 => InvSwap { rem: Rem },
+    96 => QuestRedeemResponse { rem: Rem },
+    97 => ChooseName { rem: Rem },
+    98 => QuestFetchAsk { rem: Rem },
+    99 => AccountList { rem: Rem },
+    100 => ShootAck { rem: Rem },
+    101 => CreateSuccess { rem: Rem },
+    102 => CheckCredits { rem: Rem },
+    103 => GroundDamage { rem: Rem },
+    104 => GuildInvite { rem: Rem },
+    105 => Escape { rem: Rem },
+    106 => File { rem: Rem },
+    107 => ReskinUnlock { rem: Rem },
+    108 => NewCharacterInfo { rem: Rem },
+    109 => UnlockInfo { rem: Rem },
+    112 => QueueInfo { rem: Rem },
+    113 => QueueCancel { rem: Rem },
+    114 => ExaltationBonusChanged { rem: Rem },
+    115 => RedeemExaltationReward { rem: Rem },
+    117 => VaultUpdate { rem: Rem },
+    118 => ForgeRequest { rem: Rem },
+    119 => ForgeResult { rem: Rem },
+    120 => ForgeUnlockedBlueprints { rem: Rem },
+    121 => ShootAckCounter { rem: Rem },
+    122 => ChangeAllyShoot { rem: Rem },
+    123 => GetPlayersListMessage { rem: Rem },
+    124 => ModeratorActionMessage { rem: Rem },
+    126 => CreepMoveMessage { rem: Rem },
+    134 => Unknown134 { rem: Rem },
+    137 => Dash { rem: Rem },
+    138 => DashAck { rem: Rem },
+    139 => Unknown139 { rem: Rem },
+    145 => Unknown145 { rem: Rem },
+    146 => Unknown146 { rem: Rem },
+    147 => Unknown147 { rem: Rem },
+    149 => ClaimBattlePass { rem: Rem },
+    150 => ClaimBPMilestoneResult { rem: Rem },
+    154 => ConvertSeasonal { rem: Rem },
+    159 => Emote { rem: Rem },
+    163 => Unknown163 { rem: Rem },
+    164 => Unknown164 { rem: Rem },
+    165 => Unknown165 { rem: Rem },
+    166 => Stasis { rem: Rem },
+    169 => Unknown169 { rem: Rem },
+}
+
+
+/**
+ * How a packet field is encoded, used to describe packets to tools outside of realm-stat
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub enum FieldKind {
+    U8,
+    U16,
+    U32,
+    F32,
+    Bool,
+    /// utf8 string preceded by its length as a u16
+    String,
+    /// Bytes preceded by their length as a u8
+    ShortBytes,
+    /// Every byte left in the packet
+    Rest,
+    /// Every byte left in the packet, which hasn't been worked out how to decode yet
+    Rem,
+}
+
+
+/**
+ * A packet type with its own RotmgPacket variant
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct PacketType {
+    pub type_num: u8,
+    pub name: &'static str,
+    /// Fields in the order they are read
+    pub fields: &'static [(&'static str, FieldKind)],
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(type_num: u8, body: &[u8]) -> Result<RotmgPacket, ()> {
+        let mut bytes = (body.len() as u32 + 5).to_be_bytes().to_vec();
+        bytes.push(type_num);
+        bytes.extend_from_slice(body);
+        RotmgPacket::try_from(ByteBuffer::new(bytes))
+    }
+
+    /// Every type number with its own variant, so a row missing from the table is caught
+    const KNOWN_TYPES: &[u8] = &[
+        0, 1, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24,
+        25, 26, 27, 28, 30, 31, 33, 34, 35, 36, 37, 38, 39, 40, 41, 42, 44, 45, 46, 47, 48, 49, 50, 51,
+        52, 53, 55, 56, 57, 58, 59, 60, 61, 62, 63, 64, 65, 66, 67, 68, 69, 74, 75, 76, 77, 78, 79, 80,
+        81, 82, 83, 84, 85, 86, 87, 88, 89, 90, 91, 92, 93, 94, 95, 96, 97, 98, 99, 100, 101, 102, 103, 104,
+        105, 106, 107, 108, 109, 112, 113, 114, 115, 117, 118, 119, 120, 121, 122, 123, 124, 126, 134, 137, 138, 139, 145, 146,
+        147, 149, 150, 154, 159, 163, 164, 165, 166, 169,
+    ];
+
+    #[test]
+    fn type_numbers_round_trip() {
+        for type_num in 0..=u8::MAX {
+            //zeroes decode as empty strings and byte lists
+            let p = decode(type_num, &[0; 64]).unwrap();
+            assert_eq!(p.type_num(), type_num);
+            assert_eq!(p.type_name() != "Other", KNOWN_TYPES.contains(&type_num), "type {}", type_num);
+        }
+        assert_eq!(decode(22, &[]).unwrap().type_name(), "BuyResult");
+        assert_eq!(decode(23, &[]).unwrap().type_name(), "HatchPet");
+        assert_eq!(decode(200, &[]).unwrap().type_name(), "Other");
+    }
+
+    #[test]
+    fn packet_types_match_decoding() {
+        for t in PACKET_TYPES {
+            let p = decode(t.type_num, &[0; 64]).unwrap();
+            assert_eq!((p.type_num(), p.type_name()), (t.type_num, t.name));
+        }
+        let new_tick = PACKET_TYPES.iter().find(|t| t.type_num == 10).unwrap();
+        assert_eq!(new_tick.fields[0], ("tick_id", FieldKind::U32));
+        assert_eq!(new_tick.fields.last(), Some(&("rem", FieldKind::Rem)));
+        assert_eq!(PACKET_TYPES.iter().find(|t| t.type_num == 62).map(|t| t.name), Some("Move"));
+    }
+
+    #[test]
+    fn decodes_fields_in_order() {
+        let body = [0, 0, 0, 7, 2, 0xaa, 0xbb, 0x02, 0x58, 1, 0, 3, 0, 0, 0, 9];
+        match decode(75, &body).unwrap() {
+            RotmgPacket::Damage { target_id, effects, damage_amount, killed, armor_piercing, bullet_id, owner_id } => {
+                assert_eq!((target_id, effects, damage_amount, killed, armor_piercing, bullet_id, owner_id), (7, vec![0xaa, 0xbb], 600, true, false, 3, 9));
+            },
+            p => panic!("decoded as {}", p.type_name()),
+        }
+        match decode(10, &[0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 4, 0xff]).unwrap() {
+            RotmgPacket::NewTick { tick_id, server_prev_time, rem, .. } => {
+                assert_eq!((tick_id, server_prev_time), (1, 4));
+                assert_eq!(rem.rem_to_vec(), vec![0xff]);
+            },
+            p => panic!("decoded as {}", p.type_name()),
+        }
+        assert!(decode(44, &[0, 5, b'a']).is_err());
+    }
 
-            _ => Other { type_num: packet_type, rem: buf},
-        })
+    #[test]
+    fn serializes_without_rem_bytes() {
+        let json = serde_json::to_value(decode(10, &[0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 4, 0xff]).unwrap()).unwrap();
+        assert_eq!(json, serde_json::json!({"NewTick": {"tick_id": 1, "tick_time": 2, "server_current_time": 3, "server_prev_time": 4}}));
+        let p: RotmgPacket = serde_json::from_value(json).unwrap();
+        assert_eq!(p.type_name(), "NewTick");
+        assert_eq!(serde_json::to_value(decode(200, &[1, 2]).unwrap()).unwrap(), serde_json::json!({"Other": {"type_num": 200}}));
     }
 }
@@ -1,10 +1,10 @@
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, VecDeque};
 use byteorder::{BigEndian, ByteOrder};
 use crate::rc4::Rc4;
-use super::{rotmg_packet::RotmgPacket, byte_buffer::ByteBuffer, rotmg_packet_stitcher::StitchedPacket};
+use super::{rotmg_packet::RotmgPacket, byte_buffer::ByteBuffer, rotmg_packet_stitcher::StitchedPacket, packet_envelope::{PacketEnvelope, AlignmentState, CipherEvent}};
 
 
-const IKEY: [u8; 13] = [0xc9, 0x1d, 0x9e, 0xec, 0x42, 0x01, 0x60, 0x73, 0x0d, 0x82, 0x56, 0x04, 0xe0];
+pub(super) const IKEY: [u8; 13] = [0xc9, 0x1d, 0x9e, 0xec, 0x42, 0x01, 0x60, 0x73, 0x0d, 0x82, 0x56, 0x04, 0xe0];
 const _OKEY: [u8; 13] = [0x5a, 0x4d, 0x20, 0x16, 0xbc, 0x16, 0xdc, 0x64, 0x88, 0x31, 0x94, 0xff, 0xd9];
 
 
@@ -17,22 +17,30 @@ const _OKEY: [u8; 13] = [0x5a, 0x4d, 0x20, 0x16, 0xbc, 0x16, 0xdc, 0x64, 0x88, 0
  */
 pub struct RotmgPacketConstructor {
     iqueue: VecDeque<StitchedPacket>,
-    pub oqueue: VecDeque<RotmgPacket>,
+    pub oqueue: VecDeque<PacketEnvelope>,
+    pub events: VecDeque<CipherEvent>,
 
     pub cipher: Rc4,
     current_tick: Option<u32>,
+    aligned: bool,
 
     //For detecting duplicate tick packets
     old_tick_data: Option<ByteBuffer>,
+
+    //Number of packets of each type that failed to parse after decrypting
+    pub decode_failures: BTreeMap<u8, usize>,
 }
 impl RotmgPacketConstructor {
     pub fn new() -> Self {
         Self {
             iqueue: VecDeque::new(),
             oqueue: VecDeque::new(),
+            events: VecDeque::new(),
             cipher: Rc4::new(Vec::from(IKEY)),
             current_tick: None,
+            aligned: false,
             old_tick_data: None,
+            decode_failures: BTreeMap::new(),
         }
     }
 
@@ -41,25 +49,48 @@ impl RotmgPacketConstructor {
      * Wait until a tick packet has been received and validated before flushing the queue
      * If the tick packet couldn't be validated, go through realignment steps
      */
-    pub fn insert_packet(&mut self, packet: StitchedPacket, window: &tauri::Window) {
+    pub fn insert_packet(&mut self, packet: StitchedPacket) {
         self.iqueue.push_back(packet.clone());
         //log::debug!("Received packet: {:?}", packet);
         if packet.type_num == 10 { //NewTick packet type number
-            self.process_tick(packet, window);
+            self.process_tick(packet);
         } else if packet.type_num == 45 { //Reconnect packet type number
+            //the reconnect is the last packet sent with this cipher, so output it and everything before it before starting over
+            if self.aligned {
+                self.drain_queue(AlignmentState::Unverified);
+            }
             self.reset();
         }
     }
-    pub fn get_packet(&mut self) -> Option<RotmgPacket> {
+    pub fn get_packet(&mut self) -> Option<PacketEnvelope> {
         self.oqueue.pop_front()
     }
+    pub fn get_event(&mut self) -> Option<CipherEvent> {
+        self.events.pop_front()
+    }
+
+    /**
+     * Decrypts whatever is left in the input queue with the current cipher and moves it to the output queue.
+     * No tick packet arrived after these packets to verify the cipher against, so they are output as unverified.
+     * Packets that fail to parse are dropped, and nothing is output if the cipher was never aligned.
+     * 
+     * Call this once the stream has ended (pcap eof or the capture being stopped).
+     */
+    pub fn finalize(&mut self) {
+        if self.aligned == false {
+            //log::debug!("Cipher not aligned, discarding {} pending packets", self.iqueue.len());
+            self.iqueue.clear();
+            return;
+        }
+        self.drain_queue(AlignmentState::Unverified);
+    }
 
     /**
      * Runs every time a tick packet is received to check the validity of the tick packet.
      * If the tick packet is valid, each packet in the queue will be decrypted and flushed to the output queue
      * If the tick is invalid, try to realign the cipher
      */
-    fn process_tick(&mut self, tick: StitchedPacket, window: &tauri::Window) {
+    fn process_tick(&mut self, tick: StitchedPacket) {
         //Sometimes duplicate packets arrive
         //If we get a duplicate tick, clear the entire queue
         if let Some(old_tick) = &self.old_tick_data {
@@ -82,26 +113,43 @@ impl RotmgPacketConstructor {
             Some(t) => {
                 if t == new_tick {
                     //alignment is all good
-                    self.drain_queue();
+                    self.drain_queue(AlignmentState::Aligned);
                 } else {
                     //need to realign
-                    window.emit("cipher-misaligned", ()).unwrap();
-                    self.try_realign(tick.data, window);
+                    self.aligned = false;
+                    self.events.push_back(CipherEvent::Misaligned);
+                    self.try_realign(tick.data);
                 }
             },
-            None => self.try_realign(tick.data, window),
+            None => self.try_realign(tick.data),
         }
     }
 
-    fn drain_queue(&mut self) {
+    /**
+     * Decrypts every packet in the input queue and pushes the ones that parse to the output queue.
+     * Envelopes are output with index 0, the packet factory numbers them as they leave.
+     */
+    fn drain_queue(&mut self, alignment: AlignmentState) {
         //log::debug!("Draining queue");
         for p in self.iqueue.drain(..) {
-            let data = ByteBuffer::new(self.cipher.apply_keystream(5, &p.data.to_vec()));
-            if let Ok(rp) = RotmgPacket::try_from(data) {
+            let decrypted = self.cipher.apply_keystream(5, &p.data.to_vec());
+            let body = decrypted[5..].to_vec();
+            if let Ok(rp) = RotmgPacket::try_from(ByteBuffer::new(decrypted)) {
                 //log::debug!("{:?}", rp);
-                self.oqueue.push_back(rp);
+                self.oqueue.push_back(PacketEnvelope {
+                    index: 0,
+                    timestamp: p.meta.timestamp,
+                    connection: p.meta.connection,
+                    tcp_seq: p.meta.tcp_seq,
+                    frame: p.meta.frame,
+                    encrypted_len: p.data.len(),
+                    alignment,
+                    packet: rp,
+                    data: body,
+                });
             } else {
                 log::debug!("Error constructing packet");
+                *self.decode_failures.entry(p.type_num).or_insert(0) += 1;
             }
         }
     }
@@ -113,7 +161,7 @@ impl RotmgPacketConstructor {
      * 
      * Perhaps attempt to realign in a separate thread to allow reset packets to be processed to reset the cipher. Queueing all packets while realigning may cause memory issues.
      */
-    fn try_realign(&mut self, tick_data: ByteBuffer, window: &tauri::Window) {
+    fn try_realign(&mut self, tick_data: ByteBuffer) {
         //try twice to realign the cipher, resetting in between
         //If the alignment fails twice the correct keystream must be > 100 million bytes in
         //How damn long do you have to be playing in the same area for that to be true
@@ -133,14 +181,20 @@ impl RotmgPacketConstructor {
         }
         self.cipher.reverse(self.iqueue.iter().take(self.iqueue.len()-1).map(|x| x.data.rem_len()).sum::<usize>());
 
-        window.emit("cipher-aligned", ()).unwrap();
-        self.drain_queue();
+        self.aligned = true;
+        self.events.push_back(CipherEvent::Aligned);
+        self.drain_queue(AlignmentState::Realigned);
+    }
+
+    pub fn is_aligned(&self) -> bool {
+        self.aligned
     }
 
     pub fn reset(&mut self) {
         self.cipher.reset();
         self.iqueue.clear();
         self.current_tick = None;
+        self.aligned = false;
         self.old_tick_data = None;
     }
 }
\ No newline at end of file
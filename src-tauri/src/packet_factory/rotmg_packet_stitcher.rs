@@ -2,25 +2,87 @@ use std::collections::VecDeque;
 
 use byteorder::ByteOrder;
 
-use super::byte_buffer::ByteBuffer;
+use super::{byte_buffer::ByteBuffer, packet_envelope::{Connection, FrameMeta}};
 
 
 
 pub struct RotmgPacketStitcher {
     iqueue: VecDeque<u8>,
     oqueue: VecDeque<StitchedPacket>,
+
+    //Tcp sequence number of the byte at the head of the input queue
+    head_seq: u32,
+    //Tcp sequence number the next segment of the connection should start at
+    next_seq: Option<(Connection, u32)>,
+
+    pub gaps: usize,
+    pub retransmissions: usize,
+}
+
+
+/**
+ * What the stitcher did with a segment
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentResult {
+    Stitched,
+    /// Every byte of the segment had already been received
+    Retransmission,
+    /// Bytes before the segment are missing, so packet boundaries are lost and the queue was cleared
+    Gap,
 }
 impl RotmgPacketStitcher {
     pub fn new() -> Self {
         Self {
             iqueue: VecDeque::new(),
             oqueue: VecDeque::new(),
+            head_seq: 0,
+            next_seq: None,
+            gaps: 0,
+            retransmissions: 0,
         }
     }
 
-    pub fn insert_packet(&mut self, data: &[u8]) {
+    /**
+     * Add a tcp payload to the input queue.
+     * Stitched packets take their timestamp and connection from the segment that completes them.
+     *
+     * Retransmitted bytes are skipped. If bytes are missing the queue is cleared, since the length of the next packet can't be known.
+     */
+    pub fn insert_packet(&mut self, data: &[u8], meta: FrameMeta) -> SegmentResult {
+        let mut data = data;
+        let mut seq = meta.tcp_seq;
+        if let Some((connection, expected)) = self.next_seq {
+            //a new connection starts a new stream, like after a Reconnect, so a partial packet from the old one can't be finished
+            if connection != meta.connection {
+                self.iqueue.clear();
+            } else {
+                let offset = seq.wrapping_sub(expected) as i32;
+                if offset > 0 {
+                    self.gaps += 1;
+                    self.iqueue.clear();
+                    self.next_seq = Some((meta.connection, seq.wrapping_add(data.len() as u32)));
+                    return SegmentResult::Gap
+                }
+                if offset < 0 {
+                    let overlap = offset.unsigned_abs() as usize;
+                    if overlap >= data.len() {
+                        self.retransmissions += 1;
+                        return SegmentResult::Retransmission
+                    }
+                    data = &data[overlap..];
+                    seq = expected;
+                }
+            }
+        }
+        self.next_seq = Some((meta.connection, seq.wrapping_add(data.len() as u32)));
+
+        if self.iqueue.is_empty() {
+            self.head_seq = seq;
+        }
         self.iqueue.extend(data.iter());
-        self.check_queue();
+        self.check_queue(meta);
+        SegmentResult::Stitched
     }
     pub fn get_packet(&mut self) -> Option<StitchedPacket> {
         self.oqueue.pop_front()
@@ -29,7 +91,7 @@ impl RotmgPacketStitcher {
     /**
      * Checks if the queue is long enough to construct another packet
      */
-    fn check_queue(&mut self) {
+    fn check_queue(&mut self, meta: FrameMeta) {
         loop {
             if self.iqueue.len() < 4 {
                 return
@@ -47,21 +109,61 @@ impl RotmgPacketStitcher {
             //Create a StitchedPacket and push it to the output queue
             let mut application_data = ByteBuffer::new(self.iqueue.drain(0..next_packet_len as usize).collect());
             let _ = application_data.read_n_bytes(4);
+            let meta = FrameMeta { tcp_seq: self.head_seq, ..meta };
+            self.head_seq = self.head_seq.wrapping_add(next_packet_len);
 
             if let Ok(t) = application_data.read_u8() {
-                self.oqueue.push_back(StitchedPacket { type_num: t, data: application_data });
+                self.oqueue.push_back(StitchedPacket { type_num: t, data: application_data, meta });
             }
         }
     }
 
+    /**
+     * Drop the partial packet in the input queue and accept the next segment wherever it starts, once packet boundaries are known again
+     */
+    pub fn resync(&mut self) {
+        self.iqueue.clear();
+        self.next_seq = None;
+    }
+
     pub fn reset(&mut self) {
         self.iqueue.clear();
         self.oqueue.clear();
+        self.next_seq = None;
+        self.gaps = 0;
+        self.retransmissions = 0;
     }
 }
 
 #[derive(Clone, Debug)]
 pub struct StitchedPacket {
     pub type_num: u8,
-    pub data: ByteBuffer
-}
\ No newline at end of file
+    pub data: ByteBuffer,
+    pub meta: FrameMeta,
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    fn meta(server: u8, tcp_seq: u32) -> FrameMeta {
+        let client = IpAddr::V4(Ipv4Addr::new(192, 168, 0, 2));
+        FrameMeta { timestamp: 0, connection: Connection::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, server)), 2050, client, 50000), tcp_seq, frame: 1 }
+    }
+
+    #[test]
+    fn new_connection_drops_partial_packet() {
+        let mut stitcher = RotmgPacketStitcher::new();
+        //the first 6 bytes of a 10 byte packet
+        stitcher.insert_packet(&[0, 0, 0, 10, 8, 1], meta(1, 100));
+        assert!(stitcher.get_packet().is_none());
+
+        let packet = [0, 0, 0, 7, 10, 2, 3];
+        assert_eq!(stitcher.insert_packet(&packet, meta(2, 5000)), SegmentResult::Stitched);
+        let stitched = stitcher.get_packet().unwrap();
+        assert_eq!((stitched.type_num, stitched.data.rem_to_vec(), stitched.meta.tcp_seq), (10, vec![2, 3], 5000));
+        assert!(stitcher.get_packet().is_none());
+    }
+}
@@ -0,0 +1,125 @@
+/*
+Builds the frames of an encrypted game server stream, for testing the packet factory and the capture loop
+*/
+use std::net::{IpAddr, Ipv4Addr};
+use crate::frame::GAME_PORT;
+use crate::rc4::Rc4;
+use super::byte_buffer::ByteBuffer;
+use super::packet_envelope::{AlignmentState, Connection, PacketEnvelope};
+use super::rotmg_packet::RotmgPacket;
+use super::rotmg_packet_constructor::IKEY;
+
+
+pub const SERVER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+pub const CLIENT: Ipv4Addr = Ipv4Addr::new(192, 168, 0, 2);
+const CLIENT_PORT: u16 = 50000;
+
+
+/**
+ * The packets a server sends on one connection, encrypted and framed as ethernet/ipv4/tcp frames
+ */
+pub struct TestStream {
+    cipher: Rc4,
+    server: Ipv4Addr,
+    seq: u32,
+    tick_id: u32,
+}
+impl TestStream {
+    pub fn new(server: Ipv4Addr) -> Self {
+        Self {
+            cipher: Rc4::new(Vec::from(IKEY)),
+            server,
+            seq: 1000,
+            tick_id: 100,
+        }
+    }
+
+    /**
+     * Encrypt a packet the way the server sends it, the header is left in the clear
+     */
+    pub fn packet(&mut self, type_num: u8, body: &[u8]) -> Vec<u8> {
+        let mut bytes = (body.len() as u32 + 5).to_be_bytes().to_vec();
+        bytes.push(type_num);
+        bytes.extend_from_slice(body);
+        self.cipher.apply_keystream(5, &bytes)
+    }
+
+    /**
+     * The next NewTick packet. Tick ids and times stay small enough for the cipher to be aligned to them.
+     */
+    pub fn tick(&mut self) -> Vec<u8> {
+        self.tick_id += 1;
+        let mut body = vec![];
+        body.extend_from_slice(&self.tick_id.to_be_bytes());
+        body.extend_from_slice(&200u32.to_be_bytes()); //tick_time
+        body.extend_from_slice(&(self.tick_id * 200).to_be_bytes()); //server_current_time
+        body.extend_from_slice(&0u16.to_be_bytes()); //server_prev_time
+        self.packet(10, &body)
+    }
+
+    /**
+     * A Reconnect packet sending the client to host
+     */
+    pub fn reconnect(&mut self, host: &str) -> Vec<u8> {
+        let mut body = vec![];
+        for s in ["Realm", host] {
+            body.extend_from_slice(&(s.len() as u16).to_be_bytes());
+            body.extend_from_slice(s.as_bytes());
+        }
+        body.extend_from_slice(&0u32.to_be_bytes()); //unknown
+        body.extend_from_slice(&(GAME_PORT as u32).to_be_bytes());
+        body.extend_from_slice(&(-2i32).to_be_bytes()); //game_id
+        self.packet(45, &body)
+    }
+
+    /**
+     * Wrap a tcp payload in an ethernet frame, continuing the connection's sequence numbers
+     */
+    pub fn frame(&mut self, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0u8; 12]; //mac addresses
+        frame.extend_from_slice(&0x0800u16.to_be_bytes());
+
+        frame.extend_from_slice(&[0x45, 0]);
+        frame.extend_from_slice(&(40 + payload.len() as u16).to_be_bytes());
+        frame.extend_from_slice(&[0, 0, 0x40, 0, 64, 6, 0, 0]); //id, don't fragment, ttl, tcp, checksum
+        frame.extend_from_slice(&self.server.octets());
+        frame.extend_from_slice(&CLIENT.octets());
+
+        frame.extend_from_slice(&GAME_PORT.to_be_bytes());
+        frame.extend_from_slice(&CLIENT_PORT.to_be_bytes());
+        frame.extend_from_slice(&self.seq.to_be_bytes());
+        frame.extend_from_slice(&[0, 0, 0, 0, 0x50, 0x18, 0xff, 0xff, 0, 0, 0, 0]); //ack, header length, psh ack, window, checksum, urgent
+        frame.extend_from_slice(payload);
+
+        self.seq = self.seq.wrapping_add(payload.len() as u32);
+        frame
+    }
+
+    /**
+     * Skip over payload bytes without framing them, as if the frames carrying them were lost
+     */
+    pub fn drop_bytes(&mut self, len: usize) {
+        self.seq = self.seq.wrapping_add(len as u32);
+    }
+}
+
+
+/**
+ * A packet from the server as the packet factory hands it out, decoded from its decrypted body
+ */
+pub fn envelope(index: usize, type_num: u8, body: Vec<u8>, timestamp: i64) -> PacketEnvelope {
+    let mut bytes = (body.len() as u32 + 5).to_be_bytes().to_vec();
+    bytes.push(type_num);
+    bytes.extend_from_slice(&body);
+    PacketEnvelope {
+        index,
+        timestamp,
+        connection: Connection::new(IpAddr::V4(SERVER), GAME_PORT, IpAddr::V4(CLIENT), CLIENT_PORT),
+        tcp_seq: 0,
+        frame: index + 1,
+        encrypted_len: bytes.len(),
+        alignment: AlignmentState::Aligned,
+        packet: RotmgPacket::try_from(ByteBuffer::new(bytes)).unwrap(),
+        data: body,
+    }
+}
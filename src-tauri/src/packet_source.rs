@@ -0,0 +1,442 @@
+/*
+Sources of captured frames for the sniffer: live devices, capture files, pipes, and frames held in memory
+*/
+use std::collections::HashMap;
+#[cfg(test)]
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::Duration;
+use pcap::{Active, BpfProgram, Capture, Device};
+use crate::capture_file::{CaptureFrame, CaptureReader};
+use crate::frame;
+use crate::settings::CaptureSettings;
+use crate::error::{self, Error};
+
+
+/// Frames read from a capture stream that can wait to be processed before the reader blocks
+const STREAM_QUEUE_SIZE: usize = 4096;
+
+/// Frames a file source skips over before returning so the sniffer isn't held up by a long run of filtered frames
+const FILTERED_FRAMES_PER_POLL: usize = 1000;
+
+/// How long a stream source waits for a frame before letting the sniffer check if it should stop
+const STREAM_POLL_TIMEOUT: Duration = Duration::from_millis(100);
+
+
+/**
+ * Result of asking a source for its next frame
+ */
+pub enum SourcePoll {
+    Frame(CaptureFrame),
+    /// Nothing arrived in time, ask again later
+    Idle,
+    /// The source has no more frames
+    End,
+    /// The source went back to an earlier point, so the session has to start over
+    Reset,
+}
+
+
+/**
+ * Anything the sniffer can read timestamped frames from
+ */
+pub trait PacketSource: Send {
+    /**
+     * Get the next frame. Sources that wait for frames should return Idle now and then so the sniffer can be stopped.
+     */
+    fn next(&mut self) -> io::Result<SourcePoll>;
+
+    /**
+     * Link type of every frame from this source, if it is known before reading them
+     */
+    fn linktype(&self) -> Option<u32> {
+        None
+    }
+
+    /**
+     * How much of the input has been read, for sources with a known size
+     */
+    fn progress(&self) -> Option<ReadProgress> {
+        None
+    }
+
+    /**
+     * Frame counts kept by the capture driver, for sources that have them
+     */
+    fn stats(&mut self) -> Option<SourceStats> {
+        None
+    }
+}
+
+
+/**
+ * Frame counts from libpcap
+ */
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+pub struct SourceStats {
+    /// Frames that passed the capture filter
+    pub received: u32,
+    /// Frames dropped because the capture buffer was full
+    pub kernel_drops: u32,
+    /// Frames dropped by the network interface or its driver
+    pub interface_drops: u32,
+}
+
+
+fn pcap_error(e: pcap::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e.to_string())
+}
+
+fn pcap_frame(linktype: u32, number: usize, p: pcap::Packet) -> CaptureFrame {
+    CaptureFrame {
+        number,
+        interface: 0,
+        linktype,
+        timestamp: p.header.ts.tv_sec as i64 * 1_000_000 + p.header.ts.tv_usec as i64,
+        original_len: p.header.len,
+        data: p.data.to_vec(),
+        comments: vec![],
+    }
+}
+
+
+/**
+ * Live capture from a network device, filtered to game traffic
+ */
+pub struct LiveSource {
+    cap: Capture<Active>,
+    linktype: u32,
+    frames: usize,
+}
+impl LiveSource {
+    pub fn open(device: Device, settings: &CaptureSettings) -> error::Result<Self> {
+        let mut cap = Capture::from_device(device)?
+            .immediate_mode(true)
+            .snaplen(settings.snaplen)
+            .buffer_size(settings.buffer_size)
+            .timeout(settings.timeout_ms)
+            .open()?;
+        let linktype = frame::linktype_from_dlt(cap.get_datalink().0);
+        cap.filter(&frame::capture_filter(linktype, settings), false).map_err(Error::bad_filter)?;
+        Ok(Self { cap, linktype, frames: 0 })
+    }
+}
+impl PacketSource for LiveSource {
+    fn next(&mut self) -> io::Result<SourcePoll> {
+        match self.cap.next_packet() {
+            Ok(p) => {
+                self.frames += 1;
+                Ok(SourcePoll::Frame(pcap_frame(self.linktype, self.frames, p)))
+            },
+            Err(pcap::Error::TimeoutExpired) => Ok(SourcePoll::Idle),
+            //a live capture keeps going after read errors
+            Err(e) => {
+                log::debug!("pcap error {}", e);
+                Ok(SourcePoll::Idle)
+            }
+        }
+    }
+
+    fn linktype(&self) -> Option<u32> {
+        Some(self.linktype)
+    }
+
+    fn stats(&mut self) -> Option<SourceStats> {
+        match self.cap.stats() {
+            Ok(s) => Some(SourceStats { received: s.received, kernel_drops: s.dropped, interface_drops: s.if_dropped }),
+            Err(e) => {
+                log::debug!("Error getting capture stats {}", e);
+                None
+            }
+        }
+    }
+}
+
+
+/**
+ * How far through its input a source is
+ */
+#[derive(Debug, Clone, Copy, serde::Serialize)]
+pub struct ReadProgress {
+    pub bytes_read: u64,
+    pub total_bytes: u64,
+}
+
+
+/**
+ * Counts the bytes read through it, so the position in a capture file is known without asking the reader
+ */
+struct CountingReader<R: Read> {
+    input: R,
+    count: Arc<AtomicU64>,
+}
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.input.read(buf)?;
+        self.count.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+
+/**
+ * Capture filter compiled for a single link type
+ */
+struct CompiledFilter(BpfProgram);
+//The program is never changed after it is compiled and owns its instructions, so it can be moved to the capture thread
+unsafe impl Send for CompiledFilter {}
+
+
+/**
+ * The capture filter from the settings, or the bpf override if there is one, applied to frames read from a file.
+ * It is compiled separately for each link type since pcapng files can mix them.
+ */
+pub struct FrameFilter {
+    settings: CaptureSettings,
+    filters: HashMap<u32, CompiledFilter>,
+}
+impl FrameFilter {
+    pub fn new(settings: &CaptureSettings) -> Self {
+        Self { settings: settings.clone(), filters: HashMap::new() }
+    }
+
+    pub fn matches(&mut self, frame: &CaptureFrame) -> io::Result<bool> {
+        if self.filters.contains_key(&frame.linktype) == false {
+            let filter = pcap::Capture::dead(pcap::Linktype(frame::dlt_from_linktype(frame.linktype)))
+                .and_then(|c| c.compile(&frame::capture_filter(frame.linktype, &self.settings), true))
+                .map_err(pcap_error)?;
+            self.filters.insert(frame.linktype, CompiledFilter(filter));
+        }
+        Ok(self.filters[&frame.linktype].0.filter(&frame.data))
+    }
+}
+
+
+/**
+ * Pcap or pcapng file read without libpcap so the read position can be reported.
+ * The capture filter is still applied, compiled separately for each link type since pcapng files can mix them.
+ * Frames keep their number in the file, so the numbers skip over the frames the filter rejects like they do in wireshark.
+ */
+pub struct FileSource {
+    reader: CaptureReader<CountingReader<BufReader<File>>>,
+    bytes_read: Arc<AtomicU64>,
+    total_bytes: u64,
+    filter: FrameFilter,
+}
+impl FileSource {
+    pub fn open(path: &Path, settings: &CaptureSettings) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let total_bytes = file.metadata()?.len();
+        let bytes_read = Arc::new(AtomicU64::new(0));
+        let reader = CaptureReader::new(CountingReader { input: BufReader::new(file), count: bytes_read.clone() })?;
+        Ok(Self {
+            reader,
+            bytes_read,
+            total_bytes,
+            filter: FrameFilter::new(settings),
+        })
+    }
+}
+impl PacketSource for FileSource {
+    fn next(&mut self) -> io::Result<SourcePoll> {
+        //Give the sniffer a chance to stop or report progress while skipping through frames that don't match
+        for _ in 0..FILTERED_FRAMES_PER_POLL {
+            let frame = match self.reader.next_frame()? {
+                Some(f) => f,
+                None => return Ok(SourcePoll::End)
+            };
+            if self.filter.matches(&frame)? {
+                return Ok(SourcePoll::Frame(frame))
+            }
+        }
+        Ok(SourcePoll::Idle)
+    }
+
+    fn progress(&self) -> Option<ReadProgress> {
+        Some(ReadProgress {
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            total_bytes: self.total_bytes,
+        })
+    }
+}
+
+
+/**
+ * Capture stream written to stdin or a named pipe, like the output of `tcpdump -w -`.
+ * Reading is done on its own thread since it blocks until the writer sends more data, so stopping doesn't have to wait for the writer.
+ */
+pub struct StreamSource {
+    frames: Receiver<CaptureFrame>,
+}
+impl StreamSource {
+    pub fn open(path: String) -> Self {
+        let (frame_tx, frames) = mpsc::sync_channel(STREAM_QUEUE_SIZE);
+        std::thread::spawn(move || {
+            let mut reader = match CaptureReader::open_stream(&path) {
+                Err(e) => {
+                    log::debug!("Error opening capture stream {:?}", e);
+                    return;
+                },
+                Ok(r) => r
+            };
+            loop {
+                match reader.next_frame() {
+                    Ok(Some(f)) => {
+                        //the receiver is gone once the capture is stopped
+                        if frame_tx.send(f).is_err() {
+                            break;
+                        }
+                    },
+                    Ok(None) => break,
+                    Err(e) => {
+                        log::debug!("Error reading capture stream {:?}", e);
+                        break;
+                    }
+                }
+            }
+        });
+        Self { frames }
+    }
+}
+impl PacketSource for StreamSource {
+    fn next(&mut self) -> io::Result<SourcePoll> {
+        match self.frames.recv_timeout(STREAM_POLL_TIMEOUT) {
+            Ok(f) => Ok(SourcePoll::Frame(f)),
+            Err(RecvTimeoutError::Timeout) => Ok(SourcePoll::Idle),
+            Err(RecvTimeoutError::Disconnected) => Ok(SourcePoll::End),
+        }
+    }
+}
+
+
+/**
+ * Frames held in memory, for feeding synthetic captures through the sniffer
+ */
+#[cfg(test)]
+pub struct MemorySource {
+    frames: VecDeque<CaptureFrame>,
+}
+#[cfg(test)]
+impl MemorySource {
+    pub fn new(frames: Vec<CaptureFrame>) -> Self {
+        Self { frames: frames.into() }
+    }
+}
+#[cfg(test)]
+impl PacketSource for MemorySource {
+    fn next(&mut self) -> io::Result<SourcePoll> {
+        match self.frames.pop_front() {
+            Some(f) => Ok(SourcePoll::Frame(f)),
+            None => Ok(SourcePoll::End),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_factory::RotmgPacketFactory;
+    use crate::packet_factory::packet_envelope::{AlignmentState, CipherEvent};
+    use crate::packet_factory::test_stream::{TestStream, SERVER};
+
+    /**
+     * A frame for each packet, except for the packets at the positions in lost which are left out of the capture
+     */
+    fn source(stream: &mut TestStream, packets: Vec<Vec<u8>>, lost: &[usize]) -> MemorySource {
+        let mut frames = vec![];
+        for (i, packet) in packets.into_iter().enumerate() {
+            if lost.contains(&i) {
+                stream.drop_bytes(packet.len());
+                continue
+            }
+            let data = stream.frame(&packet);
+            frames.push(CaptureFrame { number: frames.len() + 1, interface: 0, linktype: frame::LINKTYPE_ETHERNET, timestamp: i as i64, original_len: data.len() as u32, data, comments: vec![] });
+        }
+        MemorySource::new(frames)
+    }
+
+    /**
+     * Feed every frame of the source to the factory like the sniffer does, returning the packets and cipher events output
+     */
+    fn run(source: &mut MemorySource, factory: &mut RotmgPacketFactory, finalize: bool) -> (Vec<(&'static str, AlignmentState)>, Vec<CipherEvent>) {
+        let settings = CaptureSettings::default();
+        let mut frame_number = 0;
+        while let SourcePoll::Frame(f) = source.next().unwrap() {
+            frame_number += 1;
+            if let Ok((sliced, meta)) = frame::slice_frame(f.linktype, f.timestamp, frame_number, &f.data, &settings) {
+                factory.insert_packet(sliced, meta);
+            }
+        }
+        if finalize {
+            factory.finalize();
+        }
+        let mut packets = vec![];
+        while let Some(p) = factory.get_packet() {
+            packets.push((p.packet.type_name(), p.alignment));
+        }
+        let mut events = vec![];
+        while let Some(e) = factory.get_event() {
+            events.push(e);
+        }
+        (packets, events)
+    }
+
+    #[test]
+    fn aligns_to_ticks_and_finalizes() {
+        let mut stream = TestStream::new(SERVER);
+        let packets = vec![stream.packet(8, &[1]), stream.tick(), stream.packet(8, &[2]), stream.tick(), stream.packet(8, &[3])];
+        let mut factory = RotmgPacketFactory::new();
+        let (packets, events) = run(&mut source(&mut stream, packets, &[]), &mut factory, false);
+        assert_eq!(packets, vec![
+            ("Ping", AlignmentState::Realigned),
+            ("NewTick", AlignmentState::Realigned),
+            ("Ping", AlignmentState::Aligned),
+            ("NewTick", AlignmentState::Aligned),
+        ]);
+        assert_eq!(events, vec![CipherEvent::Aligned]);
+
+        //the last packet waits for a tick until the capture ends
+        factory.finalize();
+        assert_eq!(factory.get_packet().map(|p| (p.packet.type_name(), p.alignment)), Some(("Ping", AlignmentState::Unverified)));
+    }
+
+    #[test]
+    fn finalize_drops_packets_without_alignment() {
+        let mut stream = TestStream::new(SERVER);
+        let packets = vec![stream.packet(8, &[1]), stream.packet(8, &[2])];
+        let mut factory = RotmgPacketFactory::new();
+        let (packets, _) = run(&mut source(&mut stream, packets, &[]), &mut factory, true);
+        assert_eq!(packets, vec![]);
+        assert_eq!(factory.stats().packets_in, 2);
+    }
+
+    #[test]
+    fn resyncs_and_realigns_after_a_gap() {
+        let mut stream = TestStream::new(SERVER);
+        let packets = vec![
+            stream.tick(), stream.packet(8, &[1]), stream.tick(),
+            stream.packet(8, &[2]), stream.packet(8, &[3]),
+            stream.tick(), stream.packet(8, &[4]), stream.tick(),
+        ];
+        //the frame after the lost one shows the gap, so it can't be stitched either
+        let mut factory = RotmgPacketFactory::new();
+        let (packets, events) = run(&mut source(&mut stream, packets, &[3]), &mut factory, true);
+        assert_eq!(packets, vec![
+            ("NewTick", AlignmentState::Realigned),
+            ("Ping", AlignmentState::Aligned),
+            ("NewTick", AlignmentState::Aligned),
+            ("NewTick", AlignmentState::Realigned),
+            ("Ping", AlignmentState::Aligned),
+            ("NewTick", AlignmentState::Aligned),
+        ]);
+        assert_eq!(events, vec![CipherEvent::Aligned, CipherEvent::Misaligned, CipherEvent::Aligned]);
+        let stats = factory.stats();
+        assert_eq!((stats.tcp_gaps, stats.resyncs), (1, 1));
+    }
+}
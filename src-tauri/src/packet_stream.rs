@@ -0,0 +1,222 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use crate::packet_factory::packet_envelope::PacketEnvelope;
+
+
+/**
+ * Controls how often decoded packets are pushed to the ui and how far the ui is allowed to fall behind
+ */
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct StreamConfig {
+    /// Time between batches in milliseconds
+    pub interval_ms: u64,
+    /// Most packets sent in a single batch. When more than this are waiting, the oldest are dropped from the stream.
+    pub max_batch_size: usize,
+    /// Batches the ui can leave unacknowledged before packets are held back and coalesced into the next batch
+    pub max_unacked_batches: u64,
+}
+impl Default for StreamConfig {
+    fn default() -> Self {
+        Self {
+            interval_ms: 100,
+            max_batch_size: 1000,
+            max_unacked_batches: 4,
+        }
+    }
+}
+impl StreamConfig {
+    /**
+     * Check the config keeps packets flowing without the emitter thread spinning
+     */
+    pub fn validate(&self) -> Result<(), String> {
+        if self.interval_ms == 0 {
+            return Err("The stream interval must be at least 1 ms".to_string())
+        }
+        if self.max_batch_size == 0 || self.max_unacked_batches == 0 {
+            return Err("The batch size and unacknowledged batch limit must be at least 1".to_string())
+        }
+        Ok(())
+    }
+}
+
+
+/**
+ * Payload of the "packets" event
+ */
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct PacketBatch {
+    /// Pass this to the ack_packets command once the batch has been handled
+    pub batch_id: u64,
+    pub packets: Vec<PacketEnvelope>,
+    /// Packets left out of the stream since the last batch because the ui fell behind.
+    /// They are still in the session and can be pulled with fetch_packets.
+    pub dropped: usize,
+}
+
+
+struct StreamState {
+    config: StreamConfig,
+    pending: VecDeque<PacketEnvelope>,
+    dropped: usize,
+    next_batch_id: u64,
+    acked_batch_id: u64,
+    running: bool,
+}
+
+
+/**
+ * Pushes decoded packets to the ui as batched "packets" events instead of the ui polling for them.
+ * Packets are collected from the capture thread and emitted by a separate thread every config.interval_ms.
+ *
+ * The ui acknowledges each batch. If it stops keeping up, pending packets are coalesced into a single batch
+ * and anything past max_batch_size is dropped from the stream, so the webview is never flooded.
+ */
+#[derive(Clone)]
+pub struct PacketStream {
+    state: Arc<Mutex<StreamState>>,
+}
+impl PacketStream {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(StreamState {
+                config: StreamConfig::default(),
+                pending: VecDeque::new(),
+                dropped: 0,
+                next_batch_id: 1,
+                acked_batch_id: 0,
+                running: false,
+            }))
+        }
+    }
+
+    /**
+     * Start the emitter thread. Call stop before starting the stream again.
+     */
+    pub fn start(&self, window: tauri::Window) -> std::thread::JoinHandle<()> {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.running = true;
+            state.pending.clear();
+            state.dropped = 0;
+            state.acked_batch_id = state.next_batch_id - 1;
+        }
+        let stream = self.clone();
+        std::thread::spawn(move || {
+            loop {
+                let interval = stream.state.lock().unwrap().config.interval_ms;
+                std::thread::sleep(Duration::from_millis(interval));
+
+                let running = stream.state.lock().unwrap().running;
+                stream.emit_batch(&window, running == false);
+                if running == false { break }
+            }
+        })
+    }
+
+    /**
+     * Signal the emitter thread to send whatever is pending and exit
+     */
+    pub fn stop(&self) {
+        self.state.lock().unwrap().running = false;
+    }
+
+    /**
+     * Queue a packet to be sent in the next batch
+     */
+    pub fn push(&self, packet: PacketEnvelope) {
+        let mut state = self.state.lock().unwrap();
+        if state.running == false { return }
+        state.pending.push_back(packet);
+        //Coalesce by only keeping the newest packets
+        while state.pending.len() > state.config.max_batch_size {
+            state.pending.pop_front();
+            state.dropped += 1;
+        }
+    }
+
+    /**
+     * Forget the pending packets, for when the session they belong to is discarded
+     */
+    pub fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.pending.clear();
+        state.dropped = 0;
+    }
+
+    /**
+     * Mark every batch up to and including batch_id as handled by the ui.
+     * Batches that haven't been sent yet can't be acknowledged.
+     */
+    pub fn ack(&self, batch_id: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.acked_batch_id = state.acked_batch_id.max(batch_id).min(state.next_batch_id - 1);
+    }
+
+    pub fn config(&self) -> StreamConfig {
+        self.state.lock().unwrap().config
+    }
+
+    pub fn set_config(&self, config: StreamConfig) {
+        self.state.lock().unwrap().config = config;
+    }
+
+    /**
+     * Send the pending packets as a batch, unless the ui is too far behind.
+     * A final batch is always sent so nothing is left pending when the stream stops.
+     */
+    fn emit_batch(&self, window: &tauri::Window, is_final: bool) {
+        if let Some(batch) = self.take_batch(is_final) {
+            if let Err(e) = window.emit("packets", batch) {
+                log::debug!("Error emitting packet batch {:?}", e);
+            }
+        }
+    }
+
+    /**
+     * The next batch to send, None if nothing is pending or the ui is too far behind
+     */
+    fn take_batch(&self, is_final: bool) -> Option<PacketBatch> {
+        let mut state = self.state.lock().unwrap();
+        if state.pending.is_empty() && state.dropped == 0 { return None }
+
+        let unacked = state.next_batch_id - 1 - state.acked_batch_id;
+        if unacked >= state.config.max_unacked_batches && is_final == false {
+            //log::debug!("UI is {} batches behind, holding packets back", unacked);
+            return None
+        }
+
+        let batch = PacketBatch {
+            batch_id: state.next_batch_id,
+            packets: state.pending.drain(..).collect(),
+            dropped: state.dropped,
+        };
+        state.next_batch_id += 1;
+        state.dropped = 0;
+        Some(batch)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_factory::test_stream;
+
+    #[test]
+    fn ignores_acks_for_batches_not_sent_yet() {
+        let stream = PacketStream::new();
+        stream.state.lock().unwrap().running = true;
+        stream.set_config(StreamConfig { max_unacked_batches: 1, ..Default::default() });
+
+        stream.ack(100);
+        stream.push(test_stream::envelope(0, 8, vec![], 0));
+        assert_eq!(stream.take_batch(false).map(|b| b.batch_id), Some(1));
+        //the ack before batch 1 was sent doesn't count for it, so the ui is a batch behind
+        stream.push(test_stream::envelope(1, 8, vec![], 0));
+        assert!(stream.take_batch(false).is_none());
+        stream.ack(1);
+        assert_eq!(stream.take_batch(false).map(|b| b.batch_id), Some(2));
+    }
+}
@@ -39,9 +39,16 @@ impl Rc4 {
         }
     }
 
-    pub fn from_string_key(key: String) -> Self {
-        //turn hex string into bytes then call new
-        todo!()
+    /**
+     * Construct a new Rc4 cipher from a key written as hex
+     */
+    pub fn from_string_key(key: &str) -> Result<Self, hex::FromHexError> {
+        let key = hex::decode(key.trim())?;
+        //an empty key can't seed the state
+        if key.is_empty() {
+            return Err(hex::FromHexError::InvalidStringLength)
+        }
+        Ok(Self::new(key))
     }
 
     pub fn skip(&mut self, amount: usize) {
@@ -0,0 +1,145 @@
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::capture_file::pcap::PcapWriter;
+
+
+/**
+ * Settings for recording live captures to pcap files
+ */
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct RecorderConfig {
+    pub enabled: bool,
+    /// Where recordings are written, set to the app data directory on startup
+    pub directory: PathBuf,
+    /// Start a new file once the current one reaches this many bytes, 0 for no limit
+    pub max_file_size: u64,
+    /// Start a new file whenever the client is sent to a new server
+    pub rotate_on_reconnect: bool,
+}
+impl Default for RecorderConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            directory: std::env::temp_dir().join("realm-stat-recordings"),
+            max_file_size: 256 * 1024 * 1024,
+            rotate_on_reconnect: true,
+        }
+    }
+}
+
+
+/**
+ * A recording listed in the ui, which can be replayed with start_pcap
+ */
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct RecordingInfo {
+    pub name: String,
+    pub path: PathBuf,
+    pub size: u64,
+    /// Last modified time in seconds since the unix epoch
+    pub modified: u64,
+}
+
+
+/**
+ * Writes every frame of a live capture to pcap files so decoding problems can be reproduced later.
+ * Files are named after the time the recording started and numbered as they rotate.
+ */
+pub struct Recorder {
+    config: RecorderConfig,
+    linktype: u32,
+    started: u64,
+    part: usize,
+    writer: Option<PcapWriter<BufWriter<File>>>,
+}
+impl Recorder {
+    pub fn start(config: RecorderConfig, linktype: u32) -> io::Result<Self> {
+        std::fs::create_dir_all(&config.directory)?;
+        let mut recorder = Self {
+            config,
+            linktype,
+            started: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+            part: 0,
+            writer: None,
+        };
+        recorder.rotate()?;
+        Ok(recorder)
+    }
+
+    /**
+     * Write a captured frame, starting a new file first if the current one is full
+     */
+    pub fn write(&mut self, timestamp: i64, original_len: u32, data: &[u8]) -> io::Result<()> {
+        let full = match &self.writer {
+            Some(w) => self.config.max_file_size > 0 && w.bytes_written() >= self.config.max_file_size,
+            None => true,
+        };
+        if full {
+            self.rotate()?;
+        }
+        self.writer.as_mut().unwrap().write_frame(timestamp, original_len, data)
+    }
+
+    /**
+     * Called when a Reconnect packet is seen
+     */
+    pub fn on_reconnect(&mut self) -> io::Result<()> {
+        if self.config.rotate_on_reconnect {
+            self.rotate()?;
+        }
+        Ok(())
+    }
+
+    /**
+     * Close the current file and open the next one
+     */
+    pub fn rotate(&mut self) -> io::Result<()> {
+        self.finish()?;
+        self.part += 1;
+        let path = self.config.directory.join(format!("capture-{}-{}.pcap", self.started, self.part));
+        self.writer = Some(PcapWriter::create(&path, self.linktype)?);
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        if let Some(mut w) = self.writer.take() {
+            w.flush()?;
+        }
+        Ok(())
+    }
+}
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+
+/**
+ * Every pcap file in the recording directory, newest first
+ */
+pub fn list_recordings(config: &RecorderConfig) -> io::Result<Vec<RecordingInfo>> {
+    let mut recordings = vec![];
+    if config.directory.exists() == false {
+        return Ok(recordings)
+    }
+    for entry in std::fs::read_dir(&config.directory)? {
+        let entry = entry?;
+        let path = entry.path();
+        if path.extension().map(|e| e == "pcap").unwrap_or(false) == false {
+            continue
+        }
+        let metadata = entry.metadata()?;
+        recordings.push(RecordingInfo {
+            name: entry.file_name().to_string_lossy().to_string(),
+            path,
+            size: metadata.len(),
+            modified: metadata.modified().ok().and_then(|t| t.duration_since(UNIX_EPOCH).ok()).map(|d| d.as_secs()).unwrap_or(0),
+        });
+    }
+    recordings.sort_by(|a, b| b.modified.cmp(&a.modified));
+    Ok(recordings)
+}
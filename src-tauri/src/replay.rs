@@ -0,0 +1,230 @@
+/*
+Paced replay of capture files, so recordings can be watched like a live capture
+*/
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use crate::capture_file::CaptureFrame;
+use crate::packet_source::{FileSource, PacketSource, ReadProgress, SourcePoll};
+use crate::settings::CaptureSettings;
+
+
+/// Longest the replay sleeps before letting the sniffer check if it should stop
+const MAX_WAIT: Duration = Duration::from_millis(50);
+/// Slowest replay speed, slower speeds would put frames due further out than the clock can represent
+const MIN_SPEED: f64 = 0.01;
+
+
+/**
+ * Where a replay is and how it is being played, shown in the ui
+ */
+#[derive(Debug, Clone, Copy, Default, serde::Serialize, serde::Deserialize)]
+pub struct ReplayStatus {
+    /// Multiple of the captured speed, 0 replays as fast as possible
+    pub speed: f64,
+    pub paused: bool,
+    /// Timestamp of the first frame in microseconds since the unix epoch, once it has been read
+    pub start: Option<i64>,
+    /// Timestamp of the last frame handed to the sniffer
+    pub position: Option<i64>,
+    /// Timestamp being seeked to, frames are decoded as fast as possible until it is reached
+    pub seeking: Option<i64>,
+}
+
+
+/**
+ * Shared handle for controlling a replay from tauri commands while the capture thread reads from it
+ */
+#[derive(Clone)]
+pub struct ReplayControl {
+    status: Arc<Mutex<ReplayStatus>>,
+    seek_request: Arc<Mutex<Option<i64>>>,
+}
+impl ReplayControl {
+    pub fn new(speed: f64) -> Self {
+        Self {
+            status: Arc::new(Mutex::new(ReplayStatus { speed: valid_speed(speed), ..Default::default() })),
+            seek_request: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn status(&self) -> ReplayStatus {
+        *self.status.lock().unwrap()
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.status.lock().unwrap().paused = paused;
+    }
+
+    pub fn set_speed(&self, speed: f64) {
+        self.status.lock().unwrap().speed = valid_speed(speed);
+    }
+
+    /**
+     * Jump to a timestamp in microseconds since the unix epoch.
+     * Seeking backwards restarts the session from the beginning of the file, since the cipher has to be aligned from the start.
+     */
+    pub fn seek(&self, timestamp: i64) {
+        *self.seek_request.lock().unwrap() = Some(timestamp);
+    }
+
+    fn take_seek(&self) -> Option<i64> {
+        self.seek_request.lock().unwrap().take()
+    }
+}
+
+
+/**
+ * Speed a replay can be paced at. Anything that isn't a positive number replays as fast as possible.
+ */
+fn valid_speed(speed: f64) -> f64 {
+    if speed.is_finite() == false || speed <= 0.0 {
+        return 0.0
+    }
+    speed.max(MIN_SPEED)
+}
+
+
+/**
+ * Wraps a capture file source and hands out its frames at the pace they were captured, scaled by the replay speed
+ */
+pub struct ReplaySource {
+    path: PathBuf,
+    settings: CaptureSettings,
+    source: FileSource,
+    control: ReplayControl,
+    /// Frame read from the source that isn't due yet
+    pending: Option<CaptureFrame>,
+    /// Capture timestamp and wall clock time a frame was released at, with the speed at the time, to pace the following frames from
+    anchor: Option<(i64, Instant, f64)>,
+}
+impl ReplaySource {
+    pub fn open(path: PathBuf, settings: CaptureSettings, control: ReplayControl) -> io::Result<Self> {
+        let source = FileSource::open(&path, &settings)?;
+        Ok(Self {
+            path,
+            settings,
+            source,
+            control,
+            pending: None,
+            anchor: None,
+        })
+    }
+
+    /**
+     * Handle a seek requested since the last frame.
+     * Returns true if the file was reopened and the sniffer has to start a new session.
+     */
+    fn apply_seek(&mut self) -> io::Result<bool> {
+        let target = match self.control.take_seek() {
+            Some(t) => t,
+            None => return Ok(false)
+        };
+        self.anchor = None;
+        let position = {
+            let mut status = self.control.status.lock().unwrap();
+            status.seeking = Some(target);
+            status.position
+        };
+        if position.map(|p| target < p).unwrap_or(false) {
+            self.source = FileSource::open(&self.path, &self.settings)?;
+            self.pending = None;
+            self.control.status.lock().unwrap().position = None;
+            return Ok(true)
+        }
+        Ok(false)
+    }
+
+    /**
+     * Release a frame to the sniffer, recording it as the replay position
+     */
+    fn release(&mut self, frame: CaptureFrame) -> SourcePoll {
+        let mut status = self.control.status.lock().unwrap();
+        status.start = status.start.or(Some(frame.timestamp));
+        status.position = Some(frame.timestamp);
+        SourcePoll::Frame(frame)
+    }
+}
+impl PacketSource for ReplaySource {
+    fn next(&mut self) -> io::Result<SourcePoll> {
+        if self.apply_seek()? {
+            return Ok(SourcePoll::Reset)
+        }
+
+        let status = self.control.status();
+        if status.paused && status.seeking.is_none() {
+            //pacing starts over from the next frame when resumed
+            self.anchor = None;
+            std::thread::sleep(MAX_WAIT);
+            return Ok(SourcePoll::Idle)
+        }
+
+        let frame = match self.pending.take() {
+            Some(f) => f,
+            None => match self.source.next()? {
+                SourcePoll::Frame(f) => f,
+                other => return Ok(other)
+            }
+        };
+
+        //frames before the seek target are decoded without waiting
+        if let Some(target) = status.seeking {
+            if frame.timestamp < target {
+                return Ok(self.release(frame))
+            }
+            self.control.status.lock().unwrap().seeking = None;
+            if status.paused {
+                self.pending = Some(frame);
+                return Ok(SourcePoll::Idle)
+            }
+        }
+
+        if status.speed <= 0.0 {
+            return Ok(self.release(frame))
+        }
+        let (anchor_timestamp, anchor_time, anchor_speed) = match self.anchor {
+            Some(a) if a.2 == status.speed => a,
+            _ => {
+                self.anchor = Some((frame.timestamp, Instant::now(), status.speed));
+                return Ok(self.release(frame))
+            }
+        };
+
+        let offset = (frame.timestamp - anchor_timestamp).max(0) as f64 / anchor_speed;
+        let due = anchor_time + Duration::from_micros(offset as u64);
+        let now = Instant::now();
+        if due > now {
+            std::thread::sleep((due - now).min(MAX_WAIT));
+            if due > Instant::now() {
+                self.pending = Some(frame);
+                return Ok(SourcePoll::Idle)
+            }
+        }
+        Ok(self.release(frame))
+    }
+
+    fn linktype(&self) -> Option<u32> {
+        self.source.linktype()
+    }
+
+    fn progress(&self) -> Option<ReadProgress> {
+        self.source.progress()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_speeds() {
+        for (speed, valid) in [(2.0, 2.0), (f64::NAN, 0.0), (f64::INFINITY, 0.0), (-1.0, 0.0), (1e-300, MIN_SPEED)] {
+            assert_eq!(ReplayControl::new(speed).status().speed, valid);
+            let control = ReplayControl::new(1.0);
+            control.set_speed(speed);
+            assert_eq!(control.status().speed, valid);
+        }
+    }
+}
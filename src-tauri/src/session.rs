@@ -0,0 +1,546 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use crate::packet_factory::packet_envelope::PacketEnvelope;
+use crate::packet_factory::rotmg_packet::RotmgPacket;
+use crate::filter::{DisplayFilter, FilterError};
+
+
+/// Fewest packets kept in memory, below this nearly every packet fetched would be read back from disk
+const MIN_MEMORY_WINDOW: usize = 1_000;
+/// Most packets kept in memory, a few gigabytes of decoded packets
+const MAX_MEMORY_WINDOW: usize = 10_000_000;
+/// Packets read at a time when paging through a shared session
+const PAGE_SIZE: usize = 1_000;
+
+
+/**
+ * Filter used when fetching packets from a session.
+ * Every field is optional, an empty query matches every packet.
+ */
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct PacketQuery {
+    /// Packet type names to include, e.g. "NewTick"
+    pub types: Option<Vec<String>>,
+    /// Inclusive lower bound on the capture timestamp in microseconds since the unix epoch
+    pub start_time: Option<i64>,
+    /// Exclusive upper bound on the capture timestamp in microseconds since the unix epoch
+    pub end_time: Option<i64>,
+    /// Display filter expression, see filter.rs
+    pub filter: Option<String>,
+    /// Minutes the local time zone is ahead of utc, for comparing times of day in the display filter
+    pub utc_offset: i32,
+}
+impl PacketQuery {
+    /**
+     * Parse the display filter so the query can be matched against packets
+     */
+    pub fn matcher(&self) -> Result<PacketMatcher, FilterError> {
+        let filter = match self.filter.as_ref().filter(|f| f.trim().is_empty() == false) {
+            Some(f) => Some(DisplayFilter::parse(f)?.with_utc_offset(self.utc_offset)),
+            None => None
+        };
+        Ok(PacketMatcher { query: self.clone(), filter })
+    }
+
+    fn matches_bounds(&self, envelope: &PacketEnvelope) -> bool {
+        return self.matches_summary(envelope.packet.type_num(), envelope.timestamp)
+    }
+
+    /**
+     * Check the type and time bounds of the query, which only need the type number and timestamp of a packet
+     */
+    fn matches_summary(&self, type_num: u8, timestamp: i64) -> bool {
+        if let Some(types) = &self.types {
+            let type_name = RotmgPacket::type_name_of(type_num);
+            if types.iter().any(|t| t == type_name) == false {
+                return false
+            }
+        }
+        if let Some(start) = self.start_time {
+            if timestamp < start { return false }
+        }
+        if let Some(end) = self.end_time {
+            if timestamp >= end { return false }
+        }
+        return true
+    }
+}
+
+
+/**
+ * A packet query with its display filter parsed
+ */
+pub struct PacketMatcher {
+    query: PacketQuery,
+    filter: Option<DisplayFilter>,
+}
+impl PacketMatcher {
+    pub fn matches(&self, envelope: &PacketEnvelope) -> bool {
+        self.query.matches_bounds(envelope) && self.filter.as_ref().map(|f| f.matches(envelope)).unwrap_or(true)
+    }
+
+    /**
+     * True if the query only has type and time bounds, so packets can be matched without decoding them
+     */
+    fn is_bounds_only(&self) -> bool {
+        self.filter.is_none()
+    }
+
+    fn matches_everything(&self) -> bool {
+        self.is_bounds_only() && self.query.types.is_none() && self.query.start_time.is_none() && self.query.end_time.is_none()
+    }
+}
+
+
+/**
+ * A chunk of packets returned from a fetch.
+ * Pass next_cursor into the following fetch to continue where this one stopped.
+ */
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct PacketPage {
+    pub packets: Vec<PacketEnvelope>,
+    pub next_cursor: usize,
+    /// Number of packets in the session, matching or not
+    pub total: usize,
+}
+
+
+/**
+ * How much of a session is kept in memory
+ */
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct SessionConfig {
+    /// Number of most recent packets kept in memory, older packets are spilled to disk
+    pub memory_window: usize,
+    /// Where spill files are written, defaults to the system temp directory
+    pub spill_directory: Option<PathBuf>,
+}
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            memory_window: 50_000,
+            spill_directory: None,
+        }
+    }
+}
+impl SessionConfig {
+    /**
+     * Check the memory window is one packets can be kept in, and that the spill directory exists
+     */
+    pub fn validate(&self) -> Result<(), String> {
+        if self.memory_window < MIN_MEMORY_WINDOW || self.memory_window > MAX_MEMORY_WINDOW {
+            return Err(format!("The memory window must be between {} and {} packets", MIN_MEMORY_WINDOW, MAX_MEMORY_WINDOW))
+        }
+        if let Some(directory) = &self.spill_directory {
+            if directory.is_dir() == false {
+                return Err(format!("Spill directory {} doesn't exist", directory.display()))
+            }
+        }
+        Ok(())
+    }
+}
+
+
+/**
+ * Every packet decoded during a capture session, in the order they were output by the packet factory.
+ * 
+ * Only the newest config.memory_window packets are kept in memory.
+ * Older packets are appended to a spill file on disk and read back transparently when fetched,
+ * so memory usage stays flat no matter how long the session runs.
+ */
+pub struct Session {
+    /// Changes whenever the session is cleared, so pages read before and after can't be mixed up
+    id: usize,
+    config: SessionConfig,
+    memory: VecDeque<PacketEnvelope>,
+    spill: Option<SpillFile>,
+}
+impl Session {
+    pub fn new(config: SessionConfig) -> Self {
+        Self {
+            id: next_session_id(),
+            config,
+            memory: VecDeque::new(),
+            spill: None,
+        }
+    }
+
+    pub fn push(&mut self, packet: PacketEnvelope) {
+        self.memory.push_back(packet);
+        while self.memory.len() > self.config.memory_window {
+            if let Err(e) = self.spill_oldest() {
+                //Keep everything in memory rather than lose packets
+                log::debug!("Error spilling packets to disk {:?}", e);
+                break;
+            }
+        }
+    }
+
+    /**
+     * Remove every packet from the session, deleting the spill file
+     */
+    pub fn clear(&mut self) {
+        self.id = next_session_id();
+        self.memory.clear();
+        self.spill = None;
+    }
+
+    /**
+     * Number of packets in the session, including the ones spilled to disk
+     */
+    pub fn len(&self) -> usize {
+        self.spilled_len() + self.memory.len()
+    }
+
+    pub fn config(&self) -> SessionConfig {
+        self.config.clone()
+    }
+
+    /**
+     * Takes effect on the next packet pushed. A new spill directory is only used once the session is cleared.
+     */
+    pub fn set_config(&mut self, config: SessionConfig) {
+        self.config = config;
+    }
+
+    /**
+     * Returns up to limit packets matching the query, starting at the cursor position in the session
+     */
+    pub fn fetch(&mut self, cursor: usize, limit: usize, query: &PacketMatcher) -> PacketPage {
+        let total = self.len();
+        let mut packets = vec![];
+        let mut next_cursor = cursor.min(total);
+        for p in self.iter_from(cursor) {
+            if packets.len() >= limit { break }
+            next_cursor += 1;
+            if query.matches(&p) {
+                packets.push(p);
+            }
+        }
+        PacketPage { packets, next_cursor, total }
+    }
+
+    /**
+     * Number of packets in the session matching the query.
+     * Queries without a display filter are counted from the spill index, without reading the spilled packets.
+     */
+    pub fn count(&mut self, query: &PacketMatcher) -> usize {
+        if query.matches_everything() {
+            return self.len()
+        }
+        if query.is_bounds_only() {
+            let spilled = match self.spill.as_mut() {
+                Some(spill) => spill.count_matching(|type_num, timestamp| query.query.matches_summary(type_num, timestamp)),
+                None => Ok(0)
+            };
+            match spilled {
+                Ok(spilled) => return spilled + self.memory.iter().filter(|p| query.matches(p)).count(),
+                Err(e) => log::debug!("Error reading spill index {:?}", e)
+            }
+        }
+        self.iter_from(0).filter(|p| query.matches(p)).count()
+    }
+
+    /**
+     * Iterate over every packet in the session starting at position start, reading from the spill file first if needed
+     */
+    pub fn iter_from(&mut self, start: usize) -> Box<dyn Iterator<Item = PacketEnvelope> + '_> {
+        let spilled_len = self.spilled_len();
+        let memory = self.memory.iter().skip(start.saturating_sub(spilled_len)).cloned();
+        if start >= spilled_len {
+            return Box::new(memory)
+        }
+        match self.spill.as_mut().unwrap().read_from(start) {
+            Ok(spilled) => Box::new(spilled.chain(memory)),
+            Err(e) => {
+                log::debug!("Error reading spill file {:?}", e);
+                Box::new(memory)
+            }
+        }
+    }
+
+    fn spilled_len(&self) -> usize {
+        self.spill.as_ref().map(|s| s.len).unwrap_or(0)
+    }
+
+    fn spill_oldest(&mut self) -> io::Result<()> {
+        if self.spill.is_none() {
+            let directory = self.config.spill_directory.clone().unwrap_or(std::env::temp_dir());
+            self.spill = Some(SpillFile::create(&directory)?);
+        }
+        let packet = self.memory.front().unwrap();
+        self.spill.as_mut().unwrap().append(packet)?;
+        self.memory.pop_front();
+        Ok(())
+    }
+}
+
+
+/// Bytes per packet in the spill index: data offset as a u64, timestamp as an i64 and type number
+const INDEX_RECORD_LEN: u64 = 17;
+
+
+fn next_session_id() -> usize {
+    static SESSION_COUNT: AtomicUsize = AtomicUsize::new(0);
+    SESSION_COUNT.fetch_add(1, Ordering::Relaxed)
+}
+
+
+/**
+ * The packets a shared session held when paging started, read a page at a time so the session is only locked briefly.
+ * Long exports don't hold up the capture thread or the ui this way.
+ * Ends early if the session is cleared or replaced.
+ */
+pub struct SessionPages {
+    session: Arc<Mutex<Session>>,
+    id: usize,
+    cursor: usize,
+    end: usize,
+    page: std::vec::IntoIter<PacketEnvelope>,
+}
+impl SessionPages {
+    pub fn new(session: Arc<Mutex<Session>>) -> Self {
+        let (id, end) = {
+            let session = session.lock().unwrap();
+            (session.id, session.len())
+        };
+        Self { session, id, cursor: 0, end, page: vec![].into_iter() }
+    }
+}
+impl Iterator for SessionPages {
+    type Item = PacketEnvelope;
+
+    fn next(&mut self) -> Option<PacketEnvelope> {
+        if let Some(p) = self.page.next() {
+            return Some(p)
+        }
+        if self.cursor >= self.end {
+            return None
+        }
+        let page: Vec<PacketEnvelope> = {
+            let mut session = self.session.lock().unwrap();
+            if session.id != self.id {
+                return None
+            }
+            session.iter_from(self.cursor).take(PAGE_SIZE.min(self.end - self.cursor)).collect()
+        };
+        //a spill file that can't be read ends the pages instead of asking for the same page again
+        if page.is_empty() {
+            return None
+        }
+        self.cursor += page.len();
+        self.page = page.into_iter();
+        self.page.next()
+    }
+}
+
+
+/**
+ * Append-only file of packets, each written as its envelope in json followed by its decrypted body, both prefixed with their length.
+ * A companion index file holds the byte offset of every packet for random access,
+ * along with its timestamp and type number so queries on those can skip decoding the packet, all big endian.
+ * Both files are deleted when the spill file is dropped.
+ */
+struct SpillFile {
+    data_path: PathBuf,
+    index_path: PathBuf,
+    data: BufWriter<File>,
+    index: BufWriter<File>,
+    data_len: u64,
+    len: usize,
+}
+impl SpillFile {
+    fn create(directory: &Path) -> io::Result<Self> {
+        static SPILL_COUNT: AtomicUsize = AtomicUsize::new(0);
+        let name = format!("realm-stat-{}-{}", std::process::id(), SPILL_COUNT.fetch_add(1, Ordering::Relaxed));
+        let data_path = directory.join(format!("{name}.spill"));
+        let index_path = directory.join(format!("{name}.idx"));
+        Ok(Self {
+            data: BufWriter::new(File::create(&data_path)?),
+            index: BufWriter::new(File::create(&index_path)?),
+            data_path,
+            index_path,
+            data_len: 0,
+            len: 0,
+        })
+    }
+
+    fn append(&mut self, packet: &PacketEnvelope) -> io::Result<()> {
+        let envelope = serde_json::to_vec(packet)?;
+        let mut record = Vec::with_capacity(8 + envelope.len() + packet.data.len());
+        record.write_u32::<BigEndian>(envelope.len() as u32)?;
+        record.extend_from_slice(&envelope);
+        record.write_u32::<BigEndian>(packet.data.len() as u32)?;
+        record.extend_from_slice(&packet.data);
+        self.data.write_all(&record)?;
+        self.index.write_u64::<BigEndian>(self.data_len)?;
+        self.index.write_i64::<BigEndian>(packet.timestamp)?;
+        self.index.write_u8(packet.packet.type_num())?;
+        self.data_len += record.len() as u64;
+        self.len += 1;
+        Ok(())
+    }
+
+    /**
+     * Open a reader positioned at the packet with the given position in the file
+     */
+    fn read_from(&mut self, start: usize) -> io::Result<SpillReader> {
+        self.data.flush()?;
+        self.index.flush()?;
+
+        let mut index = File::open(&self.index_path)?;
+        index.seek(SeekFrom::Start(start as u64 * INDEX_RECORD_LEN))?;
+        let offset = index.read_u64::<BigEndian>()?;
+
+        let mut data = BufReader::new(File::open(&self.data_path)?);
+        data.seek(SeekFrom::Start(offset))?;
+        Ok(SpillReader { data, remaining: self.len - start })
+    }
+
+    /**
+     * Count the spilled packets whose type number and timestamp pass the predicate, reading only the index
+     */
+    fn count_matching(&mut self, predicate: impl Fn(u8, i64) -> bool) -> io::Result<usize> {
+        self.index.flush()?;
+
+        let mut index = BufReader::new(File::open(&self.index_path)?);
+        let mut count = 0;
+        for _ in 0..self.len {
+            let _offset = index.read_u64::<BigEndian>()?;
+            let timestamp = index.read_i64::<BigEndian>()?;
+            let type_num = index.read_u8()?;
+            if predicate(type_num, timestamp) {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+}
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.data_path);
+        let _ = std::fs::remove_file(&self.index_path);
+    }
+}
+
+
+struct SpillReader {
+    data: BufReader<File>,
+    remaining: usize,
+}
+impl Iterator for SpillReader {
+    type Item = PacketEnvelope;
+
+    fn next(&mut self) -> Option<PacketEnvelope> {
+        if self.remaining == 0 { return None }
+        self.remaining -= 1;
+
+        match self.read_record() {
+            Ok(p) => Some(p),
+            Err(e) => {
+                log::debug!("Error reading spilled packet {:?}", e);
+                None
+            }
+        }
+    }
+}
+impl SpillReader {
+    fn read_record(&mut self) -> io::Result<PacketEnvelope> {
+        let mut envelope = vec![0; self.data.read_u32::<BigEndian>()? as usize];
+        self.data.read_exact(&mut envelope)?;
+        let mut packet: PacketEnvelope = serde_json::from_slice(&envelope)?;
+        packet.data = vec![0; self.data.read_u32::<BigEndian>()? as usize];
+        self.data.read_exact(&mut packet.data)?;
+        packet.redecode().map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("Spilled packet {} couldn't be decoded", packet.index)))?;
+        Ok(packet)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_factory::test_stream;
+
+    fn envelope(index: usize, type_num: u8) -> PacketEnvelope {
+        //long enough for the fields of a NewTick
+        let mut body = vec![0; 14];
+        body[13] = index as u8;
+        test_stream::envelope(index, type_num, body, index as i64 * 1_000_000)
+    }
+
+    fn query(types: Option<&[&str]>, start_time: Option<i64>, filter: Option<&str>) -> PacketMatcher {
+        PacketQuery {
+            types: types.map(|t| t.iter().map(|t| t.to_string()).collect()),
+            start_time,
+            filter: filter.map(|f| f.to_string()),
+            ..Default::default()
+        }.matcher().unwrap()
+    }
+
+    #[test]
+    fn counts_spilled_and_memory_packets() {
+        let mut session = Session::new(SessionConfig { memory_window: 3, spill_directory: None });
+        for i in 0..10 {
+            session.push(envelope(i, if i % 2 == 0 { 10 } else { 8 }));
+        }
+        assert_eq!(session.len(), 10);
+        assert_eq!(session.spilled_len(), 7);
+
+        assert_eq!(session.count(&query(None, None, None)), 10);
+        assert_eq!(session.count(&query(Some(&["NewTick"]), None, None)), 5);
+        assert_eq!(session.count(&query(Some(&["Ping"]), Some(4_000_000), None)), 3);
+        assert_eq!(session.count(&query(None, None, Some("type == NewTick && frame > 4"))), 3);
+        assert_eq!(session.count(&query(Some(&["Other"]), None, None)), 0);
+    }
+
+    #[test]
+    fn reads_spilled_packets_with_their_data_from_any_position() {
+        let mut session = Session::new(SessionConfig { memory_window: 2, spill_directory: None });
+        for i in 0..6 {
+            session.push(envelope(i, 10));
+        }
+        let packets = session.iter_from(1).map(|p| (p.index, p.data[13])).collect::<Vec<_>>();
+        assert_eq!(packets, vec![(1, 1), (2, 2), (3, 3), (4, 4), (5, 5)]);
+        //the body is kept out of what the ui is sent
+        assert!(serde_json::to_value(&envelope(0, 10)).unwrap().get("data").is_none());
+    }
+
+    #[test]
+    fn rejects_unusable_configs() {
+        assert!(SessionConfig::default().validate().is_ok());
+        assert!(SessionConfig { memory_window: 0, ..Default::default() }.validate().is_err());
+        assert!(SessionConfig { memory_window: usize::MAX, ..Default::default() }.validate().is_err());
+        let missing = std::env::temp_dir().join("realm-stat-missing-spill-directory");
+        assert!(SessionConfig { spill_directory: Some(missing), ..Default::default() }.validate().is_err());
+    }
+
+    #[test]
+    fn pages_through_the_packets_it_started_with() {
+        let session = Arc::new(Mutex::new(Session::new(SessionConfig { memory_window: 1_500, spill_directory: None })));
+        for i in 0..2_500 {
+            session.lock().unwrap().push(envelope(i, 10));
+        }
+        let mut pages = SessionPages::new(session.clone());
+        assert_eq!(pages.next().map(|p| p.index), Some(0));
+        //packets pushed once paging has started aren't included
+        session.lock().unwrap().push(envelope(2_500, 10));
+        assert_eq!(pages.map(|p| p.index).collect::<Vec<_>>(), (1..2_500).collect::<Vec<_>>());
+
+        let mut pages = SessionPages::new(session.clone());
+        assert!(pages.next().is_some());
+        session.lock().unwrap().clear();
+        for i in 0..2_000 {
+            session.lock().unwrap().push(envelope(i, 10));
+        }
+        //the rest of the page read before the session was cleared, then nothing from the new session
+        assert_eq!(pages.count(), PAGE_SIZE - 1);
+    }
+}
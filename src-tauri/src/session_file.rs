@@ -0,0 +1,358 @@
+/*
+Compact binary file holding a decoded session, so it can be reopened or shared without decoding the capture again.
+
+All numbers are big endian. The file starts with a header:
+    magic "RSTS", format version u16, packet count u64, index offset u64, created i64 (microseconds since the unix epoch),
+    name and protocol version as u16 length prefixed utf8
+followed by one record per packet:
+    index u64, timestamp i64, source address, destination address, tcp sequence u32, frame u64, encrypted length u32,
+    alignment u8, packet type u8, body length u32, decrypted body
+with each address written as an ip version u8 (4 or 6), the ip and a u16 port.
+The index at the end of the file holds the offset of every record as a u64.
+*/
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use crate::packet_factory::byte_buffer::ByteBuffer;
+use crate::packet_factory::packet_envelope::{AlignmentState, Connection, PacketEnvelope};
+use crate::packet_factory::rotmg_packet::RotmgPacket;
+
+
+const MAGIC: &[u8; 4] = b"RSTS";
+pub const FORMAT_VERSION: u16 = 1;
+/// Position of the packet count in the header, the index offset follows it
+const COUNT_OFFSET: u64 = 6;
+
+
+/**
+ * What a session file holds, shown in the ui once it is loaded
+ */
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SessionFileInfo {
+    pub name: String,
+    /// Build version of the game the session was captured from, empty if it was never sent
+    pub protocol_version: String,
+    /// Time the file was saved in microseconds since the unix epoch
+    pub created: i64,
+    pub packet_count: usize,
+}
+
+
+/**
+ * Save packets to a session file named after the file, returning the number of packets written.
+ * The protocol version is taken from the first MapInfo packet in versions, which are the same packets read separately
+ * so they don't all have to be held at once.
+ */
+pub fn save(path: &Path, packets: impl Iterator<Item = PacketEnvelope>, mut versions: impl Iterator<Item = PacketEnvelope>) -> io::Result<usize> {
+    let protocol_version = versions.find_map(|p| match p.packet {
+        RotmgPacket::MapInfo { build_version, .. } => Some(build_version),
+        _ => None
+    }).unwrap_or_default();
+    let name = path.file_stem().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let mut writer = SessionFileWriter::create(path, &name, &protocol_version)?;
+    for p in packets {
+        writer.write(&p)?;
+    }
+    writer.finish()
+}
+
+
+/**
+ * Writes packets to a session file, which is only complete once finish is called
+ */
+pub struct SessionFileWriter {
+    out: BufWriter<File>,
+    offsets: Vec<u64>,
+    position: u64,
+}
+impl SessionFileWriter {
+    pub fn create(path: &Path, name: &str, protocol_version: &str) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(MAGIC)?;
+        out.write_u16::<BigEndian>(FORMAT_VERSION)?;
+        //packet count and index offset are filled in by finish
+        out.write_u64::<BigEndian>(0)?;
+        out.write_u64::<BigEndian>(0)?;
+        out.write_i64::<BigEndian>(SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_micros() as i64).unwrap_or(0))?;
+        let mut position = COUNT_OFFSET + 24;
+        position += write_string(&mut out, name)?;
+        position += write_string(&mut out, protocol_version)?;
+        Ok(Self { out, offsets: vec![], position })
+    }
+
+    pub fn write(&mut self, p: &PacketEnvelope) -> io::Result<()> {
+        let mut record = vec![];
+        record.write_u64::<BigEndian>(p.index as u64)?;
+        record.write_i64::<BigEndian>(p.timestamp)?;
+        write_address(&mut record, p.connection.source)?;
+        write_address(&mut record, p.connection.destination)?;
+        record.write_u32::<BigEndian>(p.tcp_seq)?;
+        record.write_u64::<BigEndian>(p.frame as u64)?;
+        record.write_u32::<BigEndian>(p.encrypted_len as u32)?;
+        record.write_u8(match p.alignment {
+            AlignmentState::Aligned => 0,
+            AlignmentState::Realigned => 1,
+            AlignmentState::Unverified => 2,
+        })?;
+        record.write_u8(p.packet.type_num())?;
+        record.write_u32::<BigEndian>(p.data.len() as u32)?;
+        record.extend_from_slice(&p.data);
+
+        self.out.write_all(&record)?;
+        self.offsets.push(self.position);
+        self.position += record.len() as u64;
+        Ok(())
+    }
+
+    /**
+     * Write the index and fill in the header, returning the number of packets written
+     */
+    pub fn finish(mut self) -> io::Result<usize> {
+        for offset in &self.offsets {
+            self.out.write_u64::<BigEndian>(*offset)?;
+        }
+        self.out.seek(SeekFrom::Start(COUNT_OFFSET))?;
+        self.out.write_u64::<BigEndian>(self.offsets.len() as u64)?;
+        self.out.write_u64::<BigEndian>(self.position)?;
+        self.out.flush()?;
+        Ok(self.offsets.len())
+    }
+}
+
+
+/**
+ * Reads a session file, any packet can be read without reading the ones before it
+ */
+pub struct SessionFileReader {
+    input: BufReader<File>,
+    info: SessionFileInfo,
+    records_start: u64,
+    /// The index follows the last record
+    index_offset: u64,
+}
+impl SessionFileReader {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut input = BufReader::new(File::open(path)?);
+        let mut magic = [0; 4];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a realm-stat session file"))
+        }
+        let version = input.read_u16::<BigEndian>()?;
+        if version > FORMAT_VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Session file version {} is newer than this app supports", version)))
+        }
+        let packet_count = input.read_u64::<BigEndian>()? as usize;
+        let index_offset = input.read_u64::<BigEndian>()?;
+        if index_offset == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Session file was never finished"))
+        }
+        let created = input.read_i64::<BigEndian>()?;
+        let name = read_string(&mut input)?;
+        let protocol_version = read_string(&mut input)?;
+        //an index running past the end of the file means the count or offset is corrupt
+        let records_start = input.stream_position()?;
+        let file_len = input.get_ref().metadata()?.len();
+        if index_offset < records_start || (packet_count as u64).saturating_mul(8) > file_len.saturating_sub(index_offset) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Session file index is past the end of the file"))
+        }
+        Ok(Self {
+            input,
+            info: SessionFileInfo { name, protocol_version, created, packet_count },
+            records_start,
+            index_offset,
+        })
+    }
+
+    pub fn info(&self) -> &SessionFileInfo {
+        &self.info
+    }
+
+    pub fn len(&self) -> usize {
+        self.info.packet_count
+    }
+
+    /**
+     * Read the packet at position i in the session, seeking to it through the index
+     */
+    #[allow(dead_code)]
+    pub fn read(&mut self, i: usize) -> io::Result<PacketEnvelope> {
+        if i >= self.len() {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Packet is past the end of the session"))
+        }
+        self.input.seek(SeekFrom::Start(self.index_offset + i as u64 * 8))?;
+        let offset = self.input.read_u64::<BigEndian>()?;
+        if offset < self.records_start || offset >= self.index_offset {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Packet {} is outside the records of the session file", i)))
+        }
+        self.input.seek(SeekFrom::Start(offset))?;
+        self.read_record()
+    }
+
+    /**
+     * Read every packet in order
+     */
+    pub fn read_all(&mut self, mut f: impl FnMut(PacketEnvelope)) -> io::Result<()> {
+        //records are written back to back, so they can be read without the index
+        self.input.seek(SeekFrom::Start(self.records_start))?;
+        for _ in 0..self.len() {
+            f(self.read_record()?);
+        }
+        Ok(())
+    }
+
+    fn read_record(&mut self) -> io::Result<PacketEnvelope> {
+        let input = &mut self.input;
+        let index = input.read_u64::<BigEndian>()? as usize;
+        let timestamp = input.read_i64::<BigEndian>()?;
+        let source = read_address(input)?;
+        let destination = read_address(input)?;
+        let tcp_seq = input.read_u32::<BigEndian>()?;
+        let frame = input.read_u64::<BigEndian>()? as usize;
+        let encrypted_len = input.read_u32::<BigEndian>()? as usize;
+        let alignment = match input.read_u8()? {
+            0 => AlignmentState::Aligned,
+            1 => AlignmentState::Realigned,
+            _ => AlignmentState::Unverified,
+        };
+        let type_num = input.read_u8()?;
+        //the body can't run past the records, so a corrupt length isn't trusted with an allocation
+        let body_len = input.read_u32::<BigEndian>()? as u64;
+        if body_len > self.index_offset.saturating_sub(input.stream_position()?) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Packet {} is longer than the rest of the session file", index)))
+        }
+        let mut data = vec![0; body_len as usize];
+        input.read_exact(&mut data)?;
+
+        //packets are decoded from the decrypted body again, with the header they had on the wire
+        let mut bytes = Vec::with_capacity(data.len() + 5);
+        bytes.write_u32::<BigEndian>(data.len() as u32 + 5)?;
+        bytes.push(type_num);
+        bytes.extend_from_slice(&data);
+        let packet = RotmgPacket::try_from(ByteBuffer::new(bytes))
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("Packet {} couldn't be decoded", index)))?;
+
+        Ok(PacketEnvelope {
+            index,
+            timestamp,
+            connection: Connection { source, destination },
+            tcp_seq,
+            frame,
+            encrypted_len,
+            alignment,
+            packet,
+            data,
+        })
+    }
+}
+
+
+fn write_string(out: &mut impl Write, s: &str) -> io::Result<u64> {
+    let bytes = &s.as_bytes()[..s.len().min(u16::MAX as usize)];
+    out.write_u16::<BigEndian>(bytes.len() as u16)?;
+    out.write_all(bytes)?;
+    Ok(2 + bytes.len() as u64)
+}
+
+fn read_string(input: &mut impl Read) -> io::Result<String> {
+    let mut bytes = vec![0; input.read_u16::<BigEndian>()? as usize];
+    input.read_exact(&mut bytes)?;
+    Ok(String::from_utf8_lossy(&bytes).to_string())
+}
+
+fn write_address(out: &mut impl Write, address: SocketAddr) -> io::Result<()> {
+    match address.ip() {
+        IpAddr::V4(ip) => {
+            out.write_u8(4)?;
+            out.write_all(&ip.octets())?;
+        },
+        IpAddr::V6(ip) => {
+            out.write_u8(6)?;
+            out.write_all(&ip.octets())?;
+        },
+    }
+    out.write_u16::<BigEndian>(address.port())
+}
+
+fn read_address(input: &mut impl Read) -> io::Result<SocketAddr> {
+    let ip = match input.read_u8()? {
+        4 => {
+            let mut octets = [0; 4];
+            input.read_exact(&mut octets)?;
+            IpAddr::V4(Ipv4Addr::from(octets))
+        },
+        6 => {
+            let mut octets = [0; 16];
+            input.read_exact(&mut octets)?;
+            IpAddr::V6(Ipv6Addr::from(octets))
+        },
+        v => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Unknown ip version {}", v)))
+    };
+    Ok(SocketAddr::new(ip, input.read_u16::<BigEndian>()?))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_factory::test_stream;
+
+    fn envelope(index: usize) -> PacketEnvelope {
+        test_stream::envelope(index, 8, vec![index as u8, 0xff], index as i64)
+    }
+
+    fn write_session(path: &Path, count: usize) {
+        let mut writer = SessionFileWriter::create(path, "test", "").unwrap();
+        for i in 0..count {
+            writer.write(&envelope(i)).unwrap();
+        }
+        assert_eq!(writer.finish().unwrap(), count);
+    }
+
+    #[test]
+    fn reads_back_written_packets() {
+        let path = std::env::temp_dir().join(format!("realm-stat-session-test-{}.rsts", std::process::id()));
+        write_session(&path, 3);
+        let mut reader = SessionFileReader::open(&path).unwrap();
+        assert_eq!((reader.info().name.as_str(), reader.len()), ("test", 3));
+        let mut packets = vec![];
+        reader.read_all(|p| packets.push((p.index, p.data))).unwrap();
+        assert_eq!(packets, vec![(0, vec![0, 0xff]), (1, vec![1, 0xff]), (2, vec![2, 0xff])]);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn reads_any_packet_through_the_index() {
+        let path = std::env::temp_dir().join(format!("realm-stat-session-index-test-{}.rsts", std::process::id()));
+        write_session(&path, 5);
+        let mut reader = SessionFileReader::open(&path).unwrap();
+        for i in [3, 0, 4, 1] {
+            let p = reader.read(i).unwrap();
+            assert_eq!((p.index, p.data), (i, vec![i as u8, 0xff]));
+        }
+        assert_eq!(reader.read(5).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        //reading every packet starts from the first, wherever the last read left off
+        let mut count = 0;
+        reader.read_all(|_| count += 1).unwrap();
+        assert_eq!(count, 5);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn rejects_body_longer_than_the_file() {
+        let path = std::env::temp_dir().join(format!("realm-stat-session-corrupt-test-{}.rsts", std::process::id()));
+        write_session(&path, 1);
+        let mut bytes = std::fs::read(&path).unwrap();
+        //header with the name "test" and no protocol version, then the body length is 48 bytes into the record
+        let body_len = COUNT_OFFSET as usize + 24 + 6 + 2 + 48;
+        bytes.splice(body_len..body_len + 4, u32::MAX.to_be_bytes());
+        std::fs::write(&path, bytes).unwrap();
+
+        let error = SessionFileReader::open(&path).unwrap().read_all(|_| ()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,454 @@
+/*
+Saves capture sessions to an sqlite database so they can be opened again after the app is closed
+*/
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use crate::error::{self, Error};
+use crate::packet_factory::packet_envelope::{self, PacketEnvelope};
+use crate::packet_factory::rotmg_packet::RotmgPacket;
+
+
+/// Pending packets are written once there are this many of them
+const COMMIT_BATCH: usize = 500;
+
+/// Longest pending packets wait before being written, so little is lost if the app is closed mid capture
+const COMMIT_INTERVAL: Duration = Duration::from_secs(1);
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS sessions (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL,
+    source TEXT NOT NULL,
+    created INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS packets (
+    session_id INTEGER NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+    idx INTEGER NOT NULL,
+    timestamp INTEGER NOT NULL,
+    type_num INTEGER NOT NULL,
+    type_name TEXT NOT NULL,
+    map TEXT,
+    source TEXT NOT NULL,
+    destination TEXT NOT NULL,
+    tcp_seq INTEGER NOT NULL,
+    frame INTEGER NOT NULL,
+    encrypted_len INTEGER NOT NULL,
+    alignment TEXT NOT NULL,
+    packet TEXT NOT NULL,
+    data BLOB NOT NULL,
+    PRIMARY KEY (session_id, idx)
+);
+CREATE INDEX IF NOT EXISTS packets_type ON packets (session_id, type_name);
+CREATE INDEX IF NOT EXISTS packets_time ON packets (session_id, timestamp);
+CREATE INDEX IF NOT EXISTS packets_map ON packets (session_id, map);
+CREATE INDEX IF NOT EXISTS packets_connection ON packets (session_id, source, destination);
+";
+
+const SESSION_INFO_QUERY: &str = "
+SELECT s.id, s.name, s.source, s.created, MIN(p.timestamp), MAX(p.timestamp), COUNT(p.idx)
+FROM sessions s LEFT JOIN packets p ON p.session_id = s.id
+";
+
+
+/**
+ * A saved session, listed in the ui
+ */
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SessionInfo {
+    pub id: i64,
+    pub name: String,
+    /// What the session was captured from, like a device or file name
+    pub source: String,
+    /// Time the session was started in microseconds since the unix epoch
+    pub created: i64,
+    /// Capture time of the first packet
+    pub start_time: Option<i64>,
+    /// Capture time of the last packet
+    pub end_time: Option<i64>,
+    pub packet_count: usize,
+}
+
+
+struct StoreState {
+    db: Connection,
+    /// Session packets are currently saved to
+    current: Option<(i64, String)>,
+    /// Name of the map the client is in, from the last MapInfo packet
+    map: Option<String>,
+    pending: Vec<(PacketEnvelope, Option<String>)>,
+    last_commit: Instant,
+}
+
+
+/**
+ * Work handed to the writer thread
+ */
+enum WriterMessage {
+    Packet(PacketEnvelope),
+    /// Write everything received so far, then signal the sender
+    Flush(Sender<()>),
+}
+
+
+/**
+ * Shared handle to the session database, fed packets by the capture thread and queried from tauri commands.
+ * Packets are written by a thread of its own so the capture thread never waits on the database.
+ * Does nothing until the database is opened, so sessions are only kept in memory if it can't be.
+ */
+#[derive(Clone)]
+pub struct SessionStore {
+    state: Arc<Mutex<Option<StoreState>>>,
+    writer: Sender<WriterMessage>,
+}
+impl SessionStore {
+    pub fn new() -> Self {
+        let state = Arc::new(Mutex::new(None));
+        let (writer, messages) = mpsc::channel();
+        let writer_state = state.clone();
+        std::thread::spawn(move || Self::run_writer(writer_state, messages));
+        Self { state, writer }
+    }
+
+    /**
+     * Save packets as they arrive, committing pending ones when no more arrive in time.
+     * Stops once every handle to the store is dropped.
+     */
+    fn run_writer(state: Arc<Mutex<Option<StoreState>>>, messages: Receiver<WriterMessage>) {
+        loop {
+            let (packet, done) = match messages.recv_timeout(COMMIT_INTERVAL) {
+                Ok(WriterMessage::Packet(p)) => (Some(p), None),
+                Ok(WriterMessage::Flush(done)) => (None, Some(done)),
+                Err(RecvTimeoutError::Timeout) => (None, None),
+                Err(RecvTimeoutError::Disconnected) => return,
+            };
+            let result = match state.lock().unwrap().as_mut() {
+                Some(state) => match packet {
+                    Some(p) => Self::queue(state, p),
+                    None => Self::commit(state),
+                },
+                None => Ok(())
+            };
+            if let Err(e) = result {
+                log::debug!("Error saving packets {:?}", e);
+            }
+            if let Some(done) = done {
+                let _ = done.send(());
+            }
+        }
+    }
+
+    /**
+     * Wait until every packet pushed so far has been written
+     */
+    fn flush(&self) {
+        let (done, flushed) = mpsc::channel();
+        if self.writer.send(WriterMessage::Flush(done)).is_ok() {
+            let _ = flushed.recv();
+        }
+    }
+
+    /**
+     * Open or create the database at path
+     */
+    pub fn open(&self, path: &Path) -> error::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let db = Connection::open(path)?;
+        db.execute_batch("PRAGMA journal_mode = WAL; PRAGMA foreign_keys = ON;")?;
+        db.execute_batch(SCHEMA)?;
+        *self.state.lock().unwrap() = Some(StoreState {
+            db,
+            current: None,
+            map: None,
+            pending: vec![],
+            last_commit: Instant::now(),
+        });
+        Ok(())
+    }
+
+    /**
+     * Finish the current session and save packets pushed from now on to a new one
+     */
+    pub fn begin_session(&self, source: &str) -> error::Result<()> {
+        self.flush();
+        let mut guard = self.state.lock().unwrap();
+        let state = match guard.as_mut() {
+            Some(s) => s,
+            None => return Ok(())
+        };
+        Self::commit(state)?;
+        state.db.execute("INSERT INTO sessions (name, source, created) VALUES (?1, ?2, ?3)", params![source, source, now()])?;
+        state.current = Some((state.db.last_insert_rowid(), source.to_string()));
+        state.map = None;
+        Ok(())
+    }
+
+    /**
+     * Begin a new session from the same source as the current one, if there is one
+     */
+    pub fn restart_session(&self) -> error::Result<()> {
+        let source = match self.state.lock().unwrap().as_ref().and_then(|s| s.current.clone()) {
+            Some((_, source)) => source,
+            None => return Ok(())
+        };
+        self.begin_session(&source)
+    }
+
+    /**
+     * Save what is pending and stop saving packets
+     */
+    pub fn end_session(&self) -> error::Result<()> {
+        self.flush();
+        let mut guard = self.state.lock().unwrap();
+        if let Some(state) = guard.as_mut() {
+            Self::commit(state)?;
+            state.current = None;
+        }
+        Ok(())
+    }
+
+    /**
+     * Delete every packet saved to the current session, for when it starts over
+     */
+    pub fn clear_session(&self) -> error::Result<()> {
+        self.flush();
+        let mut guard = self.state.lock().unwrap();
+        if let Some(state) = guard.as_mut() {
+            state.pending.clear();
+            state.map = None;
+            if let Some((id, _)) = state.current {
+                state.db.execute("DELETE FROM packets WHERE session_id = ?1", [id])?;
+            }
+        }
+        Ok(())
+    }
+
+    /**
+     * Hand a packet to the writer thread to be saved to the current session
+     */
+    pub fn push(&self, packet: PacketEnvelope) {
+        if self.writer.send(WriterMessage::Packet(packet)).is_err() {
+            log::debug!("Session writer has stopped");
+        }
+    }
+
+    /**
+     * Queue a packet to be saved to the current session, writing the queue once it is large or old enough
+     */
+    fn queue(state: &mut StoreState, packet: PacketEnvelope) -> error::Result<()> {
+        if state.current.is_none() {
+            return Ok(())
+        }
+        if let RotmgPacket::MapInfo { name, .. } = &packet.packet {
+            state.map = Some(name.clone());
+        }
+        let map = state.map.clone();
+        state.pending.push((packet, map));
+        if state.pending.len() >= COMMIT_BATCH || state.last_commit.elapsed() >= COMMIT_INTERVAL {
+            Self::commit(state)?;
+        }
+        Ok(())
+    }
+
+    /**
+     * Every saved session, newest first
+     */
+    pub fn list(&self) -> error::Result<Vec<SessionInfo>> {
+        self.flush();
+        let mut guard = self.state.lock().unwrap();
+        let state = guard.as_mut().ok_or_else(not_open)?;
+        Self::commit(state)?;
+        let mut select = state.db.prepare(&format!("{SESSION_INFO_QUERY} GROUP BY s.id ORDER BY s.created DESC"))?;
+        let sessions = select.query_map([], Self::read_info)?.collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(sessions)
+    }
+
+    /**
+     * Read every packet of a saved session in order, handing each to f
+     */
+    pub fn load(&self, id: i64, mut f: impl FnMut(PacketEnvelope)) -> error::Result<SessionInfo> {
+        self.flush();
+        let mut guard = self.state.lock().unwrap();
+        let state = guard.as_mut().ok_or_else(not_open)?;
+        Self::commit(state)?;
+        let info = Self::session_info(&state.db, id)?;
+        let mut select = state.db.prepare("SELECT idx, timestamp, source, destination, tcp_seq, frame, encrypted_len, alignment, packet, data FROM packets WHERE session_id = ?1 ORDER BY idx")?;
+        let mut rows = select.query([id])?;
+        while let Some(row) = rows.next()? {
+            f(Self::read_envelope(row)?);
+        }
+        Ok(info)
+    }
+
+    pub fn rename(&self, id: i64, name: &str) -> error::Result<()> {
+        let guard = self.state.lock().unwrap();
+        let state = guard.as_ref().ok_or_else(not_open)?;
+        if state.db.execute("UPDATE sessions SET name = ?1 WHERE id = ?2", params![name, id])? == 0 {
+            return Err(Error::SessionNotFound(id))
+        }
+        Ok(())
+    }
+
+    /**
+     * Delete a saved session and its packets. Deleting the session being captured stops it from being saved.
+     */
+    pub fn delete(&self, id: i64) -> error::Result<()> {
+        let mut guard = self.state.lock().unwrap();
+        let state = guard.as_mut().ok_or_else(not_open)?;
+        if state.current.as_ref().map(|c| c.0) == Some(id) {
+            state.current = None;
+            state.pending.clear();
+        }
+        let tx = state.db.transaction()?;
+        tx.execute("DELETE FROM packets WHERE session_id = ?1", [id])?;
+        let deleted = tx.execute("DELETE FROM sessions WHERE id = ?1", [id])?;
+        tx.commit()?;
+        if deleted == 0 {
+            return Err(Error::SessionNotFound(id))
+        }
+        Ok(())
+    }
+
+    /**
+     * Write the pending packets in a single transaction
+     */
+    fn commit(state: &mut StoreState) -> error::Result<()> {
+        state.last_commit = Instant::now();
+        let id = match (&state.current, state.pending.is_empty()) {
+            (Some((id, _)), false) => *id,
+            _ => {
+                state.pending.clear();
+                return Ok(())
+            }
+        };
+        let tx = state.db.transaction()?;
+        {
+            let mut insert = tx.prepare_cached("INSERT OR REPLACE INTO packets (session_id, idx, timestamp, type_num, type_name, map, source, destination, tcp_seq, frame, encrypted_len, alignment, packet, data) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)")?;
+            for (p, map) in state.pending.drain(..) {
+                let packet = serde_json::to_string(&p.packet).map_err(io::Error::from)?;
+                insert.execute(params![
+                    id,
+                    p.index as i64,
+                    p.timestamp,
+                    p.packet.type_num(),
+                    p.packet.type_name(),
+                    map,
+                    p.connection.source.to_string(),
+                    p.connection.destination.to_string(),
+                    p.tcp_seq,
+                    p.frame as i64,
+                    p.encrypted_len as i64,
+                    format!("{:?}", p.alignment),
+                    packet,
+                    p.data,
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn session_info(db: &Connection, id: i64) -> error::Result<SessionInfo> {
+        db.query_row(&format!("{SESSION_INFO_QUERY} WHERE s.id = ?1 GROUP BY s.id"), [id], Self::read_info)
+            .optional()?
+            .ok_or(Error::SessionNotFound(id))
+    }
+
+    fn read_info(row: &Row) -> rusqlite::Result<SessionInfo> {
+        Ok(SessionInfo {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            source: row.get(2)?,
+            created: row.get(3)?,
+            start_time: row.get(4)?,
+            end_time: row.get(5)?,
+            packet_count: row.get::<_, i64>(6)? as usize,
+        })
+    }
+
+    fn read_envelope(row: &Row) -> error::Result<PacketEnvelope> {
+        let address = |i: usize| -> error::Result<SocketAddr> {
+            let text: String = row.get(i)?;
+            text.parse().map_err(|_| Error::Database(format!("Invalid address {}", text)))
+        };
+        let alignment: String = row.get(7)?;
+        let packet: String = row.get(8)?;
+        let mut envelope = PacketEnvelope {
+            index: row.get::<_, i64>(0)? as usize,
+            timestamp: row.get(1)?,
+            connection: packet_envelope::Connection { source: address(2)?, destination: address(3)? },
+            tcp_seq: row.get(4)?,
+            frame: row.get::<_, i64>(5)? as usize,
+            encrypted_len: row.get::<_, i64>(6)? as usize,
+            alignment: serde_json::from_value(serde_json::Value::String(alignment)).map_err(io::Error::from)?,
+            packet: serde_json::from_str(&packet).map_err(io::Error::from)?,
+            data: row.get(9)?,
+        };
+        envelope.redecode().map_err(|_| Error::Database(format!("Packet {} couldn't be decoded", envelope.index)))?;
+        Ok(envelope)
+    }
+}
+
+
+fn not_open() -> Error {
+    Error::Database("The session database isn't open".to_string())
+}
+
+/**
+ * Current time in microseconds since the unix epoch
+ */
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_micros() as i64).unwrap_or(0)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_factory::test_stream;
+
+    fn envelope(index: usize) -> PacketEnvelope {
+        test_stream::envelope(index, 8, vec![index as u8], index as i64)
+    }
+
+    #[test]
+    fn saves_pushed_packets_from_the_writer_thread() {
+        let path = std::env::temp_dir().join(format!("realm-stat-store-test-{}.sqlite", std::process::id()));
+        let store = SessionStore::new();
+        store.open(&path).unwrap();
+        store.begin_session("test").unwrap();
+        for i in 0..3 {
+            store.push(envelope(i));
+        }
+
+        let sessions = store.list().unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].packet_count, 3);
+        let mut data = vec![];
+        store.load(sessions[0].id, |p| data.push((p.data.clone(), format!("{:?}", p.packet)))).unwrap();
+        let expected: Vec<_> = (0..3).map(|i| { let p = envelope(i); (p.data, format!("{:?}", p.packet)) }).collect();
+        //the rem bytes of loaded packets are decoded again from their data
+        assert_eq!(data, expected);
+        //and are only saved once, in the data column
+        let db = Connection::open(&path).unwrap();
+        let packet: String = db.query_row("SELECT packet FROM packets LIMIT 1", [], |row| row.get(0)).unwrap();
+        assert_eq!(packet, r#"{"Ping":{}}"#);
+        drop(db);
+
+        //packets pushed once the session has ended aren't saved
+        store.end_session().unwrap();
+        store.push(envelope(3));
+        assert_eq!(store.list().unwrap()[0].packet_count, 3);
+
+        drop(store);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+}
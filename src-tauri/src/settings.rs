@@ -0,0 +1,182 @@
+use std::collections::BTreeSet;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use crate::frame::GAME_PORT;
+
+
+/**
+ * Settings controlling what is captured and how the capture handle is opened.
+ * Saved as json in the app config directory whenever they are changed.
+ */
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct CaptureSettings {
+    /// Tcp ports the game servers send from
+    pub game_ports: Vec<u16>,
+    /// Only accept traffic from these server addresses, any server is accepted when empty
+    pub server_allowlist: BTreeSet<IpAddr>,
+    /// While the allowlist is in use, add the host of every Reconnect packet to it so the capture follows the client between servers
+    pub learn_servers: bool,
+    /// BPF filter used instead of the one built from the game ports
+    pub bpf_override: Option<String>,
+    /// Maximum number of bytes captured from each frame
+    pub snaplen: i32,
+    /// Size of the kernel capture buffer in bytes
+    pub buffer_size: i32,
+    /// Read timeout of the capture handle in milliseconds
+    pub timeout_ms: i32,
+}
+impl Default for CaptureSettings {
+    fn default() -> Self {
+        Self {
+            game_ports: vec![GAME_PORT],
+            server_allowlist: BTreeSet::new(),
+            learn_servers: true,
+            bpf_override: None,
+            snaplen: 65535,
+            buffer_size: 16 * 1024 * 1024,
+            timeout_ms: 1000,
+        }
+    }
+}
+impl CaptureSettings {
+    /**
+     * Load settings from a json file, falling back to the defaults if the file doesn't exist yet
+     */
+    pub fn load(path: &Path) -> io::Result<Self> {
+        if path.exists() == false {
+            return Ok(Self::default())
+        }
+        let file = std::fs::File::open(path)?;
+        serde_json::from_reader(io::BufReader::new(file)).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file = std::fs::File::create(path)?;
+        serde_json::to_writer_pretty(io::BufWriter::new(file), self).map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    }
+
+    /**
+     * Check the settings can be used to open a capture
+     */
+    pub fn validate(&self) -> Result<(), String> {
+        if self.game_ports.is_empty() {
+            return Err("At least one game port is required".to_string())
+        }
+        if self.snaplen <= 0 || self.buffer_size <= 0 || self.timeout_ms < 0 {
+            return Err("Snaplen and buffer size must be positive and the timeout can't be negative".to_string())
+        }
+        Ok(())
+    }
+
+    pub fn is_game_port(&self, port: u16) -> bool {
+        self.game_ports.contains(&port)
+    }
+
+    pub fn is_allowed_server(&self, ip: &IpAddr) -> bool {
+        self.server_allowlist.is_empty() || self.server_allowlist.contains(ip)
+    }
+
+    /**
+     * Add the host of a Reconnect packet to the allowlist.
+     * Returns true if the allowlist changed. Hosts that aren't ip addresses, like the empty host used to reconnect to the same server, are ignored.
+     * Nothing is learned while the allowlist is empty, since that would start excluding servers that are accepted now.
+     */
+    pub fn learn_server(&mut self, host: &str) -> bool {
+        if self.learn_servers == false || self.server_allowlist.is_empty() {
+            return false
+        }
+        match host.parse::<IpAddr>() {
+            Ok(ip) => self.server_allowlist.insert(ip),
+            Err(_) => false
+        }
+    }
+}
+
+
+/// Bundle identifier from tauri.conf.json, the app config directory is named after it
+const APP_IDENTIFIER: &str = "stat.realm.tauri";
+
+
+/**
+ * Where the capture settings are saved
+ */
+pub fn settings_path(config_dir: &Path) -> PathBuf {
+    config_dir.join("capture_settings.json")
+}
+
+/**
+ * Where the ui saves the capture settings, for finding them without the tauri app running
+ */
+pub fn default_settings_path() -> Option<PathBuf> {
+    tauri::api::path::config_dir().map(|dir| settings_path(&dir.join(APP_IDENTIFIER)))
+}
+
+
+/**
+ * Capture settings shared between the sniffer and its capture thread, saved to disk whenever they change
+ */
+#[derive(Clone)]
+pub struct SharedSettings {
+    settings: Arc<Mutex<CaptureSettings>>,
+    path: Arc<Mutex<Option<PathBuf>>>,
+}
+impl SharedSettings {
+    pub fn new() -> Self {
+        Self {
+            settings: Arc::new(Mutex::new(CaptureSettings::default())),
+            path: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /**
+     * Load the settings saved at path, and save any later changes there
+     */
+    pub fn load(&self, path: PathBuf) -> io::Result<()> {
+        let loaded = CaptureSettings::load(&path);
+        *self.path.lock().unwrap() = Some(path);
+        *self.settings.lock().unwrap() = loaded?;
+        Ok(())
+    }
+
+    pub fn get(&self) -> CaptureSettings {
+        self.settings.lock().unwrap().clone()
+    }
+
+    pub fn set(&self, settings: CaptureSettings) -> io::Result<()> {
+        *self.settings.lock().unwrap() = settings;
+        self.save()
+    }
+
+    /**
+     * Run f with the current settings without cloning them
+     */
+    pub fn with<T>(&self, f: impl FnOnce(&CaptureSettings) -> T) -> T {
+        f(&self.settings.lock().unwrap())
+    }
+
+    /**
+     * Learn a server from a Reconnect packet's host, saving the settings if the allowlist changed
+     */
+    pub fn learn_server(&self, host: &str) {
+        let changed = self.settings.lock().unwrap().learn_server(host);
+        if changed {
+            log::debug!("Added {} to the server allowlist", host);
+            if let Err(e) = self.save() {
+                log::debug!("Error saving capture settings {:?}", e);
+            }
+        }
+    }
+
+    fn save(&self) -> io::Result<()> {
+        match self.path.lock().unwrap().as_ref() {
+            Some(path) => self.get().save(path),
+            None => Ok(())
+        }
+    }
+}
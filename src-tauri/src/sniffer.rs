@@ -1,9 +1,43 @@
 #![allow(dead_code)]
 
-use etherparse::{TransportSlice, InternetSlice, SlicedPacket};
-use pcap::{Device, Packet};
-use crate::packet_factory::{RotmgPacketFactory, rotmg_packet::RotmgPacket};
+use pcap::Device;
+use crate::packet_factory::RotmgPacketFactory;
+use crate::session::{Session, SessionConfig, SessionPages, PacketQuery, PacketPage};
+use crate::packet_stream::{PacketStream, StreamConfig};
+use crate::recorder::{Recorder, RecorderConfig};
+use crate::capture_file::STDIN_PATH;
+use crate::packet_source::{PacketSource, SourcePoll, LiveSource, FileSource, StreamSource};
+use crate::frame::{self, FrameCounts};
+use crate::health::{HealthTracker, CaptureHealth};
+use crate::error::{self, Error};
+use crate::settings::{CaptureSettings, SharedSettings};
+use crate::packet_factory::rotmg_packet::RotmgPacket;
+use crate::packet_factory::field_map::AnnotatedPacket;
+use crate::replay::{ReplayControl, ReplaySource};
+use crate::session_store::{SessionStore, SessionInfo};
+use std::path::Path;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+
+/// How often progress events are sent while reading a source with a known size
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How often capture-health events are sent
+const HEALTH_INTERVAL: Duration = Duration::from_secs(1);
+
+
+/**
+ * Sent to the ui as "import-progress" events while reading a file
+ */
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ImportProgress {
+    pub bytes_read: u64,
+    pub total_bytes: u64,
+    pub frames: usize,
+    pub packets: usize,
+    pub aligned: bool,
+}
 
 
 pub struct Sniffer {
@@ -11,7 +45,16 @@ pub struct Sniffer {
     capture_thread: Option<std::thread::JoinHandle<()>>,
     factory: Arc<Mutex<RotmgPacketFactory>>,
     collect: Arc<Mutex<bool>>,
-    session_buffer: Arc<Mutex<Vec<RotmgPacket>>>,
+    session_buffer: Arc<Mutex<Session>>,
+    stream: PacketStream,
+    stream_thread: Option<std::thread::JoinHandle<()>>,
+    recorder_config: RecorderConfig,
+    health: Arc<Mutex<HealthTracker>>,
+    settings: SharedSettings,
+    replay: Option<ReplayControl>,
+    //While paused frames still go through the packet factory to keep the cipher aligned, but packets aren't stored
+    paused: Arc<Mutex<bool>>,
+    store: SessionStore,
 }
 impl Sniffer {
     pub fn new() -> Self {
@@ -20,42 +63,160 @@ impl Sniffer {
             capture_thread: None,
             factory: Arc::new(Mutex::new(RotmgPacketFactory::new())),
             collect: Arc::new(Mutex::new(false)),
-            session_buffer: Arc::new(Mutex::new(vec![])),
+            session_buffer: Arc::new(Mutex::new(Session::new(SessionConfig::default()))),
+            stream: PacketStream::new(),
+            stream_thread: None,
+            recorder_config: RecorderConfig::default(),
+            health: Arc::new(Mutex::new(HealthTracker::new())),
+            settings: SharedSettings::new(),
+            replay: None,
+            paused: Arc::new(Mutex::new(false)),
+            store: SessionStore::new(),
         }
     }
 
     /**
-     * Open the capture handle, set the filter, and begin listening for packets and sending them to the packet factory.
+     * Open a capture handle on the selected device and begin listening for packets and sending them to the packet factory.
      * A tauri window is required to inform the ui of changes in the cipher alignment
      */
-    pub fn start(&mut self, window: tauri::Window) {
+    pub fn start(&mut self, window: tauri::Window) -> error::Result<()> {
+        let device = self.device.clone().ok_or(Error::NoDeviceSelected)?;
+        let name = format!("Live capture on {}", device.desc.clone().unwrap_or(device.name.clone()));
+        let source = LiveSource::open(device, &self.settings.get())?;
+
+        let mut recorder = None;
+        if self.recorder_config.enabled {
+            match Recorder::start(self.recorder_config.clone(), source.linktype().unwrap_or(frame::LINKTYPE_ETHERNET)) {
+                Ok(r) => recorder = Some(r),
+                Err(e) => {
+                    log::debug!("Error starting recording {:?}", e);
+                    let _ = window.emit("recording-error", e.to_string());
+                }
+            }
+        }
+        self.start_source(window, &name, Box::new(source), recorder);
+        Ok(())
+    }
+
+    /**
+     * Open a pcap or pcapng file and begin processing packets
+     */
+    pub fn start_using_pcap_file(&mut self, window: tauri::Window, file_path: String) -> error::Result<()> {
+        let source = FileSource::open(Path::new(&file_path), &self.settings.get())?;
+        self.start_source(window, &file_name(&file_path), Box::new(source), None);
+        Ok(())
+    }
+
+    /**
+     * Replay a pcap or pcapng file at the pace it was captured, scaled by speed.
+     * Packets are pushed to the ui as if they were live, and the replay can be controlled while it runs.
+     */
+    pub fn start_replay(&mut self, window: tauri::Window, file_path: String, speed: f64) -> error::Result<()> {
+        let control = ReplayControl::new(speed);
+        let name = format!("Replay of {}", file_name(&file_path));
+        let source = ReplaySource::open(file_path.into(), self.settings.get(), control.clone())?;
+        self.start_source(window, &name, Box::new(source), None);
+        self.replay = Some(control);
+        Ok(())
+    }
+
+    /**
+     * Control of the running replay, None if the current capture isn't a replay
+     */
+    pub fn replay(&self) -> Option<&ReplayControl> {
+        self.replay.as_ref()
+    }
+
+    /**
+     * Read a pcap or pcapng stream from stdin or a named pipe and process frames as they arrive, like a live capture
+     */
+    pub fn start_using_stream(&mut self, window: tauri::Window, path: String) -> error::Result<()> {
+        //opening the pipe itself blocks until a writer connects, so only check that it is there
+        if path != STDIN_PATH {
+            std::fs::metadata(&path)?;
+        }
+        let name = if path == STDIN_PATH { "Stream from stdin".to_string() } else { format!("Stream from {}", path) };
+        self.start_source(window, &name, Box::new(StreamSource::open(path)), None);
+        Ok(())
+    }
+
+    /**
+     * Start a new capture session, unpaused, and process frames from the source on the capture thread until it ends or the sniffer is stopped.
+     * The session is saved to the session database under name as it is captured.
+     * The ui is sent a pcap-eof event when the source ends by itself, and import-progress events while reading a source with a known size.
+     */
+    pub fn start_source(&mut self, window: tauri::Window, name: &str, mut source: Box<dyn PacketSource>, mut recorder: Option<Recorder>) {
         {
+            self.replay = None;
             *self.collect.lock().unwrap() = true;
+            *self.paused.lock().unwrap() = false;
             self.factory.lock().unwrap().reset();
             self.session_buffer.lock().unwrap().clear();
-            window.emit("cipher-misaligned", ()).unwrap();
+            *self.health.lock().unwrap() = HealthTracker::new();
+            if let Err(e) = self.store.begin_session(name) {
+                log::debug!("Error saving session {:?}", e);
+            }
+            emit(&window, "cipher-misaligned", ());
         }
+        self.start_stream(window.clone());
         let factory = self.factory.clone();
-        let device = self.device.clone();
         let run = self.collect.clone();
         let session_buffer = self.session_buffer.clone();
+        let stream = self.stream.clone();
+        let health = self.health.clone();
+        let settings = self.settings.clone();
+        let paused = self.paused.clone();
+        let store = self.store.clone();
         let handle = std::thread::spawn(move || {
-            let mut received_nonmax_packet = false; //Whether or not a packet smaller than the maximum size has been received
-
-            let mut cap = pcap::Capture::from_device(device.unwrap().clone())
-                .unwrap()
-                .immediate_mode(true)
-                .timeout(1000)
-                .open()
-                .unwrap();
-
-            cap.filter("ip proto \\tcp and src port 2050", false).expect("Error with packet filter");
-
+            let mut frames_read = 0;
+            let mut last_progress = Instant::now();
+            let mut last_health = Instant::now();
             while *run.lock().unwrap() == true {
+                if last_progress.elapsed() >= PROGRESS_INTERVAL {
+                    last_progress = Instant::now();
+                    Self::emit_progress(source.as_ref(), frames_read, &window, &factory);
+                }
+                if last_health.elapsed() >= HEALTH_INTERVAL {
+                    last_health = Instant::now();
+                    Self::emit_health(source.as_mut(), &window, &factory, &health);
+                }
                 //log::debug!("sniffer is running");
-                match cap.next_packet() {
-                    Ok(p) => Self::process_packet(p, &mut received_nonmax_packet, &window, &factory, &session_buffer),
-                    Err(e) => println!("pcap error {}", e),   
+                let poll = match source.next() {
+                    Ok(poll) => poll,
+                    Err(e) => {
+                        log::debug!("Error reading packet source {:?}", e);
+                        SourcePoll::End
+                    }
+                };
+                match poll {
+                    SourcePoll::Frame(f) => {
+                        frames_read += 1;
+                        Self::process_frame(f.linktype, f.timestamp, f.original_len, &f.data, f.number, &window, &factory, &session_buffer, &stream, &health, &settings, &paused, &store, &mut recorder);
+                    },
+                    SourcePoll::Idle => (),
+                    SourcePoll::Reset => {
+                        frames_read = 0;
+                        factory.lock().unwrap().reset();
+                        session_buffer.lock().unwrap().clear();
+                        *health.lock().unwrap() = HealthTracker::new();
+                        stream.clear();
+                        if let Err(e) = store.clear_session() {
+                            log::debug!("Error clearing saved session {:?}", e);
+                        }
+                        emit(&window, "session-reset", ());
+                        emit(&window, "cipher-misaligned", ());
+                    },
+                    SourcePoll::End => {
+                        factory.lock().unwrap().finalize();
+                        Self::flush_factory(&factory, &session_buffer, &stream, &settings, &paused, &store);
+                        if let Err(e) = store.end_session() {
+                            log::debug!("Error saving session {:?}", e);
+                        }
+                        Self::emit_progress(source.as_ref(), frames_read, &window, &factory);
+                        Self::emit_health(source.as_mut(), &window, &factory, &health);
+                        emit(&window, "pcap-eof", ());
+                        break;
+                    }
                 }
             }
             //log::debug!("Collection thread stopping");
@@ -63,75 +224,114 @@ impl Sniffer {
         self.capture_thread = Some(handle);
     }
 
+    fn emit_progress(source: &dyn PacketSource, frame_number: usize, window: &tauri::Window, factory: &Arc<Mutex<RotmgPacketFactory>>) {
+        let read = match source.progress() {
+            Some(p) => p,
+            None => return
+        };
+        let factory = factory.lock().unwrap();
+        let progress = ImportProgress {
+            bytes_read: read.bytes_read,
+            total_bytes: read.total_bytes,
+            frames: frame_number,
+            packets: factory.packets_out,
+            aligned: factory.is_aligned(),
+        };
+        if let Err(e) = window.emit("import-progress", progress) {
+            log::debug!("Error emitting progress {:?}", e);
+        }
+    }
+
+    fn emit_health(source: &mut dyn PacketSource, window: &tauri::Window, factory: &Arc<Mutex<RotmgPacketFactory>>, health: &Arc<Mutex<HealthTracker>>) {
+        let snapshot = {
+            let mut health = health.lock().unwrap();
+            if let Some(stats) = source.stats() {
+                health.record_source_stats(stats);
+            }
+            health.snapshot(factory.lock().unwrap().stats())
+        };
+        if let Err(e) = window.emit("capture-health", snapshot) {
+            log::debug!("Error emitting capture health {:?}", e);
+        }
+    }
+
     /**
-     * Open a capture handle to a pcap file, set the filter, and begin processing packets
+     * Record a captured frame if a recorder is running, then hand it to the packet factory and collect the results
      */
-    pub fn start_using_pcap_file(&mut self, window: tauri::Window, file_path: String) {
-        {
-            *self.collect.lock().unwrap() = true;
-            self.factory.lock().unwrap().reset();
-            self.session_buffer.lock().unwrap().clear();
+    fn process_frame(linktype: u32, timestamp: i64, original_len: u32, data: &[u8], frame_number: usize, window: &tauri::Window, factory: &Arc<Mutex<RotmgPacketFactory>>, session_buffer: &Arc<Mutex<Session>>, stream: &PacketStream, health: &Arc<Mutex<HealthTracker>>, settings: &SharedSettings, paused: &Arc<Mutex<bool>>, store: &SessionStore, recorder: &mut Option<Recorder>) {
+        if let Some(r) = recorder.as_mut() {
+            if let Err(e) = r.write(timestamp, original_len, data) {
+                log::debug!("Error writing recording {:?}", e);
+                let _ = window.emit("recording-error", e.to_string());
+                *recorder = None;
+            }
         }
-        let factory = self.factory.clone();
-        let session_buffer = self.session_buffer.clone();
-        let handle = std::thread::spawn(move || {
-            let mut received_nonmax_packet = false; //Whether or not a packet smaller than the maximum size has been received
 
-            let mut cap = match pcap::Capture::from_file(file_path) {
-                Err(e) => {
-                    log::debug!("{:?}", e);
-                    return;
-                },
-                Ok(c) => c
-            };
+        let sliced = settings.with(|s| frame::slice_frame(linktype, timestamp, frame_number, data, s));
+        health.lock().unwrap().record_frame(original_len, &sliced);
+        let (s, meta) = match sliced {
+            Ok(f) => f,
+            Err(_) => return
+        };
 
-            if let Err(e) = cap.filter("ip proto \\tcp and src port 2050", false) {
-                log::debug!("{:?}", e);
-                return;
+        let reconnected = {
+            let mut factory = factory.lock().expect("RwLock error");
+            let reconnects = factory.reconnects;
+            factory.insert_packet(s, meta);
+            while let Some(e) = factory.get_event() {
+                health.lock().unwrap().record_event(e, timestamp);
+                emit(window, e.event_name(), ());
             }
+            factory.reconnects != reconnects
+        };
+        Self::flush_factory(factory, session_buffer, stream, settings, paused, store);
 
-            loop {
-                match cap.next_packet() {
-                    Err(_) => break,
-                    Ok(p) => Self::process_packet(p, &mut received_nonmax_packet, &window, &factory, &session_buffer),   
-                }
+        //Each server gets its own recording file
+        if let (true, Some(r)) = (reconnected, recorder.as_mut()) {
+            if let Err(e) = r.on_reconnect() {
+                log::debug!("Error rotating recording {:?}", e);
+                *recorder = None;
             }
-            //log::debug!("Collection thread stopping");
-            window.emit("pcap-eof", ()).expect("Error emitting event");
-        });
-        self.capture_thread = Some(handle);
+        }
     }
 
-    fn process_packet(p: Packet, received_nonmax_packet: &mut bool, window: &tauri::Window, factory: &Arc<Mutex<RotmgPacketFactory>>, session_buffer: &Arc<Mutex<Vec<RotmgPacket>>>) {
-        let slice = etherparse::SlicedPacket::from_ethernet(&(*p));
-        match slice {
-            Ok(s) => {
-                if let (InternetSlice::Ipv4(ip_h, _), TransportSlice::Tcp(tcp_h)) = (s.clone().ip.expect("No ipv4 header"), s.clone().transport.expect("No tcp header")) {
-                    //log::debug!("{}", ip_h.payload_len() - (tcp_h.data_offset() as u16 * 4));
-                    if ip_h.payload_len() - (tcp_h.data_offset() as u16 * 4) <= 0 {
-                        return
-                    }
-                    
-                }
-                let payload_len = match (s.clone().ip, s.clone().transport) {
-                    (Some(InternetSlice::Ipv4(ip_h, _)), Some(TransportSlice::Tcp(tcp_h))) => ip_h.payload_len() - (tcp_h.data_offset() as u16 * 4),
-                    (Some(InternetSlice::Ipv6(ip_h, _)), Some(TransportSlice::Tcp(tcp_h))) => ip_h.payload_length() - (tcp_h.data_offset() as u16 * 4),
-                    _ => 0
-                };
-                if payload_len <= 0 { return }
-                
-                if s.payload.len() < 1460 && *received_nonmax_packet == false {
-                    *received_nonmax_packet = true;
-                }
-                if *received_nonmax_packet == true {
-                    let mut factory = factory.lock().expect("RwLock error");
-                    factory.insert_packet(s, &window);
-                    while let Some(p) = factory.get_packet() {
-                        session_buffer.lock().unwrap().push(p);
-                    }
-                }
-            },
-            Err(e) => println!("Packet data error: {}", e)
+    /**
+     * Move every packet the factory has finished into the session buffer, the session database and the ui stream, or drop them while paused.
+     * Servers the client is sent to are learned along the way, even while paused, so the capture can follow the client.
+     */
+    fn flush_factory(factory: &Arc<Mutex<RotmgPacketFactory>>, session_buffer: &Arc<Mutex<Session>>, stream: &PacketStream, settings: &SharedSettings, paused: &Arc<Mutex<bool>>, store: &SessionStore) {
+        let mut factory = factory.lock().expect("RwLock error");
+        let paused = *paused.lock().unwrap();
+        loop {
+            let next = if paused { factory.skip_packet() } else { factory.get_packet() };
+            let p = match next {
+                Some(p) => p,
+                None => break
+            };
+            if let RotmgPacket::Reconnect { host, .. } = &p.packet {
+                settings.learn_server(host);
+            }
+            if paused {
+                continue
+            }
+            store.push(p.clone());
+            stream.push(p.clone());
+            session_buffer.lock().unwrap().push(p);
+        }
+    }
+
+    /**
+     * Stop the previous ui stream if there is one and start a new one
+     */
+    fn start_stream(&mut self, window: tauri::Window) {
+        self.stop_stream();
+        self.stream_thread = Some(self.stream.start(window));
+    }
+
+    fn stop_stream(&mut self) {
+        self.stream.stop();
+        if let Some(jh) = self.stream_thread.take() {
+            let _ = jh.join();
         }
     }
 
@@ -140,10 +340,123 @@ impl Sniffer {
         if let Some(jh) = self.capture_thread.take() {
             let _ = jh.join();
         }
+        //Packets after the last tick would otherwise be lost
+        self.factory.lock().unwrap().finalize();
+        Self::flush_factory(&self.factory, &self.session_buffer, &self.stream, &self.settings, &self.paused, &self.store);
+        if let Err(e) = self.store.end_session() {
+            log::debug!("Error saving session {:?}", e);
+        }
+        self.stop_stream();
         //log::debug!("Collection stopped");
 
     }
 
+    /**
+     * Stop storing packets while still following the stream, so the capture can be resumed without realigning the cipher.
+     * A running recording keeps recording every frame.
+     */
+    pub fn pause(&self) -> error::Result<()> {
+        self.set_paused(true)
+    }
+
+    /**
+     * Store packets again, appended to the same session
+     */
+    pub fn resume(&self) -> error::Result<()> {
+        self.set_paused(false)
+    }
+
+    fn set_paused(&self, paused: bool) -> error::Result<()> {
+        if self.is_capturing() == false {
+            return Err(Error::NotCapturing)
+        }
+        *self.paused.lock().unwrap() = paused;
+        Ok(())
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused.lock().unwrap()
+    }
+
+    /**
+     * Clear the stored packets and number packets from 0 again.
+     * A running capture carries on into the new session without losing cipher alignment, and is saved as a new session.
+     */
+    pub fn new_session(&self, window: &tauri::Window) -> error::Result<()> {
+        //the factory lock keeps the capture thread from storing packets until the session is cleared
+        let mut factory = self.factory.lock().unwrap();
+        factory.new_session();
+        self.session_buffer.lock().unwrap().clear();
+        self.stream.clear();
+        emit(window, "session-reset", ());
+        if self.is_capturing() {
+            self.store.restart_session()?;
+        } else {
+            self.store.end_session()?;
+        }
+        Ok(())
+    }
+
+    fn is_capturing(&self) -> bool {
+        self.capture_thread.as_ref().map(|t| t.is_finished() == false).unwrap_or(false)
+    }
+
+    /**
+     * Open the session database at path, sessions are saved to it from the next capture on
+     */
+    pub fn open_session_store(&self, path: &Path) -> error::Result<()> {
+        self.store.open(path)
+    }
+
+    pub fn list_sessions(&self) -> error::Result<Vec<SessionInfo>> {
+        self.store.list()
+    }
+
+    /**
+     * Handle to the session database, for reading saved sessions without holding the sniffer locked
+     */
+    pub fn session_store(&self) -> SessionStore {
+        self.store.clone()
+    }
+
+    /**
+     * The packets of the current session, for reading the whole session without holding the sniffer or the session locked
+     */
+    pub fn session_pages(&self) -> SessionPages {
+        SessionPages::new(self.session_buffer.clone())
+    }
+
+    /**
+     * An empty session with the current config for loading a saved session into.
+     * Saved sessions can't be loaded while capturing, the capture would keep adding to the session they replace.
+     */
+    pub fn session_for_loading(&self) -> error::Result<Session> {
+        if self.is_capturing() {
+            return Err(Error::CaptureRunning)
+        }
+        Ok(Session::new(self.session_buffer.lock().unwrap().config()))
+    }
+
+    /**
+     * Replace the packets in memory with a session loaded in full by session_for_loading
+     */
+    pub fn replace_session(&self, session: Session) -> error::Result<()> {
+        if self.is_capturing() {
+            return Err(Error::CaptureRunning)
+        }
+        *self.session_buffer.lock().unwrap() = session;
+        self.stream.clear();
+        Ok(())
+    }
+
+    pub fn rename_session(&self, id: i64, name: &str) -> error::Result<()> {
+        self.store.rename(id, name)
+    }
+
+    pub fn delete_session(&self, id: i64) -> error::Result<()> {
+        self.store.delete(id)
+    }
+
     pub fn log_packets(&mut self) {
         loop {
             match self.factory.lock().unwrap().get_packet() {
@@ -153,11 +466,138 @@ impl Sniffer {
         }
     }
 
-    pub fn get_all_packets(&mut self) -> Vec<RotmgPacket> {
-        self.session_buffer.lock().unwrap().to_vec()
+    pub fn fetch_packets(&self, cursor: usize, limit: usize, query: &PacketQuery) -> error::Result<PacketPage> {
+        let matcher = query.matcher()?;
+        Ok(self.session_buffer.lock().unwrap().fetch(cursor, limit, &matcher))
+    }
+
+    /**
+     * Decode a packet of the current session again, recording which bytes became which field
+     */
+    pub fn inspect_packet(&self, index: usize) -> error::Result<AnnotatedPacket> {
+        let packet = self.session_buffer.lock().unwrap().iter_from(index).next().ok_or(Error::PacketNotFound(index))?;
+        Ok(AnnotatedPacket::decode(packet.decrypted()))
+    }
+
+    pub fn packet_count(&self, query: &PacketQuery) -> error::Result<usize> {
+        let matcher = query.matcher()?;
+        Ok(self.session_buffer.lock().unwrap().count(&matcher))
+    }
+
+    pub fn session_config(&self) -> SessionConfig {
+        self.session_buffer.lock().unwrap().config()
+    }
+
+    pub fn set_session_config(&self, config: SessionConfig) -> error::Result<()> {
+        config.validate().map_err(Error::InvalidSettings)?;
+        self.session_buffer.lock().unwrap().set_config(config);
+        Ok(())
+    }
+
+    pub fn ack_packets(&self, batch_id: u64) {
+        self.stream.ack(batch_id);
+    }
+
+    pub fn stream_config(&self) -> StreamConfig {
+        self.stream.config()
+    }
+
+    pub fn set_stream_config(&self, config: StreamConfig) -> error::Result<()> {
+        config.validate().map_err(Error::InvalidSettings)?;
+        self.stream.set_config(config);
+        Ok(())
+    }
+
+    pub fn frame_counts(&self) -> FrameCounts {
+        self.health.lock().unwrap().frame_counts()
+    }
+
+    pub fn capture_health(&self) -> CaptureHealth {
+        let factory_stats = self.factory.lock().unwrap().stats();
+        self.health.lock().unwrap().snapshot(factory_stats)
+    }
+
+    /**
+     * Load the capture settings saved at path and keep saving changes there
+     */
+    pub fn load_capture_settings(&self, path: std::path::PathBuf) -> std::io::Result<()> {
+        self.settings.load(path)
+    }
+
+    pub fn capture_settings(&self) -> CaptureSettings {
+        self.settings.get()
+    }
+
+    /**
+     * Takes effect the next time a capture is started, except for the server allowlist which applies immediately
+     */
+    pub fn set_capture_settings(&self, settings: CaptureSettings) -> error::Result<()> {
+        settings.validate().map_err(Error::InvalidSettings)?;
+        Ok(self.settings.set(settings)?)
+    }
+
+    pub fn recorder_config(&self) -> RecorderConfig {
+        self.recorder_config.clone()
+    }
+
+    /**
+     * Takes effect the next time a live capture is started
+     */
+    pub fn set_recorder_config(&mut self, config: RecorderConfig) {
+        self.recorder_config = config;
     }
 
     pub fn set_device(&mut self, device: &Device) {
         self.device = Some(device.clone());
     }
-}
\ No newline at end of file
+}
+
+
+/**
+ * Send an event to the ui, logging instead of panicking if the window has gone away
+ */
+fn emit<S: serde::Serialize + Clone>(window: &tauri::Window, event: &str, payload: S) {
+    if let Err(e) = window.emit(event, payload) {
+        log::debug!("Error emitting {} {:?}", event, e);
+    }
+}
+
+
+/**
+ * Name of the file at path, used to name the sessions read from it
+ */
+fn file_name(path: &str) -> String {
+    Path::new(path).file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or(path.to_string())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr};
+    use crate::packet_factory::test_stream::{TestStream, SERVER};
+
+    #[test]
+    fn learns_the_server_of_a_reconnect() {
+        let settings = SharedSettings::new();
+        let mut allowed = CaptureSettings::default();
+        allowed.server_allowlist.insert(IpAddr::V4(SERVER));
+        settings.set(allowed).unwrap();
+
+        let factory = Arc::new(Mutex::new(RotmgPacketFactory::new()));
+        let session = Arc::new(Mutex::new(Session::new(SessionConfig::default())));
+        let mut stream = TestStream::new(SERVER);
+        let mut packets = vec![stream.tick(), stream.packet(8, &[1, 2, 3, 4]), stream.tick()];
+        packets.push(stream.reconnect("10.0.0.7"));
+        for (i, packet) in packets.into_iter().enumerate() {
+            let data = stream.frame(&packet);
+            let (sliced, meta) = frame::slice_frame(frame::LINKTYPE_ETHERNET, 0, i + 1, &data, &settings.get()).unwrap();
+            factory.lock().unwrap().insert_packet(sliced, meta);
+        }
+        Sniffer::flush_factory(&factory, &session, &PacketStream::new(), &settings, &Arc::new(Mutex::new(false)), &SessionStore::new());
+
+        assert!(settings.get().server_allowlist.contains(&IpAddr::V4(Ipv4Addr::new(10, 0, 0, 7))));
+        let types = session.lock().unwrap().iter_from(0).map(|p| p.packet.type_name()).collect::<Vec<_>>();
+        assert_eq!(types, vec!["NewTick", "Ping", "NewTick", "Reconnect"]);
+    }
+}
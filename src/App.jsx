@@ -1,12 +1,12 @@
 //import 'bootstrap/dist/css/bootstrap.min.css';
-import { Button, ButtonGroup, Container, Table, Badge, Modal, Col, Row, Form } from "react-bootstrap";
+import { Button, ButtonGroup, Container, Table, Badge, Modal, Col, Row, Form, ProgressBar, Alert, InputGroup } from "react-bootstrap";
 import { invoke,  } from "@tauri-apps/api/tauri";
-import { open } from "@tauri-apps/api/dialog";
+import { open, save } from "@tauri-apps/api/dialog";
 import { appWindow } from "@tauri-apps/api/window";
 import { debug } from "tauri-plugin-log-api";
 import "./App.css";
 import 'bootstrap/dist/css/bootstrap.min.css';
-import { useEffect, useState } from "react";
+import { useEffect, useRef, useState } from "react";
 
 function App() {
   const [packet_list, set_packet_list] = useState([]);
@@ -18,6 +18,7 @@ function App() {
         <p>RotMG Packet Capture and Analysis</p>
       </header>
       <SnifferController set_packet_list={set_packet_list}/>
+      <CaptureHealthPanel/>
       <PacketTable packet_list={packet_list}/>
       <SelectDeviceModal />
     </div>
@@ -26,20 +27,35 @@ function App() {
 
 export default App;
 
+//Commands fail with {kind, message}, anything else is shown as is
+function error_message(e) {
+  return e?.message ?? String(e);
+}
+
 function SnifferController({set_packet_list}) {
   const [collecting, set_collecting] = useState(false);
+  const [paused, set_paused] = useState(false);
   const [capture_mode, set_capture_mode] = useState("live");
   const [aligned, set_aligned] = useState(false);
-  const [read_counter, set_read_counter] = useState(0);
+  const [recording, set_recording] = useState(false);
+  const [pipe_path, set_pipe_path] = useState("");
+  const [replay_speed, set_replay_speed] = useState(0);
+  const [progress, set_progress] = useState(null);
+  const [error, set_error] = useState(null);
+  const [filter_text, set_filter_text] = useState("");
+  const cursor = useRef(0);
+  const fetching = useRef(false);
+  //display filter packets are fetched with, null shows every packet
+  const filter = useRef(null);
 
-  //These two useEffect calls control when new packets are fetched from the backend to be displayed in the table
+  //New packets are pushed from the backend in batches, each batch has to be acknowledged to keep the stream flowing
   useEffect(() => {
-    const timer = setTimeout(() => collecting && set_read_counter(read_counter+1), 1e3);
-    return () => clearTimeout(timer);
-  }, [collecting, read_counter])
-  useEffect(() => {
-    if(collecting == true) get_packets();
-  }, [read_counter]);
+    const unlisten = appWindow.listen("packets", e => {
+      handle_batch(e.payload);
+      invoke("ack_packets", {batchId: e.payload.batch_id});
+    });
+    return () => { unlisten.then(f => f()); };
+  }, []);
 
   //event listeners to display the aligned status of the cipher
   appWindow.listen("cipher-aligned", _ => {
@@ -53,53 +69,221 @@ function SnifferController({set_packet_list}) {
     set_aligned(false);
   });
   appWindow.listen("pcap-eof", _ => {
-    invoke("get_packets").then(packets => {
-      set_packet_list(packets);
-    });
+    set_collecting(false);
+    set_paused(false);
+    get_packets();
+  });
+  appWindow.listen("import-progress", e => {
+    set_progress(e.payload);
+  });
+  //a replay seeking backwards starts the session over
+  appWindow.listen("session-reset", _ => {
+    clear_packets();
   });
 
   //Functions to start & stop packet collection
   async function start() {
     // Learn more about Tauri commands at https://tauri.app/v1/guides/features/command
-    await invoke("start_collection");
-    set_collecting(true);
+    clear_packets();
+    set_error(null);
+    try {
+      await invoke("start_collection");
+      set_collecting(true);
+    } catch (e) {
+      set_error(error_message(e));
+    }
   }
   async function start_pcap(file_path) {
-    await invoke("start_pcap", {"filePath": file_path})
+    clear_packets();
+    set_progress(null);
+    set_error(null);
+    try {
+      if (replay_speed > 0) {
+        await invoke("start_replay", {"filePath": file_path, "speed": replay_speed});
+      } else {
+        await invoke("start_pcap", {"filePath": file_path});
+      }
+      set_collecting(true);
+    } catch (e) {
+      set_error(error_message(e));
+    }
+  }
+
+  async function start_pipe() {
+    clear_packets();
+    set_error(null);
+    try {
+      await invoke("start_pipe", {path: pipe_path.length > 0 ? pipe_path : null});
+      set_collecting(true);
+    } catch (e) {
+      set_error(error_message(e));
+    }
   }
 
   async function stop() {
     await invoke("stop_collection");
     set_collecting(false);
+    set_paused(false);
+    get_packets();
+  }
+
+  //Pausing keeps the cipher aligned, resuming appends to the same session
+  async function toggle_paused() {
+    try {
+      await invoke(paused ? "resume_collection" : "pause_collection");
+      set_paused(!paused);
+    } catch (e) {
+      set_error(error_message(e));
+    }
+  }
+  async function new_session() {
+    await invoke("new_session");
+  }
+
+  //Load a session saved in the session database in place of the current packets
+  async function open_saved_session(id) {
+    clear_packets();
+    set_error(null);
+    try {
+      await invoke("open_session", {id: id});
+      get_packets();
+    } catch (e) {
+      set_error(error_message(e));
+    }
+  }
+
+  //Append a pushed batch, falling back to fetching if packets were dropped from the stream
+  //or if a display filter is applied, since only the backend can evaluate it
+  function handle_batch(batch) {
+    if (fetching.current) return;
+    if (filter.current != null) {
+      get_packets();
+      return;
+    }
+    const packets = batch.packets.filter(p => p.index >= cursor.current);
+    if (batch.dropped > 0 || (packets.length > 0 && packets[0].index != cursor.current)) {
+      get_packets();
+      return;
+    }
+    if (packets.length == 0) return;
+    cursor.current = packets[packets.length-1].index + 1;
+    set_packet_list(list => list.concat(packets));
   }
 
-  //Function to fetch all packets fetched and stored by the current sniffer session
+  //Function to fetch the packets decoded since the last fetch
   async function get_packets() {
-    var packets = await invoke("get_packets");
-    //packets = packets.filter(p => "NewTick" in p);
-    //debug(""+JSON.stringify(packets[0]));
-    //packets.map(p => debug(""+JSON.stringify(p["NewTick"])))
-    set_packet_list(packets);
+    if (fetching.current) return;
+    fetching.current = true;
+    let page;
+    try {
+      do {
+        page = await invoke("fetch_packets", {cursor: cursor.current, limit: 1000, query: packet_query()});
+        const packets = page.packets;
+        cursor.current = page.next_cursor;
+        set_packet_list(list => list.concat(packets));
+      } while (page.next_cursor < page.total);
+    } catch (e) {
+      set_error(error_message(e));
+    } finally {
+      fetching.current = false;
+    }
+  }
+  function packet_query() {
+    if (filter.current == null) return null;
+    //times of day in the filter are in local time
+    return {filter: filter.current, utc_offset: -new Date().getTimezoneOffset()};
+  }
+
+  //Show only the packets matching the display filter, fetching the session again with it
+  async function apply_filter(e) {
+    e?.preventDefault();
+    const text = filter_text.trim();
+    if (text.length > 0) {
+      try {
+        await invoke("validate_filter", {filter: text});
+      } catch (e) {
+        set_error(error_message(e));
+        return;
+      }
+    }
+    set_error(null);
+    filter.current = text.length > 0 ? text : null;
+    clear_packets();
+    get_packets();
+  }
+  function clear_packets() {
+    cursor.current = 0;
+    set_packet_list([]);
+  }
+
+  useEffect(() => {
+    invoke("get_recorder_config").then(c => set_recording(c.enabled));
+  }, []);
+  async function toggle_recording(enabled) {
+    let config = await invoke("get_recorder_config");
+    await invoke("set_recorder_config", {config: {...config, enabled: enabled}});
+    set_recording(enabled);
+  }
+
+  async function save_session_dialog() {
+    let path = await save({"filters": [{"name": "RealmStat Session", "extensions": ["rstat"]}]});
+    if (path == null) return;
+    try {
+      let count = await invoke("save_session_file", {path: path});
+      debug("Saved " + count + " packets to " + path);
+    } catch (e) {
+      set_error(error_message(e));
+    }
+  }
+
+  async function load_session_dialog() {
+    let path = await open({"filters": [{"name": "RealmStat Session", "extensions": ["rstat"]}]});
+    if (path == null) return;
+    clear_packets();
+    set_error(null);
+    try {
+      await invoke("load_session_file", {path: path});
+      get_packets();
+    } catch (e) {
+      set_error(error_message(e));
+    }
   }
 
   function select_file_dialog() {
-    open({"filters": [{"name": "PCAP", "extensions": ["pcap"]}]}).then(p => {
+    open({"filters": [{"name": "PCAP", "extensions": ["pcap", "pcapng"]}]}).then(p => {
       if (p == null) return;
       start_pcap(p);
     });
   }
 
+  async function export_annotated_dialog() {
+    let input = await open({"filters": [{"name": "PCAP", "extensions": ["pcap", "pcapng"]}]});
+    if (input == null) return;
+    let output = await save({"filters": [{"name": "PCAPNG", "extensions": ["pcapng"]}]});
+    if (output == null) return;
+    try {
+      let summary = await invoke("export_annotated_pcapng", {inputPath: input, outputPath: output});
+      debug("Annotated " + summary.packets + " packets in " + summary.frames + " frames");
+    } catch (e) {
+      debug("Error " + error_message(e));
+    }
+  }
+
   return (
     <Container fluid>
+      {error != null && <Alert variant="danger" dismissible onClose={() => set_error(null)}>{error}</Alert>}
       <Row>
         <Col>
           {capture_mode=="live" ? (
             <Container fluid>
               <ButtonGroup size="lg">
                 <Button onClick={start} disabled={collecting} variant="success">Start</Button>
+                <Button onClick={toggle_paused} disabled={!collecting} variant="warning">{paused ? "Resume" : "Pause"}</Button>
                 <Button onClick={stop} disabled={!collecting} variant="danger">Stop</Button>
+                <Button onClick={new_session} variant="secondary">New Session</Button>
               </ButtonGroup>
               <br />
+              {collecting && paused && <Badge bg="warning" style={{fontSize: "120%"}}>Paused, packets aren't stored</Badge>}
               {collecting ? (
                 aligned ? (
                   <Badge bg="success" style={{fontSize: "120%"}}>Cipher Aligned</Badge>
@@ -109,14 +293,56 @@ function SnifferController({set_packet_list}) {
               ) : (
                 <Badge bg="secondary" style={{fontSize: "120%"}}>Cipher Paused</Badge>
               )}
+              <Form.Check type="switch" label="Record capture" checked={recording} disabled={collecting} onChange={e => toggle_recording(e.target.checked)}/>
+              <CaptureSettingsModal disabled={collecting}/>
+            </Container>
+          ) : capture_mode=="pipe" ? (
+            <Container fluid>
+              <Form.Control placeholder="Named pipe path, empty for stdin" value={pipe_path} disabled={collecting} onChange={e => set_pipe_path(e.target.value)}/>
+              <ButtonGroup size="lg">
+                <Button onClick={start_pipe} disabled={collecting} variant="success">Start</Button>
+                <Button onClick={toggle_paused} disabled={!collecting} variant="warning">{paused ? "Resume" : "Pause"}</Button>
+                <Button onClick={stop} disabled={!collecting} variant="danger">Stop</Button>
+                <Button onClick={new_session} variant="secondary">New Session</Button>
+              </ButtonGroup>
+              <br />
+              {aligned ? (
+                <Badge bg="success" style={{fontSize: "120%"}}>Cipher Aligned</Badge>
+              ) : (
+                <Badge bg="danger" style={{fontSize: "120%"}}>Cipher Misaligned</Badge>
+              )}
             </Container>
           ) : (
             <div>
               <Form>
                 <Button size="lg" variant="success" onClick={select_file_dialog}>Select File</Button>
+                <Button size="lg" variant="secondary" onClick={export_annotated_dialog} style={{marginLeft: "1em"}}>Export Annotated pcapng</Button>
+                <Form.Select value={replay_speed} onChange={e => set_replay_speed(Number(e.target.value))}>
+                  <option value={0}>Decode as fast as possible</option>
+                  <option value={1}>Replay at 1x</option>
+                  <option value={2}>Replay at 2x</option>
+                  <option value={10}>Replay at 10x</option>
+                </Form.Select>
               </Form>
+              {progress != null && (
+                <div>
+                  <ProgressBar now={progress.total_bytes > 0 ? 100 * progress.bytes_read / progress.total_bytes : 0}/>
+                  <p>
+                    {progress.packets} packets from {progress.frames} frames{" "}
+                    {progress.aligned ? <Badge bg="success">Cipher Aligned</Badge> : <Badge bg="danger">Cipher Misaligned</Badge>}
+                  </p>
+                </div>
+              )}
+              {collecting && <Button variant="danger" onClick={stop}>Cancel</Button>}
+              {replay_speed > 0 && <ReplayControls/>}
+              <RecordingList start_pcap={start_pcap}/>
             </div>
           )}
+          <SavedSessionsModal disabled={collecting} open_session={open_saved_session}/>
+          <Button variant="link" onClick={save_session_dialog}>Save session file</Button>
+          <Button variant="link" onClick={load_session_dialog} disabled={collecting}>Open session file</Button>
+          <ExportModal/>
+          <DecodePayloadModal/>
         </Col>
         <Col>
           <Form.Select size="lg" onChange={e => {
@@ -125,31 +351,386 @@ function SnifferController({set_packet_list}) {
           }}>
             <option value="live">Live Capture</option>
             <option value="pcap">Read From Pcap File</option>
+            <option value="pipe">Read From Pipe</option>
           </Form.Select>
         </Col>
       </Row>
       <br/>
-      
+      <Form onSubmit={apply_filter}>
+        <InputGroup>
+          <Form.Control placeholder='Display filter, e.g. type == Text && Text.recipient != "" && time > 20:00' value={filter_text} onChange={e => set_filter_text(e.target.value)}/>
+          <Button type="submit" variant="primary">Apply</Button>
+          <Button variant="secondary" disabled={filter_text.length == 0} onClick={() => { set_filter_text(""); filter.current = null; clear_packets(); get_packets(); }}>Clear</Button>
+        </InputGroup>
+      </Form>
 
     </Container>
   )
 }
 
+function RecordingList({start_pcap}) {
+  const [recordings, set_recordings] = useState([]);
+
+  useEffect(() => {
+    invoke("list_recordings").then(set_recordings).catch(e => debug("Error " + error_message(e)));
+  }, []);
+
+  if (recordings.length == 0) return null;
+  return (
+    <Table size="sm">
+      <thead>
+        <tr>
+          <th>Recording</th>
+          <th>Size</th>
+          <th></th>
+        </tr>
+      </thead>
+      <tbody>
+        {recordings.map(r =>
+          <tr key={r.path}>
+            <td>{new Date(r.modified * 1000).toLocaleString()}</td>
+            <td>{(r.size / 1024 / 1024).toFixed(1)} MB</td>
+            <td style={{textAlign: "right"}}><Button size="sm" onClick={() => start_pcap(r.path)}>Replay</Button></td>
+          </tr>
+        )}
+      </tbody>
+    </Table>
+  )
+}
+
+function SavedSessionsModal({disabled, open_session}) {
+  const [show, set_show] = useState(false);
+  const [sessions, set_sessions] = useState([]);
+  const [error, set_error] = useState(null);
+
+  async function refresh() {
+    try {
+      set_sessions(await invoke("list_sessions"));
+      set_error(null);
+    } catch (e) {
+      set_error(error_message(e));
+    }
+  }
+
+  async function open_sessions() {
+    await refresh();
+    set_show(true);
+  }
+
+  async function rename(session) {
+    let name = window.prompt("Session name", session.name);
+    if (name == null || name.length == 0) return;
+    try {
+      await invoke("rename_session", {id: session.id, name: name});
+    } catch (e) {
+      set_error(error_message(e));
+    }
+    refresh();
+  }
+
+  async function remove(session) {
+    if (!window.confirm("Delete " + session.name + "?")) return;
+    try {
+      await invoke("delete_session", {id: session.id});
+    } catch (e) {
+      set_error(error_message(e));
+    }
+    refresh();
+  }
+
+  return (
+    <div>
+      <Button variant="link" onClick={open_sessions}>Saved sessions</Button>
+      <Modal show={show} size="lg" onHide={() => set_show(false)}>
+        <Modal.Header closeButton><h1>Saved sessions</h1></Modal.Header>
+        <Modal.Body>
+          {error != null && <Alert variant="danger">{error}</Alert>}
+          <Table size="sm">
+            <thead>
+              <tr>
+                <th>Session</th>
+                <th>Started</th>
+                <th>Packets</th>
+                <th></th>
+              </tr>
+            </thead>
+            <tbody>
+              {sessions.map(s =>
+                <tr key={s.id}>
+                  <td>{s.name}</td>
+                  <td>{new Date(s.created / 1000).toLocaleString()}</td>
+                  <td>{s.packet_count}</td>
+                  <td style={{textAlign: "right"}}>
+                    <ButtonGroup size="sm">
+                      <Button disabled={disabled} onClick={() => { set_show(false); open_session(s.id); }}>Open</Button>
+                      <Button variant="secondary" onClick={() => rename(s)}>Rename</Button>
+                      <Button variant="danger" onClick={() => remove(s)}>Delete</Button>
+                    </ButtonGroup>
+                  </td>
+                </tr>
+              )}
+            </tbody>
+          </Table>
+        </Modal.Body>
+      </Modal>
+    </div>
+  )
+}
+
+function ExportModal() {
+  const [show, set_show] = useState(false);
+  const [format, set_format] = useState("JsonLines");
+  const [types, set_types] = useState("");
+  const [start_time, set_start_time] = useState("");
+  const [end_time, set_end_time] = useState("");
+  const [filter, set_filter] = useState("");
+  const [result, set_result] = useState(null);
+  const [error, set_error] = useState(null);
+
+  //datetime-local values are in local time, the backend wants microseconds since the unix epoch
+  function to_micros(value) {
+    return value.length > 0 ? new Date(value).getTime() * 1000 : null;
+  }
+
+  async function export_session() {
+    let path;
+    if (format == "Csv") {
+      path = await open({directory: true});
+    } else {
+      let extension = {"Parquet": "parquet", "Wireshark": "pcapng"}[format] ?? "jsonl";
+      path = await save({"filters": [{"name": format, "extensions": [extension]}]});
+    }
+    if (path == null) return;
+    let type_list = types.split(",").map(t => t.trim()).filter(t => t.length > 0);
+    let query = {
+      types: type_list.length > 0 ? type_list : null,
+      start_time: to_micros(start_time),
+      end_time: to_micros(end_time),
+      filter: filter.trim().length > 0 ? filter : null,
+      utc_offset: -new Date().getTimezoneOffset(),
+    };
+    try {
+      //the decrypted capture is written along with a lua dissector to open it in wireshark
+      let summary = format == "Wireshark"
+        ? await invoke("export_decrypted_pcapng", {path: path, query: query})
+        : await invoke("export_session", {format: format, path: path, query: query});
+      set_result("Exported " + summary.packets + " packets to " + summary.files.length + " files");
+      set_error(null);
+    } catch (e) {
+      set_error(error_message(e));
+    }
+  }
+
+  return (
+    <div>
+      <Button variant="link" onClick={() => { set_result(null); set_error(null); set_show(true); }}>Export session</Button>
+      <Modal show={show} onHide={() => set_show(false)}>
+        <Modal.Header closeButton><h1>Export session</h1></Modal.Header>
+        <Modal.Body>
+          {error != null && <Alert variant="danger">{error}</Alert>}
+          {result != null && <Alert variant="success">{result}</Alert>}
+          <Form>
+            <Form.Label>Format</Form.Label>
+            <Form.Select value={format} onChange={e => set_format(e.target.value)}>
+              <option value="JsonLines">JSON Lines</option>
+              <option value="Csv">CSV table per packet type</option>
+              <option value="Parquet">Parquet</option>
+              <option value="Wireshark">Decrypted pcapng with Wireshark dissector</option>
+            </Form.Select>
+            <Form.Label>Packet types, comma separated, empty for all</Form.Label>
+            <Form.Control value={types} placeholder="NewTick, Update" onChange={e => set_types(e.target.value)}/>
+            <Form.Label>From</Form.Label>
+            <Form.Control type="datetime-local" step="1" value={start_time} onChange={e => set_start_time(e.target.value)}/>
+            <Form.Label>Until</Form.Label>
+            <Form.Control type="datetime-local" step="1" value={end_time} onChange={e => set_end_time(e.target.value)}/>
+            <Form.Label>Display filter</Form.Label>
+            <Form.Control value={filter} placeholder="Damage && damage_amount > 500" onChange={e => set_filter(e.target.value)}/>
+          </Form>
+        </Modal.Body>
+        <Modal.Footer>
+          <Button onClick={export_session}>Export</Button>
+        </Modal.Footer>
+      </Modal>
+    </div>
+  )
+}
+
+//Background colours cycled through for the fields of an inspected packet
+const FIELD_COLOURS = ["#cfe2ff", "#d1e7dd", "#fff3cd", "#f8d7da", "#e2d9f3", "#cff4fc"];
+
+function field_colour(field, i) {
+  return field.name == "rem" && field.value == null ? "#e9ecef" : FIELD_COLOURS[i % FIELD_COLOURS.length];
+}
+
+//Wireshark style hex view of a decrypted packet, highlighting which bytes each decoded field was read from
+function AnnotatedPacketView({annotated}) {
+  const [selected, set_selected] = useState(null);
+
+  useEffect(() => set_selected(null), [annotated]);
+
+  const bytes = annotated.bytes.match(/../g) ?? [];
+  //field each byte was read from, for colouring and selecting bytes
+  const byte_fields = new Array(bytes.length).fill(null);
+  annotated.fields.forEach((f, i) => {
+    for (let b = f.offset; b < f.offset + f.length; b++) byte_fields[b] = i;
+  });
+  const rows = [];
+  for (let offset = 0; offset < bytes.length; offset += 16) rows.push(offset);
+
+  function byte_style(b) {
+    const i = byte_fields[b];
+    if (i == null) return {};
+    const field = annotated.fields[i];
+    return {
+      backgroundColor: field_colour(field, i),
+      outline: i == selected ? "1px solid black" : "none",
+      cursor: "pointer",
+    };
+  }
+  function ascii(hex) {
+    const c = parseInt(hex, 16);
+    return c >= 0x20 && c < 0x7f ? String.fromCharCode(c) : ".";
+  }
+
+  return (
+    <Row>
+      <Col md={7}>
+        <pre style={{fontFamily: "monospace", textAlign: "left"}}>
+          {rows.map(offset =>
+            <div key={offset}>
+              <span style={{color: "gray"}}>{offset.toString(16).padStart(4, "0")}  </span>
+              {bytes.slice(offset, offset + 16).map((h, j) =>
+                <span key={j} style={byte_style(offset + j)} onClick={() => set_selected(byte_fields[offset + j])}>{h} </span>
+              )}
+              {"   ".repeat(Math.max(0, offset + 16 - bytes.length))}
+              {" "}
+              {bytes.slice(offset, offset + 16).map((h, j) =>
+                <span key={j} style={byte_style(offset + j)} onClick={() => set_selected(byte_fields[offset + j])}>{ascii(h)}</span>
+              )}
+            </div>
+          )}
+        </pre>
+      </Col>
+      <Col md={5}>
+        {annotated.packet == null && <Alert variant="warning">The packet couldn't be decoded, the fields stop where decoding failed</Alert>}
+        <Table size="sm" hover style={{textAlign: "left"}}>
+          <thead>
+            <tr><th>Field</th><th>Offset</th><th>Length</th><th>Type</th><th>Value</th></tr>
+          </thead>
+          <tbody>
+            {annotated.fields.map((f, i) =>
+              <tr key={i} onClick={() => set_selected(i)} style={{cursor: "pointer", fontWeight: i == selected ? "bold" : "normal"}}>
+                <td style={{backgroundColor: field_colour(f, i)}}>{f.name ?? "-"}</td>
+                <td>{f.offset}</td>
+                <td>{f.length}</td>
+                <td>{f.kind}</td>
+                <td style={{wordBreak: "break-all"}}>{f.value == null ? "unparsed" : JSON.stringify(f.value)}</td>
+              </tr>
+            )}
+          </tbody>
+        </Table>
+      </Col>
+    </Row>
+  )
+}
+
+//Field map of a packet in the current session
+function PacketInspector({index, on_hide}) {
+  const [inspection, set_inspection] = useState(null);
+  const [error, set_error] = useState(null);
+
+  useEffect(() => {
+    set_inspection(null);
+    set_error(null);
+    if (index == null) return;
+    invoke("inspect_packet", {index: index}).then(set_inspection).catch(e => set_error(error_message(e)));
+  }, [index]);
+
+  return (
+    <Modal show={index != null} onHide={on_hide} size="xl">
+      <Modal.Header closeButton><h1>Packet {index}</h1></Modal.Header>
+      <Modal.Body>
+        {error != null && <Alert variant="danger">{error}</Alert>}
+        {inspection != null && <AnnotatedPacketView annotated={inspection}/>}
+      </Modal.Body>
+    </Modal>
+  )
+}
+
+//Decode a packet pasted as hex or base64, like a snippet shared from a log, decrypting it first if a key is given
+function DecodePayloadModal() {
+  const [show, set_show] = useState(false);
+  const [text, set_text] = useState("");
+  const [encoding, set_encoding] = useState("");
+  const [key, set_key] = useState("");
+  const [offset, set_offset] = useState(0);
+  const [decoded, set_decoded] = useState(null);
+  const [error, set_error] = useState(null);
+
+  async function decode() {
+    try {
+      let cipher = key.trim().length > 0 ? {key: key, offset: Number(offset)} : null;
+      set_decoded(await invoke("decode_payload", {text: text, encoding: encoding.length > 0 ? encoding : null, cipher: cipher}));
+      set_error(null);
+    } catch (e) {
+      set_decoded(null);
+      set_error(error_message(e));
+    }
+  }
+
+  return (
+    <div>
+      <Button variant="link" onClick={() => set_show(true)}>Decode packet</Button>
+      <Modal show={show} onHide={() => set_show(false)} size="xl">
+        <Modal.Header closeButton><h1>Decode packet</h1></Modal.Header>
+        <Modal.Body>
+          {error != null && <Alert variant="danger">{error}</Alert>}
+          <Form>
+            <Form.Label>Packet with its length and type header</Form.Label>
+            <Form.Control as="textarea" rows={3} value={text} placeholder="00 00 00 13 4b ..." onChange={e => set_text(e.target.value)}/>
+            <Form.Select value={encoding} onChange={e => set_encoding(e.target.value)}>
+              <option value="">Detect hex or base64</option>
+              <option value="Hex">Hex</option>
+              <option value="Base64">Base64</option>
+            </Form.Select>
+            <Form.Label>RC4 key as hex, empty if the packet is already decrypted</Form.Label>
+            <Form.Control value={key} onChange={e => set_key(e.target.value)}/>
+            <Form.Label>Keystream offset</Form.Label>
+            <Form.Control type="number" min={0} value={offset} disabled={key.trim().length == 0} onChange={e => set_offset(e.target.value)}/>
+          </Form>
+          <br/>
+          {decoded != null && <AnnotatedPacketView annotated={decoded}/>}
+        </Modal.Body>
+        <Modal.Footer>
+          <Button onClick={decode} disabled={text.trim().length == 0}>Decode</Button>
+        </Modal.Footer>
+      </Modal>
+    </div>
+  )
+}
+
 function PacketTable({packet_list}) {
+  const [inspecting, set_inspecting] = useState(null);
+
   return (
     <Container>
+      <PacketInspector index={inspecting} on_hide={() => set_inspecting(null)}/>
       <Table style={{"textAlign": "left"}} striped hover>
         <thead>
           <tr>
-            <th style={{"width": "20%"}}>Packet #</th>
-            <th>Tick ID</th>
+            <th style={{"width": "10%"}}>Packet #</th>
+            <th style={{"width": "15%"}}>Time</th>
+            <th style={{"width": "20%"}}>Connection</th>
+            <th>Packet</th>
           </tr>
         </thead>
         <tbody>
-          {packet_list.map((p, i) => 
-            <tr key={i}>
-              <td>{i}</td>
-              <td>{JSON.stringify(p)}</td>
+          {packet_list.map(p => 
+            <tr key={p.index} onClick={() => set_inspecting(p.index)} style={{cursor: "pointer"}}>
+              <td>{p.index} {p.alignment == "Unverified" && <Badge bg="warning">Unverified</Badge>}</td>
+              <td>{new Date(p.timestamp / 1000).toLocaleTimeString()}</td>
+              <td>{p.connection.source} → {p.connection.destination}</td>
+              <td>{JSON.stringify(p.packet)}</td>
             </tr>
           )}
         </tbody>
@@ -161,6 +742,8 @@ function PacketTable({packet_list}) {
 function SelectDeviceModal() {
   const [devices, set_devices] = useState([]);
   const [show, set_show] = useState(true);
+  const [detecting, set_detecting] = useState(false);
+  const [detect_message, set_detect_message] = useState(null);
 
   async function get_devices() {
     let ds = await invoke("get_devices");
@@ -170,13 +753,29 @@ function SelectDeviceModal() {
     get_devices();
   }, []);
 
-  async function select_device(device_string) {
+  async function select_device(device_name) {
     try {
-      await invoke("use_device", {deviceString: device_string});
+      await invoke("use_device", {deviceName: device_name});
       set_show(false);
     } catch (e) {
-      debug("Error " + e);
+      set_detect_message(error_message(e));
+    }
+  }
+
+  async function detect_device() {
+    set_detecting(true);
+    set_detect_message(null);
+    try {
+      let result = await invoke("detect_device");
+      if (result == null) {
+        set_detect_message("No game traffic found, make sure the game is running");
+      } else {
+        set_show(false);
+      }
+    } catch (e) {
+      set_detect_message(error_message(e));
     }
+    set_detecting(false);
   }
 
   return (
@@ -184,20 +783,22 @@ function SelectDeviceModal() {
       <Modal show={show} onHide={() => set_show(false)}>
         <Modal.Header closeButton><h1>Select network adapter</h1></Modal.Header>
         <Modal.Body>
+          <Button onClick={detect_device} disabled={detecting}>{detecting ? "Detecting..." : "Detect automatically"}</Button>
+          {detect_message != null && <p>{detect_message}</p>}
           <Table>
             <thead>
               <tr>
-                <th>Device Description</th>
+                <th>Device</th>
               </tr>
             </thead>
             <tbody>
-              {devices.map((d, index) => {
+              {devices.map(d => {
                 return (
-                  <tr key={index}>
+                  <tr key={d.name}>
                     <td>
                       <Row>
-                        <Col>{d}</Col>
-                        <Col style={{textAlign: "right"}} onClick={() => select_device(d)}><Button>Select</Button></Col>
+                        <Col>{d.description ?? d.name}<br/><small className="text-muted">{d.name}</small></Col>
+                        <Col style={{textAlign: "right"}} onClick={() => select_device(d.name)}><Button>Select</Button></Col>
                       </Row>
                     </td>
                   </tr>
@@ -209,4 +810,131 @@ function SelectDeviceModal() {
       </Modal>
     </div>
   )
-}
\ No newline at end of file
+}
+
+function CaptureSettingsModal({disabled}) {
+  const [show, set_show] = useState(false);
+  const [settings, set_settings] = useState(null);
+  const [error, set_error] = useState(null);
+
+  async function open_settings() {
+    set_settings(await invoke("get_capture_settings"));
+    set_error(null);
+    set_show(true);
+  }
+
+  async function save_settings() {
+    try {
+      await invoke("set_capture_settings", {settings: settings});
+      set_show(false);
+    } catch (e) {
+      set_error(error_message(e));
+    }
+  }
+
+  function split_list(value) {
+    return value.split(",").map(v => v.trim()).filter(v => v.length > 0);
+  }
+
+  return (
+    <div>
+      <Button variant="link" onClick={open_settings} disabled={disabled}>Capture settings</Button>
+      <Modal show={show} onHide={() => set_show(false)}>
+        <Modal.Header closeButton><h1>Capture settings</h1></Modal.Header>
+        {settings != null && (
+          <Modal.Body>
+            <Form>
+              <Form.Label>Game ports</Form.Label>
+              <Form.Control defaultValue={settings.game_ports.join(", ")} onChange={e => set_settings({...settings, game_ports: split_list(e.target.value).map(Number)})}/>
+              <Form.Label>Server allowlist (empty accepts any server)</Form.Label>
+              <Form.Control defaultValue={settings.server_allowlist.join(", ")} onChange={e => set_settings({...settings, server_allowlist: split_list(e.target.value)})}/>
+              <Form.Check type="switch" label="Add servers from Reconnect packets" checked={settings.learn_servers} onChange={e => set_settings({...settings, learn_servers: e.target.checked})}/>
+              <Form.Label>Custom BPF filter</Form.Label>
+              <Form.Control defaultValue={settings.bpf_override ?? ""} onChange={e => set_settings({...settings, bpf_override: e.target.value.length > 0 ? e.target.value : null})}/>
+              <Form.Label>Snaplen</Form.Label>
+              <Form.Control type="number" defaultValue={settings.snaplen} onChange={e => set_settings({...settings, snaplen: Number(e.target.value)})}/>
+              <Form.Label>Buffer size (bytes)</Form.Label>
+              <Form.Control type="number" defaultValue={settings.buffer_size} onChange={e => set_settings({...settings, buffer_size: Number(e.target.value)})}/>
+              <Form.Label>Timeout (ms)</Form.Label>
+              <Form.Control type="number" defaultValue={settings.timeout_ms} onChange={e => set_settings({...settings, timeout_ms: Number(e.target.value)})}/>
+            </Form>
+            {error != null && <Badge bg="danger">{error}</Badge>}
+          </Modal.Body>
+        )}
+        <Modal.Footer>
+          <Button onClick={save_settings}>Save</Button>
+        </Modal.Footer>
+      </Modal>
+    </div>
+  )
+}
+
+function format_offset(micros) {
+  const seconds = Math.floor(micros / 1000000);
+  return Math.floor(seconds / 60) + ":" + String(seconds % 60).padStart(2, "0");
+}
+
+function ReplayControls() {
+  const [status, set_status] = useState(null);
+  const [seek_to, set_seek_to] = useState("");
+
+  //The replay runs in the backend, poll it for the position
+  useEffect(() => {
+    const timer = setInterval(() => {
+      invoke("get_replay_status").then(set_status).catch(_ => set_status(null));
+    }, 500);
+    return () => clearInterval(timer);
+  }, []);
+
+  async function seek() {
+    const parts = seek_to.split(":").map(Number);
+    const seconds = parts.reduce((total, part) => total * 60 + part, 0);
+    if (isNaN(seconds) || status == null || status.start == null) return;
+    await invoke("seek_replay", {timestamp: status.start + seconds * 1000000});
+  }
+
+  if (status == null) return null;
+  return (
+    <Container fluid>
+      <ButtonGroup>
+        <Button onClick={() => invoke(status.paused ? "resume_replay" : "pause_replay")}>{status.paused ? "Resume" : "Pause"}</Button>
+        {[1, 2, 10].map(speed =>
+          <Button key={speed} variant={status.speed == speed ? "primary" : "outline-primary"} onClick={() => invoke("set_replay_speed", {speed: speed})}>{speed}x</Button>
+        )}
+      </ButtonGroup>
+      <p>
+        {status.start != null && status.position != null ? format_offset(status.position - status.start) : "0:00"}
+        {status.seeking != null && " (seeking)"}
+      </p>
+      <Form.Control placeholder="Seek to mm:ss" value={seek_to} onChange={e => set_seek_to(e.target.value)}/>
+      <Button size="sm" onClick={seek}>Seek</Button>
+    </Container>
+  )
+}
+
+function CaptureHealthPanel() {
+  const [health, set_health] = useState(null);
+
+  useEffect(() => {
+    const unlisten = appWindow.listen("capture-health", e => set_health(e.payload));
+    return () => { unlisten.then(f => f()); };
+  }, []);
+
+  if (health == null) return null;
+  const failures = Object.entries(health.factory.decode_failures);
+  return (
+    <Container>
+      <Table size="sm">
+        <tbody>
+          <tr>
+            <td>Frames {health.frames.frames} ({(health.bytes / 1024 / 1024).toFixed(1)} MB), {health.frames.accepted} game segments</td>
+            <td>Drops {health.source != null ? health.source.kernel_drops + " kernel, " + health.source.interface_drops + " interface" : "n/a"}</td>
+            <td>TCP gaps {health.factory.tcp_gaps}, resyncs {health.factory.resyncs}</td>
+            <td>Realignments {health.realignments} ({(health.misaligned_time / 1000000).toFixed(1)}s misaligned)</td>
+            <td>Decode failures {failures.length == 0 ? 0 : failures.map(([type, count]) => "type " + type + ": " + count).join(", ")}</td>
+          </tr>
+        </tbody>
+      </Table>
+    </Container>
+  )
+}